@@ -1,3 +1,5 @@
+#![allow(clippy::disallowed_names)]
+
 use lmdb::{DatabaseFlags, Environment, Transaction, WriteFlags};
 use serde::{Deserialize, Serialize};
 use tempdir::TempDir;
@@ -0,0 +1,784 @@
+use std::fmt;
+use std::io::Read;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, StatusCode};
+use tungstenite::protocol::Role;
+use tungstenite::WebSocket;
+
+use crate::events::ChainEvent;
+use crate::history::{Direction, HistoryQuery};
+use crate::ingest::IngestError;
+use crate::recovery::RecoveryReport;
+use crate::render::ForkTree;
+use crate::rpc;
+use crate::store::Storage;
+use crate::ws::{Hub, Subscription, READ_TIMEOUT};
+use crate::{
+    Block, BlockID, LmdbService, Service, ServiceConfig, ServiceImpl, DB_NAME, NATIVE_ASSET,
+};
+
+/// Largest request body accepted, in bytes.
+const MAX_BODY_LEN: u64 = 1 << 20;
+
+/// Open the service stored in a data directory, creating the directory on first use. Only
+/// the headers are loaded, unless `recover` asks to check every record and rebuild what was
+/// left inconsistent, which is reported.
+pub fn open_data_dir(
+    dir: &Path,
+    config: ServiceConfig,
+    recover: bool,
+) -> anyhow::Result<(LmdbService, Option<RecoveryReport>)> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create the data directory {}", dir.display()))?;
+    let dir = dir
+        .to_str()
+        .with_context(|| format!("data directory {} is not valid UTF-8", dir.display()))?;
+    if recover {
+        let (service, report) = LmdbService::recover_db(dir, DB_NAME, config)?;
+        return Ok((service, Some(report)));
+    }
+    let service = LmdbService::from_db_with_config(dir, DB_NAME, config).with_context(|| {
+        format!(
+            "failed to open the data directory {}, --recover may salvage it",
+            dir
+        )
+    })?;
+    Ok((service, None))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ApiConfig {
+    /// Number of messages queued for a WebSocket client before it is dropped for lagging.
+    pub push_buffer: usize,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self { push_buffer: 1024 }
+    }
+}
+
+/// Serves a service over HTTP with JSON bodies. Requests are handled one at a time, in the
+/// order they arrive, so every response reflects all the ingests answered before it.
+///
+/// Clients opening a WebSocket on `/subscribe` receive the chain events of the topics they
+/// name in the URL as each ingest is committed, see `Subscription`.
+pub struct ApiServer<S: Storage> {
+    http: Arc<tiny_http::Server>,
+    stopped: Arc<AtomicBool>,
+    service: ServiceImpl<S>,
+    /// Chain events of the last ingest, collected from the service.
+    events: Arc<Mutex<Vec<ChainEvent>>>,
+    hub: Hub,
+}
+
+/// Stops a running `ApiServer` from another thread.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    http: Arc<tiny_http::Server>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    /// Make `ApiServer::run` return once the request being handled is answered.
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.http.unblock();
+    }
+}
+
+impl fmt::Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShutdownHandle")
+            .field("stopped", &self.stopped.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+impl<S: Storage> ApiServer<S> {
+    /// Listen on an address. Port 0 picks a free port, see `local_addr`.
+    pub fn bind(addr: impl ToSocketAddrs, service: ServiceImpl<S>) -> anyhow::Result<Self> {
+        Self::bind_with_config(addr, service, ApiConfig::default())
+    }
+
+    pub fn bind_with_config(
+        addr: impl ToSocketAddrs,
+        mut service: ServiceImpl<S>,
+        config: ApiConfig,
+    ) -> anyhow::Result<Self> {
+        let http = tiny_http::Server::http(addr)
+            .map_err(|error| anyhow::anyhow!("failed to bind the HTTP server: {}", error))?;
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let collected = events.clone();
+        service.subscribe(Box::new(move |event: &ChainEvent| {
+            collected
+                .lock()
+                .expect("the events are never poisoned")
+                .push(event.clone())
+        }));
+        Ok(Self {
+            http: Arc::new(http),
+            stopped: Arc::new(AtomicBool::new(false)),
+            service,
+            events,
+            hub: Hub::new(config.push_buffer),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.http
+            .server_addr()
+            .to_ip()
+            .expect("the server listens on a TCP socket")
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            http: self.http.clone(),
+            stopped: self.stopped.clone(),
+        }
+    }
+
+    /// Answer requests until the server is shut down, then hand the service back.
+    pub fn run(mut self) -> ServiceImpl<S> {
+        loop {
+            match self.http.recv() {
+                Ok(request) => self.respond(request),
+                Err(_) if self.stopped.load(Ordering::SeqCst) => break,
+                Err(error) => eprintln!("failed to accept a request: {}", error),
+            }
+        }
+        self.service
+    }
+
+    fn respond(&mut self, mut request: Request) {
+        if let Some(key) = websocket_key(&request) {
+            return self.upgrade(request, &key);
+        }
+        let too_large = || {
+            let error = ApiError::new(413, format!("request body exceeds {} bytes", MAX_BODY_LEN));
+            error.to_json()
+        };
+        if request
+            .body_length()
+            .is_some_and(|len| len as u64 > MAX_BODY_LEN)
+        {
+            return reply(request, 413, &too_large());
+        }
+        let mut body = String::new();
+        // read one byte past the limit to tell a body of the maximum length from a longer one.
+        let read = request
+            .as_reader()
+            .take(MAX_BODY_LEN + 1)
+            .read_to_string(&mut body);
+        let rpc = request.method() == &Method::Post
+            && parse_url(request.url()).is_ok_and(|(path, _)| path == ["rpc"]);
+        let (status, value) = match read {
+            Ok(len) if len as u64 > MAX_BODY_LEN => (413, too_large()),
+            Ok(_) if rpc => match self.rpc(&body) {
+                Some(value) => (200, value),
+                // a batch of notifications is answered with no content.
+                None => return reply_empty(request, 204),
+            },
+            Ok(_) => match self.handle(request.method(), request.url(), &body) {
+                Ok(value) => (200, value),
+                Err(error) => (error.status, error.to_json()),
+            },
+            Err(error) => {
+                let error = ApiError::bad_request(format!("unreadable body: {}", error));
+                (error.status, error.to_json())
+            }
+        };
+        reply(request, status, &value);
+    }
+
+    /// Register a WebSocket client and hand its socket to a writer thread.
+    fn upgrade(&mut self, request: Request, key: &str) {
+        let outbox = parse_url(request.url()).and_then(|(path, query)| {
+            if path != ["subscribe"] {
+                return Err(ApiError::not_found(format!(
+                    "no WebSocket on {}",
+                    request.url()
+                )));
+            }
+            let subscription = Subscription::from_query(&query)?;
+            watch(&request)?;
+            Ok(self.hub.subscribe(&self.service, subscription)?)
+        });
+        let outbox = match outbox {
+            Ok(outbox) => outbox,
+            Err(error) => return reply(request, error.status, &error.to_json()),
+        };
+        let accept = tungstenite::handshake::derive_accept_key(key.as_bytes());
+        let response = Response::empty(StatusCode(101))
+            .with_header(header("Upgrade", "websocket"))
+            .with_header(header("Connection", "Upgrade"))
+            .with_header(header("Sec-WebSocket-Accept", &accept));
+        let stream = request.upgrade("websocket", response);
+        let socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+        std::thread::spawn(move || outbox.run(socket));
+    }
+
+    /// Answer a JSON-RPC 2.0 request or batch, see `rpc::handle`.
+    fn rpc(&mut self, body: &str) -> Option<Value> {
+        let response = rpc::handle(&mut self.service, body);
+        self.push_events();
+        response
+    }
+
+    /// Push the chain events of the last ingest to the WebSocket clients.
+    fn push_events(&mut self) {
+        let events =
+            std::mem::take(&mut *self.events.lock().expect("the events are never poisoned"));
+        self.hub.dispatch(&self.service, &events);
+    }
+
+    /// Route a request to the service, returning the JSON body of the response.
+    pub fn handle(&mut self, method: &Method, url: &str, body: &str) -> Result<Value, ApiError> {
+        let (path, query) = parse_url(url)?;
+        let segments: Vec<&str> = path.iter().map(String::as_str).collect();
+        let service = &self.service;
+        match (method, segments.as_slice()) {
+            (Method::Post, ["blocks"]) => self.ingest(body),
+            (Method::Get, ["blocks", block_id]) => block_info(service, block_id),
+            (Method::Get, ["accounts", account, "balance"]) => {
+                balance(service, account, &balance_query(&query)?)
+            }
+            (Method::Get, ["accounts", account, "portfolio"]) => portfolio(service, account),
+            (Method::Get, ["accounts", account, "fees"]) => account_fees(service, account),
+            (Method::Get, ["accounts", account, "nonce"]) => next_nonce(service, account),
+            (Method::Get, ["accounts", account, "history"]) => {
+                history(service, account, &history_query(&query)?)
+            }
+            (Method::Get, ["transactions", tx_id]) => transaction(service, tx_id),
+            (Method::Get, ["chain", "tips"]) => tips(service),
+            (Method::Get, ["chain", "forks"]) => forks(service),
+            (Method::Get, ["chain", "pending"]) => pending(service),
+            (Method::Get, ["chain", "tree"]) => tree(service, tree_depth(&query)?),
+            (Method::Get, ["chain", "supply"]) => supply(service, &balance_query(&query)?),
+            (Method::Get, ["subscribe"]) => Err(ApiError::new(426, "expected a WebSocket upgrade")),
+            (_, ["rpc"]) => Err(ApiError::new(405, "JSON-RPC requests are POSTed")),
+            (_, ["blocks"])
+            | (_, ["blocks", _])
+            | (_, ["accounts", _, "balance" | "history" | "portfolio" | "fees" | "nonce"])
+            | (_, ["transactions", _])
+            | (_, ["chain", "tips" | "forks" | "pending" | "tree" | "supply"])
+            | (_, ["subscribe"]) => Err(ApiError::new(
+                405,
+                format!("method {} is not allowed on {}", method, url),
+            )),
+            _ => Err(ApiError::not_found(format!("no route for {}", url))),
+        }
+    }
+
+    fn ingest(&mut self, body: &str) -> Result<Value, ApiError> {
+        let block: Block = serde_json::from_str(body)
+            .map_err(|error| ApiError::bad_request(format!("malformed block: {}", error)))?;
+        let report = ingest(&mut self.service, &block);
+        self.push_events();
+        report
+    }
+}
+
+/// Make the reads of a connection about to be upgraded time out, so that the writer of its
+/// WebSocket also reads the frames of the client. tiny_http hides the stream of an upgraded
+/// request, so its socket is looked up among the open descriptors by the client address.
+fn watch(request: &Request) -> Result<(), ApiError> {
+    request
+        .remote_addr()
+        .and_then(|client| connection_of(*client))
+        .and_then(|connection| connection.set_read_timeout(Some(READ_TIMEOUT)).ok())
+        .ok_or_else(|| ApiError::new(500, "cannot watch the WebSocket connection"))
+}
+
+#[cfg(unix)]
+fn connection_of(client: SocketAddr) -> Option<TcpStream> {
+    use std::os::fd::{BorrowedFd, RawFd};
+
+    let descriptors = std::fs::read_dir("/dev/fd").ok()?;
+    descriptors
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<RawFd>().ok())
+        .find_map(|fd| {
+            // SAFETY: the descriptor is only duplicated. Had it been closed meanwhile, the
+            // duplicate fails or is another file, which is dropped.
+            let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+            let stream = TcpStream::from(fd.try_clone_to_owned().ok()?);
+            (stream.peer_addr().ok()? == client).then_some(stream)
+        })
+}
+
+#[cfg(not(unix))]
+fn connection_of(_client: SocketAddr) -> Option<TcpStream> {
+    None
+}
+
+/// Where to read a balance: at a block, at a canonical height or at the tip of a fork. At
+/// most one of them is set, none meaning the canonical tip. `asset` defaults to the native
+/// asset.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct BalanceQuery {
+    pub block: Option<BlockID>,
+    pub height: Option<u64>,
+    pub fork: Option<BlockID>,
+    pub asset: Option<String>,
+}
+
+impl BalanceQuery {
+    fn asset(&self) -> &str {
+        self.asset.as_deref().unwrap_or(NATIVE_ASSET)
+    }
+}
+
+pub(crate) fn balance_query(query: &Params) -> Result<BalanceQuery, ApiError> {
+    let mut balance = BalanceQuery::default();
+    for (key, value) in query {
+        match key.as_str() {
+            "block" => balance.block = Some(value.clone()),
+            "height" => {
+                let height = value
+                    .parse()
+                    .map_err(|_| ApiError::bad_request(format!("invalid height {}", value)))?;
+                balance.height = Some(height);
+            }
+            "fork" => balance.fork = Some(value.clone()),
+            "asset" => balance.asset = Some(value.clone()),
+            _ => return Err(ApiError::bad_request(format!("unknown parameter {}", key))),
+        }
+    }
+    Ok(balance)
+}
+
+pub(crate) fn history_query(query: &Params) -> Result<HistoryQuery, ApiError> {
+    let mut history = HistoryQuery::default();
+    for (key, value) in query {
+        match key.as_str() {
+            "cursor" => {
+                let cursor = value
+                    .parse()
+                    .map_err(|error| ApiError::bad_request(format!("invalid cursor: {}", error)))?;
+                history.cursor = Some(cursor);
+            }
+            "limit" => {
+                history.limit = value
+                    .parse()
+                    .map_err(|_| ApiError::bad_request(format!("invalid limit {}", value)))?;
+            }
+            "direction" => {
+                history.direction = match value.as_str() {
+                    "forward" => Direction::Forward,
+                    "backward" => Direction::Backward,
+                    _ => {
+                        return Err(ApiError::bad_request(format!(
+                            "invalid direction {}",
+                            value
+                        )))
+                    }
+                };
+            }
+            _ => return Err(ApiError::bad_request(format!("unknown parameter {}", key))),
+        }
+    }
+    Ok(history)
+}
+
+/// The `depth` parameter of a tree, how many heights below the canonical tip to draw.
+pub(crate) fn tree_depth(query: &Params) -> Result<Option<u64>, ApiError> {
+    let mut depth = None;
+    for (key, value) in query {
+        match key.as_str() {
+            "depth" => {
+                let value = value
+                    .parse()
+                    .map_err(|_| ApiError::bad_request(format!("invalid depth {}", value)))?;
+                depth = Some(value);
+            }
+            _ => return Err(ApiError::bad_request(format!("unknown parameter {}", key))),
+        }
+    }
+    Ok(depth)
+}
+
+pub(crate) fn ingest<S: Storage>(
+    service: &mut ServiceImpl<S>,
+    block: &Block,
+) -> Result<Value, ApiError> {
+    to_json(&service.ingest_block(block)?)
+}
+
+pub(crate) fn block_info<S: Storage>(
+    service: &ServiceImpl<S>,
+    block_id: &str,
+) -> Result<Value, ApiError> {
+    let (Some(node), Some(block)) = (service.tree.get(block_id), service.get_block(block_id)?)
+    else {
+        return Err(ApiError::not_found(format!("unknown block {}", block_id)));
+    };
+    let canonical = service.canonical_block_at(node.height)?.as_deref() == Some(block_id);
+    Ok(json!({
+        "block": block,
+        "height": node.height,
+        "children": node.children,
+        "cumulative_txs": node.cumulative_txs,
+        "cumulative_weight": node.cumulative_weight,
+        "fees": node.fees.fees,
+        "reward": node.fees.reward,
+        "canonical": canonical,
+    }))
+}
+
+/// The block a balance query reads at, `None` for the canonical tip of an empty tree.
+fn query_block<S: Storage>(
+    service: &ServiceImpl<S>,
+    query: &BalanceQuery,
+) -> Result<Option<BlockID>, ApiError> {
+    match query {
+        BalanceQuery {
+            block: None,
+            height: None,
+            fork: None,
+            ..
+        } => Ok(service.canonical_tip().cloned()),
+        BalanceQuery {
+            block: Some(block_id),
+            height: None,
+            fork: None,
+            ..
+        } => {
+            if !service.tree.contains(block_id) {
+                return Err(ApiError::not_found(format!("unknown block {}", block_id)));
+            }
+            Ok(Some(block_id.clone()))
+        }
+        BalanceQuery {
+            block: None,
+            height: Some(height),
+            fork: None,
+            ..
+        } => match service.canonical_block_at(*height)? {
+            Some(block_id) => Ok(Some(block_id)),
+            None => Err(ApiError::not_found(format!(
+                "no canonical block at height {}",
+                height
+            ))),
+        },
+        BalanceQuery {
+            block: None,
+            height: None,
+            fork: Some(tip_id),
+            ..
+        } => {
+            if !service.tree.is_tip(tip_id) {
+                return Err(ApiError::not_found(format!(
+                    "block {} is not the tip of a fork",
+                    tip_id
+                )));
+            }
+            Ok(Some(tip_id.clone()))
+        }
+        _ => Err(ApiError::bad_request(
+            "expected at most one of the block, height or fork parameters",
+        )),
+    }
+}
+
+pub(crate) fn balance<S: Storage>(
+    service: &ServiceImpl<S>,
+    account: &str,
+    query: &BalanceQuery,
+) -> Result<Value, ApiError> {
+    let block_id = query_block(service, query)?;
+    let asset = query.asset();
+    let balance = match &block_id {
+        Some(block_id) => service.get_balance_at_block(account, asset, block_id)?,
+        None => service.get_balance(account, asset)?,
+    };
+    let balance = json!({ "account": account, "block_id": block_id, "balance": balance });
+    Ok(name_asset(balance, asset))
+}
+
+/// The non-zero balances of an account at the canonical tip, by asset.
+pub(crate) fn portfolio<S: Storage>(
+    service: &ServiceImpl<S>,
+    account: &str,
+) -> Result<Value, ApiError> {
+    Ok(json!({
+        "account": account,
+        "block_id": service.canonical_tip(),
+        "balances": service.get_portfolio(account)?,
+    }))
+}
+
+/// The fees an account paid and earned as a producer up to the canonical tip.
+pub(crate) fn account_fees<S: Storage>(
+    service: &ServiceImpl<S>,
+    account: &str,
+) -> Result<Value, ApiError> {
+    let fees = service.get_account_fees(account)?;
+    Ok(json!({
+        "account": account,
+        "block_id": service.canonical_tip(),
+        "paid": fees.paid,
+        "earned": fees.earned,
+    }))
+}
+
+/// The nonce the next transfer of an account must at least carry, at the canonical tip.
+pub(crate) fn next_nonce<S: Storage>(
+    service: &ServiceImpl<S>,
+    account: &str,
+) -> Result<Value, ApiError> {
+    Ok(json!({
+        "account": account,
+        "block_id": service.canonical_tip(),
+        "next_nonce": service.get_next_nonce(account)?,
+    }))
+}
+
+/// The coins minted, burned and in circulation up to a block, read like a balance.
+pub(crate) fn supply<S: Storage>(
+    service: &ServiceImpl<S>,
+    query: &BalanceQuery,
+) -> Result<Value, ApiError> {
+    let node = query_block(service, query)?.and_then(|block_id| service.tree.get(&block_id));
+    let asset = query.asset();
+    let supply = node.map(|node| node.supply_of(asset)).unwrap_or_default();
+    let supply = json!({
+        "block_id": node.map(|node| &node.block_id),
+        "height": node.map(|node| node.height),
+        "minted": supply.minted,
+        "burned": supply.burned,
+        "circulating": supply.circulating(),
+    });
+    Ok(name_asset(supply, asset))
+}
+
+/// Add the asset to a result unless it is the native one, which stays implicit like in
+/// transactions.
+fn name_asset(mut value: Value, asset: &str) -> Value {
+    if asset != NATIVE_ASSET {
+        value["asset"] = json!(asset);
+    }
+    value
+}
+
+pub(crate) fn history<S: Storage>(
+    service: &ServiceImpl<S>,
+    account: &str,
+    query: &HistoryQuery,
+) -> Result<Value, ApiError> {
+    if query.limit == 0 {
+        return Err(ApiError::bad_request(
+            "the history limit must be at least 1",
+        ));
+    }
+    let page = service
+        .get_account_history(account, query)
+        // a stale cursor is the only way a well-formed query can fail.
+        .map_err(|error| match query.cursor {
+            Some(_) => ApiError::new(409, format!("{:#}", error)),
+            None => error.into(),
+        })?;
+    to_json(&page)
+}
+
+pub(crate) fn transaction<S: Storage>(
+    service: &ServiceImpl<S>,
+    tx_id: &str,
+) -> Result<Value, ApiError> {
+    let inclusions = service.get_transaction(tx_id)?;
+    if inclusions.is_empty() {
+        return Err(ApiError::not_found(format!(
+            "unknown transaction {}",
+            tx_id
+        )));
+    }
+    Ok(json!({ "tx_id": tx_id, "inclusions": inclusions }))
+}
+
+pub(crate) fn tips<S: Storage>(service: &ServiceImpl<S>) -> Result<Value, ApiError> {
+    let canonical_tip = service.canonical_tip();
+    let tips: Vec<Value> = service
+        .tree
+        .tips()
+        .map(|tip| {
+            json!({
+                "block_id": tip.block_id,
+                "height": tip.height,
+                "cumulative_txs": tip.cumulative_txs,
+                "cumulative_weight": tip.cumulative_weight,
+                "canonical": Some(&tip.block_id) == canonical_tip,
+            })
+        })
+        .collect();
+    Ok(json!({ "canonical_tip": canonical_tip, "tips": tips }))
+}
+
+/// Every fork by its tip, with the last block it shares with the canonical chain and the
+/// number of its blocks off the canonical chain.
+pub(crate) fn forks<S: Storage>(service: &ServiceImpl<S>) -> Result<Value, ApiError> {
+    let tree = &service.tree;
+    let canonical_tip = service.canonical_tip();
+    let forks: Vec<Value> = tree
+        .tips()
+        .map(|tip| {
+            let (_, connect) = tree.route(canonical_tip.map(String::as_str), &tip.block_id);
+            let fork_point: Option<&BlockID> = match connect.first() {
+                Some(first) => tree.get(first).and_then(|node| node.parent_id()),
+                None => Some(&tip.block_id),
+            };
+            json!({
+                "tip": tip.block_id,
+                "height": tip.height,
+                "fork_point": fork_point,
+                "length": connect.len(),
+                "canonical": Some(&tip.block_id) == canonical_tip,
+            })
+        })
+        .collect();
+    Ok(Value::Array(forks))
+}
+
+pub(crate) fn pending<S: Storage>(service: &ServiceImpl<S>) -> Result<Value, ApiError> {
+    to_json(&service.get_pending_blocks()?)
+}
+
+pub(crate) fn tree<S: Storage>(
+    service: &ServiceImpl<S>,
+    depth: Option<u64>,
+) -> Result<Value, ApiError> {
+    to_json(&ForkTree::of_service(service, depth)?)
+}
+
+/// An error answered to a request, with its HTTP status.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    pub status: u16,
+    pub message: String,
+    /// The rejection of an ingested block.
+    pub ingest_error: Option<IngestError>,
+}
+
+impl ApiError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+            ingest_error: None,
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(400, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(404, message)
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "error": self.message, "ingest_error": self.ingest_error })
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast::<IngestError>() {
+            Ok(rejection) => Self {
+                status: 422,
+                message: rejection.to_string(),
+                ingest_error: Some(rejection),
+            },
+            Err(error) => Self::new(500, format!("{:#}", error)),
+        }
+    }
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).expect("the header is valid")
+}
+
+fn reply_empty(request: Request, status: u16) {
+    if let Err(error) = request.respond(Response::empty(StatusCode(status))) {
+        eprintln!("failed to send a response: {}", error);
+    }
+}
+
+fn reply(request: Request, status: u16, value: &Value) {
+    let response = Response::from_string(value.to_string())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"));
+    if let Err(error) = request.respond(response) {
+        eprintln!("failed to send a response: {}", error);
+    }
+}
+
+/// The key of a WebSocket opening handshake.
+fn websocket_key(request: &Request) -> Option<String> {
+    let upgrade = request
+        .headers()
+        .iter()
+        .any(|h| h.field.equiv("Upgrade") && h.value.as_str().eq_ignore_ascii_case("websocket"));
+    let key = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Sec-WebSocket-Key"))?;
+    upgrade.then(|| key.value.to_string())
+}
+
+fn to_json<T: Serialize>(value: &T) -> Result<Value, ApiError> {
+    serde_json::to_value(value)
+        .map_err(|error| ApiError::new(500, format!("failed to encode the response: {}", error)))
+}
+
+/// Query parameters of a URL, in order.
+pub(crate) type Params = Vec<(String, String)>;
+
+/// The decoded segments of the path of a URL and its query parameters.
+fn parse_url(url: &str) -> Result<(Vec<String>, Params), ApiError> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let segments = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_decode)
+        .collect::<Result<_, _>>()?;
+    let mut params = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        // a `+` stands for a space in query strings only, a literal one is encoded as `%2B`.
+        let decode = |component: &str| percent_decode(&component.replace('+', " "));
+        params.push((decode(key)?, decode(value)?));
+    }
+    Ok((segments, params))
+}
+
+fn percent_decode(encoded: &str) -> Result<String, ApiError> {
+    let invalid = || ApiError::bad_request(format!("invalid percent-encoding in {}", encoded));
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        match byte {
+            b'%' => {
+                let hex = tail
+                    .get(..2)
+                    .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+                    .ok_or_else(invalid)?;
+                let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+use blockchain_explorer::api::{open_data_dir, ApiServer};
+use blockchain_explorer::cli::DEFAULT_DATA_DIR;
+use blockchain_explorer::ServiceConfig;
+
+const USAGE: &str = "usage: server [--addr HOST:PORT] [--data-dir DIR] [--recover]";
+
+fn main() {
+    let mut addr = String::from("127.0.0.1:8080");
+    let mut data_dir = PathBuf::from(DEFAULT_DATA_DIR);
+    let mut recover = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--recover" {
+            recover = true;
+            continue;
+        }
+        match (arg.as_str(), args.next()) {
+            ("--addr", Some(value)) => addr = value,
+            ("--data-dir", Some(value)) => data_dir = PathBuf::from(value),
+            _ => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let (service, report) = open_data_dir(&data_dir, ServiceConfig::default(), recover)
+        .expect("Failed to open the data");
+    if let Some(report) = report.filter(|report| report.rebuilt) {
+        eprintln!("recovered the data directory: {:?}", report);
+    }
+    let server = ApiServer::bind(&addr, service).expect("Failed to start the server");
+    println!("listening on http://{}", server.local_addr());
+    server.run();
+}
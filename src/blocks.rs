@@ -12,14 +12,19 @@ lazy_static! {
                 tx_id: "A1".to_string(),
                 to: ALICE.to_string(),
                 amount: 10,
+                asset: None,
             },
             Transaction::Transfer {
                 tx_id: "A0".to_string(),
                 from: ALICE.to_string(),
                 to: BOB.to_string(),
                 amount: 5,
+                asset: None,
+                fee: None,
+                nonce: None,
             },
         ],
+        producer: None,
     };
     pub static ref BLOCK_E: Block = Block {
         block_id: "E".to_string(),
@@ -29,14 +34,19 @@ lazy_static! {
                 tx_id: "A1".to_string(),
                 to: ALICE.to_string(),
                 amount: 8,
+                asset: None,
             },
             Transaction::Transfer {
                 tx_id: "A0".to_string(),
                 from: ALICE.to_string(),
                 to: BOB.to_string(),
                 amount: 7,
+                asset: None,
+                fee: None,
+                nonce: None,
             },
         ],
+        producer: None,
     };
     pub static ref BLOCK_B: Block = Block {
         block_id: "B".to_string(),
@@ -46,7 +56,11 @@ lazy_static! {
             from: BOB.to_string(),
             to: ALICE.to_string(),
             amount: 5,
+            asset: None,
+            fee: None,
+            nonce: None,
         }],
+        producer: None,
     };
     pub static ref BLOCK_C: Block = Block {
         block_id: "C".to_string(),
@@ -56,7 +70,11 @@ lazy_static! {
             from: BOB.to_string(),
             to: ALICE.to_string(),
             amount: 3,
+            asset: None,
+            fee: None,
+            nonce: None,
         }],
+        producer: None,
     };
     pub static ref BLOCK_D: Block = Block {
         block_id: "D".to_string(),
@@ -66,6 +84,10 @@ lazy_static! {
             from: ALICE.to_string(),
             to: BOB.to_string(),
             amount: 2,
+            asset: None,
+            fee: None,
+            nonce: None,
         }],
+        producer: None,
     };
 }
@@ -0,0 +1,337 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+
+use anyhow::Context;
+use serde_json::{json, Value};
+
+use crate::api::{self, ApiError, BalanceQuery, Params};
+use crate::history::HistoryQuery;
+use crate::ingest::{BlockStatus, IngestError};
+use crate::render::{ForkTree, TreeFormat};
+use crate::store::Storage;
+use crate::{Block, BlockID, Service, ServiceImpl, TransactionID};
+
+/// Data directory used when `--data-dir` is not given.
+pub const DEFAULT_DATA_DIR: &str = "./explorer-data";
+
+pub const USAGE: &str = "\
+usage: blockchain-explorer <command> [--data-dir DIR] [--recover]
+
+commands:
+  ingest <file>       ingest the blocks of a file, - for stdin
+  balance <account>   [--block ID | --height N | --fork TIP] [--asset ASSET]
+  portfolio <account> the balances of an account in every asset
+  fees <account>      the fees an account paid and earned as a producer
+  nonce <account>     the nonce the next transfer of an account must at least carry
+  block <id>
+  tx <id>
+  tips
+  history <account>   [--cursor CURSOR] [--limit N] [--direction forward|backward]
+  export              [--output FILE]
+  import <file>       ingest exported blocks, skipping the known ones
+  stats
+  supply              [--block ID | --height N | --fork TIP] [--asset ASSET]
+  tree                [--format ascii|dot|json] [--depth N]
+  repl                [--memory] explore interactively, see help inside";
+
+/// A subcommand of the binary operating on the service stored in a data directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Ingest the blocks of a file, printing the report or the error of each block.
+    Ingest {
+        file: String,
+    },
+    Balance {
+        account: String,
+        query: BalanceQuery,
+    },
+    Portfolio {
+        account: String,
+    },
+    Fees {
+        account: String,
+    },
+    Nonce {
+        account: String,
+    },
+    Block {
+        block_id: BlockID,
+    },
+    Tx {
+        tx_id: TransactionID,
+    },
+    Tips,
+    History {
+        account: String,
+        query: HistoryQuery,
+    },
+    /// Write every block, one per line, in an order `Import` rebuilds the same tree from.
+    Export {
+        output: Option<String>,
+    },
+    /// Ingest the blocks of a file, counting the ones already known instead of failing.
+    Import {
+        file: String,
+    },
+    Stats,
+    /// Coins minted, burned and in circulation, read like a balance.
+    Supply {
+        query: BalanceQuery,
+    },
+    /// Draw the block tree, only the heights at most `depth` below the canonical tip if set.
+    Tree {
+        format: TreeFormat,
+        depth: Option<u64>,
+    },
+}
+
+/// A parsed command line: the command and the data directory it runs against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Invocation {
+    pub data_dir: PathBuf,
+    /// Check every record of the data directory and rebuild the inconsistent ones on opening.
+    pub recover: bool,
+    pub command: Command,
+}
+
+impl Invocation {
+    /// Parse the name and the arguments of a subcommand, `None` if `name` is not one. Blocks
+    /// are read from a file whose content is a JSON block, an array of blocks or one block
+    /// per line.
+    pub fn parse(name: &str, args: &[String]) -> anyhow::Result<Option<Self>> {
+        let (options, positional): (&[&str], usize) = match name {
+            "ingest" | "import" | "block" | "tx" | "portfolio" | "fees" | "nonce" => (&[], 1),
+            "balance" => (&["block", "height", "fork", "asset"], 1),
+            "supply" => (&["block", "height", "fork", "asset"], 0),
+            "history" => (&["cursor", "limit", "direction"], 1),
+            "export" => (&["output"], 0),
+            "tree" => (&["format", "depth"], 0),
+            "tips" | "stats" => (&[], 0),
+            _ => return Ok(None),
+        };
+        let mut data_dir = PathBuf::from(DEFAULT_DATA_DIR);
+        let mut recover = false;
+        let mut params = Params::new();
+        let mut values = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(option) = arg.strip_prefix("--") else {
+                values.push(arg.clone());
+                continue;
+            };
+            if option == "recover" {
+                recover = true;
+                continue;
+            }
+            let value = args
+                .next()
+                .with_context(|| format!("option --{} needs a value", option))?;
+            if option == "data-dir" {
+                data_dir = PathBuf::from(value);
+            } else if options.contains(&option) {
+                params.push((option.to_string(), value.clone()));
+            } else {
+                anyhow::bail!("unknown option --{} of {}", option, name);
+            }
+        }
+        if values.len() != positional {
+            anyhow::bail!(
+                "{} takes {} argument(s), got {}",
+                name,
+                positional,
+                values.len()
+            );
+        }
+        let mut values = values.into_iter();
+        let mut value = || values.next().unwrap_or_default();
+        let command = match name {
+            "ingest" => Command::Ingest { file: value() },
+            "balance" => Command::Balance {
+                account: value(),
+                query: api::balance_query(&params).map_err(into_anyhow)?,
+            },
+            "block" => Command::Block { block_id: value() },
+            "tx" => Command::Tx { tx_id: value() },
+            "tips" => Command::Tips,
+            "portfolio" => Command::Portfolio { account: value() },
+            "fees" => Command::Fees { account: value() },
+            "nonce" => Command::Nonce { account: value() },
+            "supply" => Command::Supply {
+                query: api::balance_query(&params).map_err(into_anyhow)?,
+            },
+            "history" => Command::History {
+                account: value(),
+                query: api::history_query(&params).map_err(into_anyhow)?,
+            },
+            "export" => Command::Export {
+                output: params.pop().map(|(_, file)| file),
+            },
+            "import" => Command::Import { file: value() },
+            "tree" => {
+                let mut format = TreeFormat::default();
+                if let Some(i) = params.iter().position(|(option, _)| option == "format") {
+                    format = params.remove(i).1.parse()?;
+                }
+                Command::Tree {
+                    format,
+                    depth: api::tree_depth(&params).map_err(into_anyhow)?,
+                }
+            }
+            _ => Command::Stats,
+        };
+        Ok(Some(Self {
+            data_dir,
+            recover,
+            command,
+        }))
+    }
+}
+
+/// Run a command, writing its result to `output`. Queries print one JSON document. Returns
+/// whether every block of an ingest or an import went in.
+pub fn run<S: Storage>(
+    service: &mut ServiceImpl<S>,
+    command: &Command,
+    mut output: impl Write,
+) -> anyhow::Result<bool> {
+    let result = match command {
+        Command::Ingest { file } => return ingest(service, &read_blocks(file)?, output),
+        Command::Import { file } => return import(service, &read_blocks(file)?, output),
+        Command::Export { output: None } => return export(service, output),
+        Command::Export { output: Some(file) } => {
+            let file =
+                fs::File::create(file).with_context(|| format!("failed to create {}", file))?;
+            return export(service, io::BufWriter::new(file));
+        }
+        Command::Tree { format, depth } => {
+            let tree = ForkTree::of_service(service, *depth)?;
+            writeln!(output, "{}", tree.render(*format).trim_end())?;
+            return Ok(true);
+        }
+        Command::Balance { account, query } => api::balance(service, account, query),
+        Command::Block { block_id } => api::block_info(service, block_id),
+        Command::Tx { tx_id } => api::transaction(service, tx_id),
+        Command::Tips => api::tips(service),
+        Command::Portfolio { account } => api::portfolio(service, account),
+        Command::Fees { account } => api::account_fees(service, account),
+        Command::Nonce { account } => api::next_nonce(service, account),
+        Command::History { account, query } => api::history(service, account, query),
+        Command::Stats => stats(service),
+        Command::Supply { query } => api::supply(service, query),
+    };
+    let result = result.map_err(into_anyhow)?;
+    serde_json::to_writer_pretty(&mut output, &result)?;
+    writeln!(output)?;
+    Ok(true)
+}
+
+fn ingest<S: Storage>(
+    service: &mut ServiceImpl<S>,
+    blocks: &[Block],
+    mut output: impl Write,
+) -> anyhow::Result<bool> {
+    let mut ingested = true;
+    for block in blocks {
+        let line = match api::ingest(service, block) {
+            Ok(report) => report,
+            Err(error) if error.status == 500 => return Err(into_anyhow(error)),
+            Err(error) => {
+                ingested = false;
+                json!({
+                    "block_id": block.block_id,
+                    "error": error.message,
+                    "ingest_error": error.ingest_error,
+                })
+            }
+        };
+        writeln!(output, "{}", line)?;
+    }
+    Ok(ingested)
+}
+
+fn import<S: Storage>(
+    service: &mut ServiceImpl<S>,
+    blocks: &[Block],
+    mut output: impl Write,
+) -> anyhow::Result<bool> {
+    let (mut imported, mut pending, mut known, mut failed) = (0, 0, 0, 0);
+    for block in blocks {
+        match service.ingest_block(block) {
+            Ok(report) if matches!(report.status, BlockStatus::Pending { .. }) => pending += 1,
+            Ok(_) => imported += 1,
+            Err(error) => match error.downcast_ref::<IngestError>() {
+                Some(IngestError::DuplicateBlock(_)) => known += 1,
+                Some(rejection) => {
+                    eprintln!("block {}: {}", block.block_id, rejection);
+                    failed += 1;
+                }
+                None => return Err(error),
+            },
+        }
+    }
+    let summary = json!({
+        "imported": imported,
+        "pending": pending,
+        "known": known,
+        "failed": failed,
+    });
+    writeln!(output, "{}", summary)?;
+    Ok(failed == 0)
+}
+
+fn export<S: Storage>(service: &ServiceImpl<S>, mut output: impl Write) -> anyhow::Result<bool> {
+    for block in service.export_blocks()? {
+        serde_json::to_writer(&mut output, &block)?;
+        writeln!(output)?;
+    }
+    output.flush()?;
+    Ok(true)
+}
+
+fn stats<S: Storage>(service: &ServiceImpl<S>) -> Result<Value, ApiError> {
+    let tip = service
+        .canonical_tip()
+        .and_then(|tip| service.tree.get(tip));
+    Ok(json!({
+        "blocks": service.tree.len(),
+        "forks": service.tree.tips().count(),
+        "canonical_tip": tip.map(|tip| &tip.block_id),
+        "height": tip.map(|tip| tip.height),
+        "canonical_transactions": tip.map_or(0, |tip| tip.cumulative_txs),
+        "pending": service.get_pending_blocks()?.len(),
+        "accounts": service.accounts()?.len(),
+    }))
+}
+
+/// The blocks of a file, or of stdin for `-`.
+fn read_blocks(file: &str) -> anyhow::Result<Vec<Block>> {
+    let input = if file == "-" {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        input
+    } else {
+        fs::read_to_string(file).with_context(|| format!("failed to read {}", file))?
+    };
+    let mut blocks = Vec::new();
+    for value in serde_json::Deserializer::from_str(&input).into_iter::<Value>() {
+        let value = value.with_context(|| format!("{} is not JSON", file))?;
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            let block = serde_json::from_value(value)
+                .with_context(|| format!("malformed block in {}", file))?;
+            blocks.push(block);
+        }
+    }
+    Ok(blocks)
+}
+
+fn into_anyhow(error: ApiError) -> anyhow::Error {
+    match error.ingest_error {
+        Some(rejection) => rejection.into(),
+        None => anyhow::anyhow!(error.message),
+    }
+}
@@ -0,0 +1,92 @@
+use std::fmt;
+
+use crate::BlockID;
+
+/// A change of the canonical chain caused by an ingest.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ChainEvent {
+    /// A block joined the canonical chain.
+    BlockConnected { block_id: BlockID, height: u64 },
+    /// A block left the canonical chain.
+    BlockDisconnected { block_id: BlockID, height: u64 },
+    /// The canonical tip moved, after the blocks between both tips were (dis)connected.
+    TipChanged {
+        old_tip: Option<BlockID>,
+        new_tip: BlockID,
+        height: u64,
+    },
+    /// Another fork became canonical. `depth` is the number of blocks disconnected from the
+    /// old fork down to `fork_point`, the last block both forks share.
+    ReorgCompleted {
+        old_tip: BlockID,
+        new_tip: BlockID,
+        fork_point: Option<BlockID>,
+        depth: u64,
+    },
+}
+
+/// Receives the chain events of each ingest, in order, once the ingest is committed.
+pub trait Subscriber: Send {
+    fn on_event(&mut self, event: &ChainEvent);
+}
+
+impl<F: FnMut(&ChainEvent) + Send> Subscriber for F {
+    fn on_event(&mut self, event: &ChainEvent) {
+        self(event)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+/// The subscribers of a service and the events of the ongoing ingest.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Vec<(SubscriptionId, Box<dyn Subscriber>)>,
+    next_id: u64,
+    pending: Vec<ChainEvent>,
+}
+
+impl EventBus {
+    pub fn subscribe(&mut self, subscriber: Box<dyn Subscriber>) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.subscribers.push((id, subscriber));
+        id
+    }
+
+    /// Remove a subscriber, returning whether it was subscribed.
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let len = self.subscribers.len();
+        self.subscribers.retain(|(subscribed, _)| *subscribed != id);
+        self.subscribers.len() != len
+    }
+
+    /// Queue an event until the ingest producing it is committed.
+    pub fn emit(&mut self, event: ChainEvent) {
+        self.pending.push(event);
+    }
+
+    /// Deliver the queued events to every subscriber.
+    pub fn publish(&mut self) {
+        for event in self.pending.drain(..) {
+            for (_, subscriber) in &mut self.subscribers {
+                subscriber.on_event(&event);
+            }
+        }
+    }
+
+    /// Drop the queued events of an ingest which was rolled back.
+    pub fn discard(&mut self) {
+        self.pending.clear();
+    }
+}
+
+impl fmt::Debug for EventBus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventBus")
+            .field("subscribers", &self.subscribers.len())
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
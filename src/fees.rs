@@ -0,0 +1,55 @@
+use crate::{Block, Transaction};
+
+/// Coins of the native asset minted for the producer of each block, by height.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RewardSchedule {
+    /// Producers only collect the fees of their blocks.
+    #[default]
+    None,
+    /// The same reward at every height.
+    Constant(u64),
+    /// `initial` from the genesis block on, halved every `interval` blocks.
+    Halving { initial: u64, interval: u64 },
+}
+
+impl RewardSchedule {
+    pub fn reward_at(&self, height: u64) -> u64 {
+        match *self {
+            RewardSchedule::None => 0,
+            RewardSchedule::Constant(reward) => reward,
+            RewardSchedule::Halving { initial, interval } => {
+                let halvings = height.checked_div(interval).unwrap_or(0);
+                u32::try_from(halvings)
+                    .ok()
+                    .and_then(|halvings| initial.checked_shr(halvings))
+                    .unwrap_or(0)
+            }
+        }
+    }
+}
+
+/// Fees paid by the applied transactions of a block and the reward minted for its producer.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BlockFees {
+    pub fees: u128,
+    pub reward: u64,
+}
+
+/// Fees an account paid and collected as a producer on the canonical chain.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AccountFees {
+    pub paid: u128,
+    pub earned: u128,
+}
+
+/// The mint of a block reward, applied after the transactions of the block. It is recorded
+/// with the applied transactions, under an id derived from the block, but not in the
+/// transaction index since the block does not include it.
+pub fn reward_transaction(block: &Block, producer: &str, reward: u64) -> Transaction {
+    Transaction::Mint {
+        tx_id: format!("{}/reward", block.block_id),
+        to: producer.to_string(),
+        amount: reward,
+        asset: None,
+    }
+}
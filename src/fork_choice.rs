@@ -0,0 +1,109 @@
+use crate::tree::{BlockNode, BlockTree};
+use crate::Block;
+
+/// A rule selecting the canonical chain among the forks of a block tree.
+pub trait ForkChoice: std::fmt::Debug + Send {
+    /// Select the tip of the canonical chain, or `None` if the tree is empty.
+    fn select_tip<'a>(&self, tree: &'a BlockTree) -> Option<&'a BlockNode>;
+
+    /// Weight of a block, summed along each chain into `BlockNode::cumulative_weight` when the
+    /// block is ingested.
+    fn block_weight(&self, _block: &Block) -> u64 {
+        1
+    }
+
+    /// Whether the rule compares `BlockNode::subtree_size`, which the tree then keeps up to date.
+    fn uses_subtree_sizes(&self) -> bool {
+        false
+    }
+}
+
+impl Default for Box<dyn ForkChoice> {
+    fn default() -> Self {
+        Box::new(LongestChain::default())
+    }
+}
+
+/// Which block wins when two candidates score the same.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// The block that was ingested first wins.
+    FirstSeen,
+    /// The block that was ingested last wins.
+    #[default]
+    LatestSeen,
+}
+
+impl TieBreak {
+    /// Pick the candidate with the highest score, breaking ties by arrival order.
+    fn best<'a>(
+        self,
+        candidates: impl Iterator<Item = (u64, &'a BlockNode)>,
+    ) -> Option<&'a BlockNode> {
+        candidates
+            .max_by(|(a_score, a), (b_score, b)| {
+                a_score.cmp(b_score).then_with(|| match self {
+                    TieBreak::FirstSeen => b.seq.cmp(&a.seq),
+                    TieBreak::LatestSeen => a.seq.cmp(&b.seq),
+                })
+            })
+            .map(|(_, node)| node)
+    }
+}
+
+/// The chain with the most blocks is canonical.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LongestChain {
+    pub tie_break: TieBreak,
+}
+
+impl ForkChoice for LongestChain {
+    fn select_tip<'a>(&self, tree: &'a BlockTree) -> Option<&'a BlockNode> {
+        self.tie_break
+            .best(tree.tips().map(|node| (node.height, node)))
+    }
+}
+
+/// The chain with the largest total weight is canonical, where each block weighs `weight(block)`.
+/// Weights are recorded at ingestion, so blocks stored under another rule keep their old weight.
+#[derive(Debug, Clone, Copy)]
+pub struct HeaviestChain {
+    pub weight: fn(&Block) -> u64,
+    pub tie_break: TieBreak,
+}
+
+impl ForkChoice for HeaviestChain {
+    fn select_tip<'a>(&self, tree: &'a BlockTree) -> Option<&'a BlockNode> {
+        self.tie_break
+            .best(tree.tips().map(|tip| (tip.cumulative_weight, tip)))
+    }
+
+    fn block_weight(&self, block: &Block) -> u64 {
+        (self.weight)(block)
+    }
+}
+
+/// GHOST: starting from the genesis block, repeatedly follow the child with the most descendants.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Ghost {
+    pub tie_break: TieBreak,
+}
+
+impl ForkChoice for Ghost {
+    fn select_tip<'a>(&self, tree: &'a BlockTree) -> Option<&'a BlockNode> {
+        let mut best = self
+            .tie_break
+            .best(tree.roots().map(|root| (root.subtree_size, root)))?;
+        while !best.children.is_empty() {
+            best = self.tie_break.best(
+                tree.children(best.id())
+                    .map(|child| (child.subtree_size, child)),
+            )?;
+        }
+        Some(best)
+    }
+
+    fn uses_subtree_sizes(&self) -> bool {
+        true
+    }
+}
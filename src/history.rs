@@ -0,0 +1,188 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::indexer::{AppliedTransaction, IndexContext, Indexer};
+use crate::store::Table;
+use crate::{AssetID, Block, BlockID, Transaction, TransactionID};
+
+/// A transaction touching an account on the canonical chain.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HistoryEntry {
+    pub account: String,
+    pub block_id: BlockID,
+    pub height: u64,
+    pub tx_id: TransactionID,
+    /// The other side of a transfer, `None` for a mint or a burn.
+    pub counterparty: Option<String>,
+    /// The asset of the balance, absent for the native asset. The fee of a transfer in another
+    /// asset has an entry of its own in the native asset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<AssetID>,
+    /// Change of the balance of the account.
+    pub delta: i128,
+    /// Balance of the account right after the transaction.
+    pub balance: u64,
+}
+
+impl HistoryEntry {
+    /// The entries of an applied transaction, one per balance it touched.
+    fn of_transaction(block_id: &str, height: u64, applied: &AppliedTransaction) -> Vec<Self> {
+        applied
+            .changes
+            .iter()
+            .map(|change| HistoryEntry {
+                account: change.account.clone(),
+                block_id: block_id.to_string(),
+                height,
+                tx_id: applied.tx.tx_id().clone(),
+                counterparty: match &applied.tx {
+                    Transaction::Mint { .. } | Transaction::Burn { .. } => None,
+                    Transaction::Transfer { from, to, .. } => {
+                        Some(if &change.account == from { to } else { from }.clone())
+                    }
+                },
+                asset: change.asset.clone(),
+                delta: change.after as i128 - change.before as i128,
+                balance: change.after,
+            })
+            .collect()
+    }
+}
+
+/// Key of the entry at a position of the history of an account, padded so that the entries
+/// of an account sort by position.
+pub(crate) fn history_key(account: &str, position: u64) -> String {
+    format!("{}/{:020}", account, position)
+}
+
+/// Order in which a history page lists entries.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Oldest entries first.
+    Forward,
+    /// Newest entries first.
+    #[default]
+    Backward,
+}
+
+/// Position of the last entry of a page, from which the next page continues.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct HistoryCursor {
+    pub position: u64,
+    /// Block of the entry at `position`, used to detect that a reorg replaced it.
+    pub block_id: BlockID,
+}
+
+impl fmt::Display for HistoryCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.position, self.block_id)
+    }
+}
+
+impl FromStr for HistoryCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(cursor: &str) -> anyhow::Result<Self> {
+        let Some((position, block_id)) = cursor.split_once('@') else {
+            anyhow::bail!("malformed history cursor {}", cursor);
+        };
+        Ok(Self {
+            position: position.parse()?,
+            block_id: block_id.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for HistoryCursor {
+    type Error = anyhow::Error;
+
+    fn try_from(cursor: String) -> anyhow::Result<Self> {
+        cursor.parse()
+    }
+}
+
+impl From<HistoryCursor> for String {
+    fn from(cursor: HistoryCursor) -> Self {
+        cursor.to_string()
+    }
+}
+
+/// Which page of an account history to return.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+    /// Continue after this cursor, or start from the first entry in `direction`.
+    pub cursor: Option<HistoryCursor>,
+    pub limit: usize,
+    pub direction: Direction,
+}
+
+impl Default for HistoryQuery {
+    fn default() -> Self {
+        Self {
+            cursor: None,
+            limit: 100,
+            direction: Direction::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HistoryPage {
+    pub entries: Vec<HistoryEntry>,
+    /// Cursor of the next page, `None` on the last page.
+    pub next_cursor: Option<HistoryCursor>,
+}
+
+/// Keeps the history of each account on the canonical chain as a stack of entries: a block
+/// joining the chain pushes the entries of its transactions and a block leaving it pops them.
+#[derive(Debug, Default)]
+pub struct HistoryIndexer;
+
+impl Indexer for HistoryIndexer {
+    fn name(&self) -> &str {
+        "history"
+    }
+
+    fn transaction_applied(
+        &mut self,
+        ctx: &mut IndexContext,
+        block: &Block,
+        height: u64,
+        tx: &AppliedTransaction,
+    ) -> anyhow::Result<()> {
+        for entry in HistoryEntry::of_transaction(&block.block_id, height, tx) {
+            let mut account = ctx.account(&entry.account)?;
+            let key = history_key(&account.id, account.history_len);
+            ctx.put_record(Table::History, &key, &entry)?;
+            account.history_len += 1;
+            ctx.put_record(Table::Balances, &account.id, &account)?;
+        }
+        Ok(())
+    }
+
+    fn block_disconnected(
+        &mut self,
+        ctx: &mut IndexContext,
+        _block: &Block,
+        _height: u64,
+        transactions: &[AppliedTransaction],
+    ) -> anyhow::Result<()> {
+        for change in transactions
+            .iter()
+            .rev()
+            .flat_map(|tx| tx.changes.iter().rev())
+        {
+            let mut account = ctx.account(&change.account)?;
+            let Some(position) = account.history_len.checked_sub(1) else {
+                anyhow::bail!("history of {} is already empty", account.id);
+            };
+            ctx.delete_record(Table::History, &history_key(&account.id, position));
+            account.history_len = position;
+            ctx.put_record(Table::Balances, &account.id, &account)?;
+        }
+        Ok(())
+    }
+}
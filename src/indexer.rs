@@ -0,0 +1,242 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::state::NonceChange;
+use crate::store::{read, Storage, Table, WriteBatch};
+use crate::{Account, AssetID, Block, Transaction, NATIVE_ASSET};
+
+/// Balance of an account before and after a single transaction.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AccountChange {
+    pub account: String,
+    /// The asset of the balance, absent for the native asset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub asset: Option<AssetID>,
+    pub before: u64,
+    pub after: u64,
+}
+
+/// A transaction which applied when its block was connected, with the balances it touched.
+/// A transfer paying a fee also touches the native balances of the sender and the producer.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AppliedTransaction {
+    /// Position of the transaction in its block. The reward of the producer comes after the
+    /// transactions of the block.
+    pub index: usize,
+    pub tx: Transaction,
+    pub changes: Vec<AccountChange>,
+    /// Last nonce of the sender of a transfer with a nonce.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<NonceChange>,
+}
+
+/// Computes extra indexes as blocks join and leave the canonical chain.
+///
+/// Indexers run in registration order, after the built-in ones, within the ingest that moves
+/// the canonical chain. State written through the `IndexContext` is committed in the same
+/// transaction as the ingest, so it survives restarts and is dropped with a failed ingest.
+/// An error fails the whole ingest.
+pub trait Indexer: std::fmt::Debug + Send {
+    /// Unique name of the indexer, namespacing its state in the store.
+    fn name(&self) -> &str;
+
+    /// A block joined the canonical chain. Called before the transactions of the block.
+    fn block_connected(
+        &mut self,
+        _ctx: &mut IndexContext,
+        _block: &Block,
+        _height: u64,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// A transaction of a block joining the canonical chain was applied. The reward minted for
+    /// the producer of the block, if any, follows the transactions of the block.
+    fn transaction_applied(
+        &mut self,
+        _ctx: &mut IndexContext,
+        _block: &Block,
+        _height: u64,
+        _tx: &AppliedTransaction,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// A block left the canonical chain. `transactions` are the applied transactions of the
+    /// block, whose effects the indexer should undo.
+    fn block_disconnected(
+        &mut self,
+        _ctx: &mut IndexContext,
+        _block: &Block,
+        _height: u64,
+        _transactions: &[AppliedTransaction],
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// Access of an indexer to its own records, read and written as part of the ongoing ingest.
+pub struct IndexContext<'a> {
+    store: &'a dyn Storage,
+    batch: &'a mut WriteBatch,
+    name: String,
+}
+
+impl<'a> IndexContext<'a> {
+    pub(crate) fn new(store: &'a dyn Storage, batch: &'a mut WriteBatch, name: &str) -> Self {
+        Self {
+            store,
+            batch,
+            name: name.to_string(),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}/{}", self.name, key)
+    }
+
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> anyhow::Result<Option<T>> {
+        self.get_record(Table::Indexers, &self.key(key))
+    }
+
+    pub fn put<T: Serialize>(&mut self, key: &str, value: &T) -> anyhow::Result<()> {
+        let key = self.key(key);
+        self.put_record(Table::Indexers, &key, value)
+    }
+
+    pub fn delete(&mut self, key: &str) {
+        let key = self.key(key);
+        self.delete_record(Table::Indexers, &key);
+    }
+
+    /// Read a record of any table, for the built-in indexers.
+    pub(crate) fn get_record<T: DeserializeOwned>(
+        &self,
+        table: Table,
+        key: &str,
+    ) -> anyhow::Result<Option<T>> {
+        read(self.store, self.batch, table, key)
+    }
+
+    pub(crate) fn put_record<T: Serialize>(
+        &mut self,
+        table: Table,
+        key: &str,
+        value: &T,
+    ) -> anyhow::Result<()> {
+        self.batch.put(table, key, value)
+    }
+
+    pub(crate) fn delete_record(&mut self, table: Table, key: &str) {
+        self.batch.delete(table, key);
+    }
+
+    /// An account at the canonical tip.
+    pub(crate) fn account(&self, account: &str) -> anyhow::Result<Account> {
+        let stored = self.get_record(Table::Balances, account)?;
+        Ok(stored.unwrap_or_else(|| Account::empty(account)))
+    }
+}
+
+/// Applies the state transition of each transaction to the balances at the canonical tip,
+/// and counts the fees paid and earned by each account.
+#[derive(Debug, Default)]
+pub struct BalanceIndexer;
+
+impl BalanceIndexer {
+    fn set_balance(
+        ctx: &mut IndexContext,
+        change: &AccountChange,
+        balance: u64,
+    ) -> anyhow::Result<()> {
+        let mut account = ctx.account(&change.account)?;
+        account.set_balance_of(change.asset.as_deref().unwrap_or(NATIVE_ASSET), balance);
+        ctx.put_record(Table::Balances, &account.id, &account)?;
+        Ok(())
+    }
+
+    /// Count the fee of a transfer for its sender and the producer of its block, or take it
+    /// back when the block leaves the canonical chain.
+    fn count_fee(
+        ctx: &mut IndexContext,
+        block: &Block,
+        tx: &Transaction,
+        undo: bool,
+    ) -> anyhow::Result<()> {
+        let fee = tx.fee() as u128;
+        let Transaction::Transfer { from, .. } = tx else {
+            return Ok(());
+        };
+        if fee == 0 {
+            return Ok(());
+        }
+        let count = |total: u128| {
+            if undo {
+                total.saturating_sub(fee)
+            } else {
+                total + fee
+            }
+        };
+        let mut payer = ctx.account(from)?;
+        payer.fees_paid = count(payer.fees_paid);
+        ctx.put_record(Table::Balances, &payer.id, &payer)?;
+        if let Some(producer) = &block.producer {
+            let mut producer = ctx.account(producer)?;
+            producer.fees_earned = count(producer.fees_earned);
+            ctx.put_record(Table::Balances, &producer.id, &producer)?;
+        }
+        Ok(())
+    }
+
+    /// Move the last nonce of the sender of a transfer to one side of its change.
+    fn set_nonce(
+        ctx: &mut IndexContext,
+        tx: &AppliedTransaction,
+        side: impl Fn(&NonceChange) -> Option<u64>,
+    ) -> anyhow::Result<()> {
+        let (Some(change), Some((sender, _))) = (&tx.nonce, tx.tx.nonce()) else {
+            return Ok(());
+        };
+        let mut sender = ctx.account(sender)?;
+        sender.nonce = side(change);
+        ctx.put_record(Table::Balances, &sender.id, &sender)?;
+        Ok(())
+    }
+}
+
+impl Indexer for BalanceIndexer {
+    fn name(&self) -> &str {
+        "balances"
+    }
+
+    fn transaction_applied(
+        &mut self,
+        ctx: &mut IndexContext,
+        block: &Block,
+        _height: u64,
+        tx: &AppliedTransaction,
+    ) -> anyhow::Result<()> {
+        for change in &tx.changes {
+            Self::set_balance(ctx, change, change.after)?;
+        }
+        Self::count_fee(ctx, block, &tx.tx, false)?;
+        Self::set_nonce(ctx, tx, |change| Some(change.after))
+    }
+
+    fn block_disconnected(
+        &mut self,
+        ctx: &mut IndexContext,
+        block: &Block,
+        _height: u64,
+        transactions: &[AppliedTransaction],
+    ) -> anyhow::Result<()> {
+        for tx in transactions.iter().rev() {
+            for change in tx.changes.iter().rev() {
+                Self::set_balance(ctx, change, change.before)?;
+            }
+            Self::count_fee(ctx, block, &tx.tx, true)?;
+            Self::set_nonce(ctx, tx, |change| change.before)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{BlockID, TransactionID};
+
+/// Why a block or a transaction was rejected during ingestion.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error, serde::Serialize, serde::Deserialize)]
+pub enum IngestError {
+    #[error("parent {parent_id} of block {block_id} is unknown")]
+    UnknownParent {
+        block_id: BlockID,
+        parent_id: BlockID,
+    },
+    #[error("block {0} was already ingested")]
+    DuplicateBlock(BlockID),
+    #[error("transaction {0} was already included")]
+    DuplicateTransaction(TransactionID),
+    #[error("transaction {tx_id} spends {amount} from {account} whose balance is {balance}")]
+    InsufficientBalance {
+        tx_id: TransactionID,
+        account: String,
+        balance: u64,
+        amount: u64,
+    },
+    #[error("transaction {tx_id} overflows the balance of {account}")]
+    Overflow {
+        tx_id: TransactionID,
+        account: String,
+    },
+    #[error(
+        "transaction {tx_id} reuses nonce {nonce} of {account}, whose next nonce is {expected}"
+    )]
+    StaleNonce {
+        tx_id: TransactionID,
+        account: String,
+        nonce: u64,
+        expected: u64,
+    },
+    #[error("block {block_id} is malformed: {reason}")]
+    MalformedBlock { block_id: BlockID, reason: String },
+}
+
+impl IngestError {
+    /// Name of the variant, e.g. `DuplicateBlock`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IngestError::UnknownParent { .. } => "UnknownParent",
+            IngestError::DuplicateBlock(_) => "DuplicateBlock",
+            IngestError::DuplicateTransaction(_) => "DuplicateTransaction",
+            IngestError::InsufficientBalance { .. } => "InsufficientBalance",
+            IngestError::Overflow { .. } => "Overflow",
+            IngestError::StaleNonce { .. } => "StaleNonce",
+            IngestError::MalformedBlock { .. } => "MalformedBlock",
+        }
+    }
+}
+
+/// What to do with a block containing a transaction that cannot be applied.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ValidityPolicy {
+    /// Skip the invalid transaction and apply the rest of the block.
+    #[default]
+    SkipTransaction,
+    /// Reject the whole block.
+    RejectBlock,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum TxStatus {
+    Applied,
+    Rejected(IngestError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TxOutcome {
+    pub tx_id: TransactionID,
+    pub status: TxStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BlockStatus {
+    /// The block was added to the tree at the given height.
+    Connected { height: u64 },
+    /// The block waits in the orphan pool for its parent.
+    Pending { missing_parent: BlockID },
+    /// The block was rejected under `ValidityPolicy::RejectBlock`.
+    Rejected(IngestError),
+}
+
+/// A block refused by an ingest, kept to be drawn with the block tree until a later ingest
+/// of the same id succeeds.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct RejectedBlock {
+    pub block_id: BlockID,
+    pub parent_id: Option<BlockID>,
+    pub transactions: usize,
+    pub error: IngestError,
+}
+
+/// The outcome of ingesting a block.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct IngestReport {
+    pub block_id: BlockID,
+    pub status: BlockStatus,
+    /// Outcome of each transaction of a connected block, in block order.
+    pub transactions: Vec<TxOutcome>,
+    /// Reports of the orphan blocks that were waiting for this block and got reattached.
+    pub reattached: Vec<IngestReport>,
+}
+
+impl IngestReport {
+    pub fn rejected_transactions(&self) -> impl Iterator<Item = (&TransactionID, &IngestError)> {
+        self.transactions
+            .iter()
+            .filter_map(|outcome| match &outcome.status {
+                TxStatus::Applied => None,
+                TxStatus::Rejected(error) => Some((&outcome.tx_id, error)),
+            })
+    }
+}
@@ -2,34 +2,145 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod api;
 pub mod blocks;
+pub mod cli;
+pub mod events;
+pub mod fees;
+pub mod fork_choice;
+pub mod history;
+pub mod indexer;
+pub mod ingest;
+pub mod orphan;
+pub mod output;
+pub mod recovery;
+pub mod render;
+pub mod repl;
+pub mod rpc;
+pub mod scenario;
+pub mod state;
+pub mod store;
+pub mod stream;
 mod tests;
+pub mod tree;
+pub mod tx_index;
+pub mod ws;
 
 type TransactionID = String;
 type BlockID = String;
+type AssetID = String;
 
-use std::collections::HashMap;
+/// The asset of the transactions and balances which name none.
+pub const NATIVE_ASSET: &str = "native";
+
+use anyhow::Context;
+use events::{ChainEvent, EventBus, Subscriber, SubscriptionId};
+use fees::{reward_transaction, AccountFees, BlockFees, RewardSchedule};
+use fork_choice::ForkChoice;
+use history::{
+    history_key, Direction, HistoryCursor, HistoryEntry, HistoryIndexer, HistoryPage, HistoryQuery,
+};
+use indexer::{AccountChange, AppliedTransaction, BalanceIndexer, IndexContext, Indexer};
+use ingest::{
+    BlockStatus, IngestError, IngestReport, RejectedBlock, TxOutcome, TxStatus, ValidityPolicy,
+};
+use orphan::{OrphanEntry, OrphanPool, OrphanPoolConfig, PendingBlock};
+use state::{BlockState, NonceChange, StateDiff, Supply};
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
-use lmdb::{Database, DatabaseFlags, Environment, Transaction as DBTransaction, WriteFlags};
-use tempdir::TempDir;
+use store::{height_key, LmdbStorage, MemoryStorage, Storage, Table, WriteBatch};
+use tree::{BlockNode, BlockTree};
+use tx_index::{TxInclusion, TxLocation};
 
-const DB_NAME: &str = "my_db";
+/// Prefix of the databases of a service in its directory.
+pub const DB_NAME: &str = "my_db";
 const DIR_NAME: &str = "./test_database";
-const DATA_KEY: &str = "data_key";
+const TIP_KEY: &str = "tip";
+const ORPHAN_CLOCK_KEY: &str = "orphan_clock";
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Transaction {
     Mint {
         tx_id: TransactionID,
         to: String,
         amount: u64,
+        /// The asset minted, the native asset when absent.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        asset: Option<AssetID>,
     },
     Transfer {
         tx_id: TransactionID,
         from: String,
         to: String,
         amount: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        asset: Option<AssetID>,
+        /// Paid by the sender in the native asset to the producer of the block, or burned if
+        /// the block names none.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        fee: Option<u64>,
+        /// Sequence number among the transfers of the sender, strictly increasing along a
+        /// chain so that a signed transfer cannot be replayed. Transfers without one are not
+        /// ordered.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        nonce: Option<u64>,
     },
+    /// Destroy coins of an account, taking them out of the supply.
+    Burn {
+        tx_id: TransactionID,
+        from: String,
+        amount: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        asset: Option<AssetID>,
+    },
+}
+
+impl Transaction {
+    pub fn tx_id(&self) -> &TransactionID {
+        match self {
+            Transaction::Mint { tx_id, .. }
+            | Transaction::Transfer { tx_id, .. }
+            | Transaction::Burn { tx_id, .. } => tx_id,
+        }
+    }
+
+    /// The asset the transaction moves.
+    pub fn asset(&self) -> &str {
+        match self {
+            Transaction::Mint { asset, .. }
+            | Transaction::Transfer { asset, .. }
+            | Transaction::Burn { asset, .. } => asset.as_deref().unwrap_or(NATIVE_ASSET),
+        }
+    }
+
+    /// The fee the transaction pays, zero for all but transfers.
+    pub fn fee(&self) -> u64 {
+        match self {
+            Transaction::Transfer { fee, .. } => fee.unwrap_or(0),
+            Transaction::Mint { .. } | Transaction::Burn { .. } => 0,
+        }
+    }
+
+    /// The nonce of a transfer, with its sender.
+    pub fn nonce(&self) -> Option<(&String, u64)> {
+        match self {
+            Transaction::Transfer {
+                from,
+                nonce: Some(nonce),
+                ..
+            } => Some((from, *nonce)),
+            _ => None,
+        }
+    }
+
+    /// Accounts whose balance the transaction changes, in a fixed order.
+    pub fn accounts(&self) -> Vec<&String> {
+        match self {
+            Transaction::Mint { to, .. } => vec![to],
+            Transaction::Transfer { from, to, .. } => vec![from, to],
+            Transaction::Burn { from, .. } => vec![from],
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -37,217 +148,1169 @@ pub struct Block {
     pub block_id: BlockID,
     pub parent_id: Option<BlockID>,
     pub transactions: Vec<Transaction>,
+    /// Account collecting the fees of the block and its reward.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub producer: Option<String>,
 }
 
 pub trait Service {
     type Balance: PartialEq + Eq + std::fmt::Debug;
 
-    fn new() -> Self;
-    fn from_db(dir: &str, db_filename: &str) -> Self;
-    fn update_db(&mut self);
-    fn ingest_block(&mut self, block: &Block) -> anyhow::Result<()>;
-    fn get_balance(&self, account: &str) -> anyhow::Result<Self::Balance>;
+    fn new() -> anyhow::Result<Self>
+    where
+        Self: Sized;
+    fn from_db(dir: &str, db_filename: &str) -> anyhow::Result<Self>
+    where
+        Self: Sized;
+    fn update_db(&mut self) -> anyhow::Result<()>;
+    /// Ingest a block. Errors rejecting the whole block are `IngestError`s.
+    fn ingest_block(&mut self, block: &Block) -> anyhow::Result<IngestReport>;
+    /// Balance of an account in an asset, `NATIVE_ASSET` for the native one.
+    fn get_balance(&self, account: &str, asset: &str) -> anyhow::Result<Self::Balance>;
+    /// Balance of an account right after the given block was applied.
+    fn get_balance_at_block(
+        &self,
+        account: &str,
+        asset: &str,
+        block_id: &str,
+    ) -> anyhow::Result<Self::Balance>;
+    /// Balance of an account at the given height of the canonical chain.
+    fn get_balance_at_height(
+        &self,
+        account: &str,
+        asset: &str,
+        height: u64,
+    ) -> anyhow::Result<Self::Balance>;
+    /// Balance of an account at the tip of the fork ending with the given leaf block.
+    fn get_balance_on_fork(
+        &self,
+        account: &str,
+        asset: &str,
+        tip_id: &str,
+    ) -> anyhow::Result<Self::Balance>;
+    /// The non-zero balances of an account at the canonical tip, by asset.
+    fn get_portfolio(&self, account: &str) -> anyhow::Result<BTreeMap<AssetID, Self::Balance>>;
+    /// Coins of an asset minted and burned up to the canonical tip.
+    fn get_supply(&self, asset: &str) -> anyhow::Result<Supply>;
+    /// Coins of an asset minted and burned up to the given height of the canonical chain.
+    fn get_supply_at_height(&self, asset: &str, height: u64) -> anyhow::Result<Supply>;
+    /// Fees paid in a block and the reward of its producer.
+    fn get_block_fees(&self, block_id: &str) -> anyhow::Result<BlockFees>;
+    /// Fees an account paid and earned up to the canonical tip.
+    fn get_account_fees(&self, account: &str) -> anyhow::Result<AccountFees>;
+    /// The lowest nonce the next transfer of an account may use on the canonical chain.
+    fn get_next_nonce(&self, account: &str) -> anyhow::Result<u64>;
+    /// Blocks that arrived before their parent and are still waiting for it.
+    fn get_pending_blocks(&self) -> anyhow::Result<Vec<PendingBlock>>;
+    /// Connected blocks including a transaction, on any fork.
+    fn get_transaction(&self, tx_id: &str) -> anyhow::Result<Vec<TxInclusion>>;
+    /// A page of the transactions touching an account on the canonical chain.
+    fn get_account_history(
+        &self,
+        account: &str,
+        query: &HistoryQuery,
+    ) -> anyhow::Result<HistoryPage>;
+    /// Receive the chain events of every following ingest.
+    fn subscribe(&mut self, subscriber: Box<dyn Subscriber>) -> SubscriptionId;
+    fn unsubscribe(&mut self, id: SubscriptionId) -> bool;
 }
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Account {
     pub id: String,
+    /// Balance in the native asset.
     pub balance: u64,
+    /// Non-zero balances in the other assets.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub assets: BTreeMap<AssetID, u64>,
+    /// Number of entries in the history of the account on the canonical chain.
+    #[serde(default)]
+    pub history_len: u64,
+    /// Fees paid by the transfers of the account on the canonical chain.
+    #[serde(default)]
+    pub fees_paid: u128,
+    /// Fees of the canonical blocks the account produced.
+    #[serde(default)]
+    pub fees_earned: u128,
+    /// Last nonce used by the account on the canonical chain.
+    #[serde(default)]
+    pub nonce: Option<u64>,
+}
+
+impl Account {
+    fn empty(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            balance: 0,
+            assets: BTreeMap::new(),
+            history_len: 0,
+            fees_paid: 0,
+            fees_earned: 0,
+            nonce: None,
+        }
+    }
+
+    pub fn balance_of(&self, asset: &str) -> u64 {
+        if asset == NATIVE_ASSET {
+            self.balance
+        } else {
+            self.assets.get(asset).copied().unwrap_or(0)
+        }
+    }
+
+    fn set_balance_of(&mut self, asset: &str, balance: u64) {
+        if asset == NATIVE_ASSET {
+            self.balance = balance;
+        } else if balance == 0 {
+            self.assets.remove(asset);
+        } else {
+            self.assets.insert(asset.to_string(), balance);
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct ServiceConfig {
+    pub orphan_pool: OrphanPoolConfig,
+    /// Rule used to select the canonical chain.
+    pub fork_choice: Box<dyn ForkChoice>,
+    pub validity_policy: ValidityPolicy,
+    /// Coins minted for the producer of each block.
+    pub block_reward: RewardSchedule,
+    /// Custom indexers, run after the built-in ones.
+    pub indexers: Vec<Box<dyn Indexer>>,
 }
 
-#[derive(Default, Debug, serde::Serialize, serde::Deserialize)]
-pub struct ServiceImpl {
-    pub states: Vec<HashMap<String, Account>>,
-    pub chains: Vec<Vec<Block>>,
-    pub leaf_blocks: HashMap<BlockID, usize>,
-    #[serde(skip_serializing)]
-    #[serde(skip_deserializing)]
-    db: Option<Database>,
-    #[serde(skip_serializing)]
-    #[serde(skip_deserializing)]
-    env: Option<Environment>,
+#[derive(Debug)]
+pub struct ServiceImpl<S: Storage> {
+    /// Positions of all connected blocks, indexed by block id.
+    pub tree: BlockTree,
+    /// Blocks whose parent has not arrived yet.
+    pub orphans: OrphanPool,
+    canonical_tip: Option<BlockID>,
+    fork_choice: Box<dyn ForkChoice>,
+    validity_policy: ValidityPolicy,
+    block_reward: RewardSchedule,
+    store: S,
+    /// Writes of the ongoing ingest, committed together by `update_db`.
+    batch: WriteBatch,
+    events: EventBus,
+    /// The built-in indexers followed by the custom ones.
+    indexers: Vec<Box<dyn Indexer>>,
+    /// Directory of the storage, if it is kept on disk.
     pub path: Option<String>,
 }
 
-impl Service for ServiceImpl {
+/// A service keeping its data in memory, for tests and short-lived runs.
+pub type MemoryService = ServiceImpl<MemoryStorage>;
+/// A service keeping its data in LMDB.
+pub type LmdbService = ServiceImpl<LmdbStorage>;
+
+impl<S: Storage> Service for ServiceImpl<S> {
     type Balance = u64;
 
-    fn new() -> Self {
-        let tmp = TempDir::new(DIR_NAME).expect("failed to open tmpdir");
-        let path = tmp.into_path();
-        // println!("Path: {:}", &*path.to_str().unwrap());
-        let mut builder = Environment::new();
-        builder.set_max_dbs(16);
-
-        let env = builder.open(&*path).expect("failed to open env");
-        let db = env
-            .create_db(Some(DB_NAME), DatabaseFlags::empty())
-            .expect("failed to open db");
-        let path_str = &*path.to_string_lossy();
-        Self {
-            states: Vec::new(),
-            chains: Vec::new(),
-            leaf_blocks: HashMap::new(),
-            db: Some(db),
-            env: Some(env),
-            path: Some(path_str.to_string()),
-        }
-    }
-
-    /// Deserialize the blockchain data from a database.
-    fn from_db(dir: &str, db_filename: &str) -> Self {
-        let path = Path::new(dir);
-
-        let mut builder = Environment::new();
-        builder.set_max_dbs(16);
-
-        let env = builder.open(path).expect("failed to open env");
-        let db = env
-            .create_db(Some(db_filename), DatabaseFlags::empty())
-            .expect("failed to open db");
-        let rotxn = env.begin_ro_txn().expect("can't begin ro txn");
-        let rbytes = rotxn.get(db, &DATA_KEY).expect("failed to get key");
-        let rstr = std::str::from_utf8(rbytes).expect("failed to parse read bytes");
-        let service: Self = serde_json::from_str(rstr).expect("failed to deserialize");
-        Self {
-            states: service.states,
-            chains: service.chains,
-            leaf_blocks: service.leaf_blocks,
-            db: Some(db),
-            env: Some(builder.open(path).expect("failed to open env")),
-            path: service.path,
-        }
-    }
-
-    /// Serialize and write all blockchains into the database.
-    fn update_db(&mut self) {
-        let wbytes = serde_json::to_string(self).expect("failed to serialize");
-        if let Some(env) = &self.env {
-            let mut rwtxn = env.begin_rw_txn().expect("can't begin rw txn");
-            if let Some(db) = self.db {
-                rwtxn
-                    .put(db, &DATA_KEY, &wbytes, WriteFlags::empty())
-                    .expect("put failed");
-                rwtxn.commit().expect("commit failed for rwtxn");
-            }
-        }
-    }
-
-    fn ingest_block(&mut self, _block: &Block) -> anyhow::Result<()> {
-        // Blockchain as a state machine, transition into a new state.
-        fn state_transition(state: &mut HashMap<String, Account>, tx: &Transaction) {
-            match tx {
-                Transaction::Mint {
-                    tx_id: _,
-                    to,
-                    amount,
-                } => {
-                    if let Some(to_account) = state.get_mut(to) {
-                        to_account.balance += amount;
-                    } else {
-                        let account = Account {
-                            id: to.clone(),
-                            balance: *amount,
-                        };
-                        state.insert(to.clone(), account);
-                    }
+    fn new() -> anyhow::Result<Self> {
+        Self::with_config(ServiceConfig::default())
+    }
+
+    /// Open the blockchain data stored in a database.
+    fn from_db(dir: &str, db_filename: &str) -> anyhow::Result<Self> {
+        Self::from_db_with_config(dir, db_filename, ServiceConfig::default())
+    }
+
+    /// Commit the writes of the last ingest into the database in one transaction.
+    fn update_db(&mut self) -> anyhow::Result<()> {
+        let batch = std::mem::take(&mut self.batch);
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.store
+            .commit(batch)
+            .context("failed to commit to the storage")
+    }
+
+    fn ingest_block(&mut self, _block: &Block) -> anyhow::Result<IngestReport> {
+        let result = match validate_block(_block) {
+            Err(error) => Err(error.into()),
+            Ok(()) => {
+                if self.tree.contains(&_block.block_id) || self.orphans.contains(&_block.block_id) {
+                    return Err(IngestError::DuplicateBlock(_block.block_id.clone()).into());
                 }
-                Transaction::Transfer {
-                    tx_id: _,
-                    from,
-                    to,
-                    amount,
-                } => {
-                    if let Some(from_account) = state.get_mut(from) {
-                        if from_account.balance >= *amount {
-                            from_account.balance -= amount;
-                        }
-                    }
-                    if let Some(to_account) = state.get_mut(to) {
-                        to_account.balance += amount;
-                    } else {
-                        let to_account = Account {
-                            id: to.clone(),
-                            balance: *amount,
-                        };
-                        state.insert(to.clone(), to_account);
-                    }
+                for expired in self.orphans.tick() {
+                    self.batch.delete(Table::Orphans, &expired);
                 }
+                self.batch
+                    .put(Table::Meta, ORPHAN_CLOCK_KEY, &self.orphans.clock())?;
+                self.attach_block(_block)
+            }
+        };
+        let result = match result {
+            Err(error) if !error.is::<IngestError>() => Err(error),
+            // a rejected block is remembered, and unless malformed still advances the orphan clock.
+            report => self
+                .record_outcome(_block, &report)
+                .and_then(|()| self.update_db())
+                .and(report),
+        };
+        match &result {
+            Err(error) if !error.is::<IngestError>() => {
+                // the storage holds the last consistent state, drop what it did not record.
+                self.batch = WriteBatch::default();
+                self.events.discard();
+                self.reload()
+                    .context("failed to reload the state after a storage fault")?;
             }
+            _ => self.events.publish(),
         }
+        result
+    }
+
+    fn get_balance(&self, _account: &str, asset: &str) -> anyhow::Result<Self::Balance> {
+        self.balance_at(_account, asset, self.canonical_tip().map(String::as_str))
+    }
 
-        if let Some(parent) = _block.parent_id.clone() {
-            // println!("parent id {}", parent);
-            if let Some(idx) = self.leaf_blocks.get(&parent) {
-                self.chains[*idx].push(_block.clone());
-                self.leaf_blocks.insert(_block.block_id.clone(), *idx);
-                self.leaf_blocks.remove(&parent);
-                let last_state = self.states.last_mut().unwrap();
-                for tx in &_block.transactions {
-                    state_transition(last_state, tx);
+    fn get_balance_at_block(
+        &self,
+        account: &str,
+        asset: &str,
+        block_id: &str,
+    ) -> anyhow::Result<Self::Balance> {
+        if !self.tree.contains(block_id) {
+            anyhow::bail!("unknown block {}", block_id);
+        }
+        self.balance_at(account, asset, Some(block_id))
+    }
+
+    fn get_balance_at_height(
+        &self,
+        account: &str,
+        asset: &str,
+        height: u64,
+    ) -> anyhow::Result<Self::Balance> {
+        match self.canonical_block_at(height)? {
+            Some(block_id) => self.balance_at(account, asset, Some(&block_id)),
+            None => anyhow::bail!("no canonical block at height {}", height),
+        }
+    }
+
+    fn get_balance_on_fork(
+        &self,
+        account: &str,
+        asset: &str,
+        tip_id: &str,
+    ) -> anyhow::Result<Self::Balance> {
+        if !self.tree.is_tip(tip_id) {
+            anyhow::bail!("block {} is not the tip of a fork", tip_id);
+        }
+        self.balance_at(account, asset, Some(tip_id))
+    }
+
+    fn get_portfolio(&self, account: &str) -> anyhow::Result<BTreeMap<AssetID, Self::Balance>> {
+        let account = self.account(account)?;
+        let mut portfolio = account.assets;
+        if account.balance > 0 {
+            portfolio.insert(NATIVE_ASSET.to_string(), account.balance);
+        }
+        Ok(portfolio)
+    }
+
+    fn get_supply(&self, asset: &str) -> anyhow::Result<Supply> {
+        let tip = self.canonical_tip().and_then(|tip| self.tree.get(tip));
+        Ok(tip.map(|tip| tip.supply_of(asset)).unwrap_or_default())
+    }
+
+    fn get_supply_at_height(&self, asset: &str, height: u64) -> anyhow::Result<Supply> {
+        let Some(block_id) = self.canonical_block_at(height)? else {
+            anyhow::bail!("no canonical block at height {}", height);
+        };
+        let Some(node) = self.tree.get(&block_id) else {
+            anyhow::bail!("canonical block {} is not in the tree", block_id);
+        };
+        Ok(node.supply_of(asset))
+    }
+
+    fn get_block_fees(&self, block_id: &str) -> anyhow::Result<BlockFees> {
+        match self.tree.get(block_id) {
+            Some(node) => Ok(node.fees),
+            None => anyhow::bail!("unknown block {}", block_id),
+        }
+    }
+
+    fn get_account_fees(&self, account: &str) -> anyhow::Result<AccountFees> {
+        let account = self.account(account)?;
+        Ok(AccountFees {
+            paid: account.fees_paid,
+            earned: account.fees_earned,
+        })
+    }
+
+    fn get_next_nonce(&self, account: &str) -> anyhow::Result<u64> {
+        let nonce = self.nonce_at(account, self.canonical_tip().map(String::as_str))?;
+        Ok(nonce.map_or(0, |nonce| nonce.saturating_add(1)))
+    }
+
+    fn get_pending_blocks(&self) -> anyhow::Result<Vec<PendingBlock>> {
+        anyhow::Ok(self.orphans.pending())
+    }
+
+    fn get_transaction(&self, tx_id: &str) -> anyhow::Result<Vec<TxInclusion>> {
+        let locations: Vec<TxLocation> = self.read(Table::Transactions, tx_id)?.unwrap_or_default();
+        let tip_height = self
+            .canonical_tip()
+            .and_then(|tip| self.tree.get(tip))
+            .map(|tip| tip.height);
+        let mut inclusions = Vec::with_capacity(locations.len());
+        for location in locations {
+            let Some(node) = self.tree.get(&location.block_id) else {
+                anyhow::bail!(
+                    "block {} of transaction {} is not in the tree",
+                    location.block_id,
+                    tx_id
+                );
+            };
+            let canonical =
+                self.canonical_block_at(node.height)?.as_ref() == Some(&location.block_id);
+            let confirmations = match tip_height {
+                Some(tip_height) if canonical => tip_height - node.height + 1,
+                _ => 0,
+            };
+            inclusions.push(TxInclusion {
+                block_id: location.block_id,
+                height: node.height,
+                index: location.index,
+                status: location.status,
+                canonical,
+                confirmations,
+            });
+        }
+        Ok(inclusions)
+    }
+
+    fn subscribe(&mut self, subscriber: Box<dyn Subscriber>) -> SubscriptionId {
+        self.events.subscribe(subscriber)
+    }
+
+    fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.events.unsubscribe(id)
+    }
+
+    fn get_account_history(
+        &self,
+        account: &str,
+        query: &HistoryQuery,
+    ) -> anyhow::Result<HistoryPage> {
+        anyhow::ensure!(query.limit > 0, "the history limit must be at least 1");
+        let len = self.account(account)?.history_len;
+        let mut position = match &query.cursor {
+            Some(cursor) => {
+                let entry: Option<HistoryEntry> =
+                    self.read(Table::History, &history_key(account, cursor.position))?;
+                if entry.map(|entry| entry.block_id).as_ref() != Some(&cursor.block_id) {
+                    anyhow::bail!("history cursor {} was invalidated by a reorg", cursor);
                 }
-            } else {
-                // no leaf contains parent, search the previous blocks
-                let current_chains = self.chains.clone();
-                for chain in current_chains {
-                    for (i, block) in chain.iter().enumerate() {
-                        // discard orphaned blocks.
-                        if parent == block.block_id {
-                            // fork from here.
-                            let mut fork = Vec::new();
-                            for block in &chain[..(i + 1)] {
-                                fork.push(block.clone());
+                match query.direction {
+                    Direction::Forward => cursor.position.checked_add(1),
+                    Direction::Backward => cursor.position.checked_sub(1),
+                }
+            }
+            None => match query.direction {
+                Direction::Forward => Some(0),
+                Direction::Backward => len.checked_sub(1),
+            },
+        };
+        let mut entries = Vec::new();
+        let mut last = None;
+        while let Some(current) = position.filter(|&position| position < len) {
+            if entries.len() == query.limit {
+                break;
+            }
+            let Some(entry) =
+                self.read::<HistoryEntry>(Table::History, &history_key(account, current))?
+            else {
+                anyhow::bail!("history entry {} of {} is missing", current, account);
+            };
+            last = Some(HistoryCursor {
+                position: current,
+                block_id: entry.block_id.clone(),
+            });
+            entries.push(entry);
+            position = match query.direction {
+                Direction::Forward => current.checked_add(1),
+                Direction::Backward => current.checked_sub(1),
+            };
+        }
+        let more = position.is_some_and(|position| position < len);
+        Ok(HistoryPage {
+            entries,
+            next_cursor: last.filter(|_| more),
+        })
+    }
+}
+
+impl<S: Storage> ServiceImpl<S> {
+    /// Create a service with a new temporary storage.
+    pub fn with_config(config: ServiceConfig) -> anyhow::Result<Self> {
+        let store = S::temporary().context("failed to create the storage")?;
+        Self::with_storage(store, config)
+    }
+
+    /// Open the blockchain data stored in a database with the given configuration.
+    pub fn from_db_with_config(
+        dir: &str,
+        db_filename: &str,
+        config: ServiceConfig,
+    ) -> anyhow::Result<Self> {
+        let store = S::open(Path::new(dir), db_filename)
+            .with_context(|| format!("failed to open storage {} in {}", db_filename, dir))?;
+        Self::with_storage(store, config)
+    }
+
+    /// Open the blockchain data kept in a storage. Only the block headers and metadata are
+    /// loaded, everything else is read when it is queried. Fails if any of them is corrupted,
+    /// see `recover` to salvage such a storage.
+    pub fn with_storage(store: S, config: ServiceConfig) -> anyhow::Result<Self> {
+        let (tree, orphans, canonical_tip) = Self::load(&store, config.orphan_pool)?;
+        Self::from_parts(store, config, tree, orphans, canonical_tip)
+    }
+
+    fn load(
+        store: &S,
+        orphan_pool: OrphanPoolConfig,
+    ) -> anyhow::Result<(BlockTree, OrphanPool, Option<BlockID>)> {
+        let mut children: HashMap<BlockID, Vec<BlockID>> = store
+            .scan(Table::Children)
+            .context("failed to read the children index")?
+            .into_iter()
+            .collect();
+        let nodes = store
+            .scan::<BlockNode>(Table::Headers)
+            .context("failed to read the block headers")?
+            .into_iter()
+            .map(|(id, mut node)| {
+                node.children = children.remove(&id).unwrap_or_default();
+                node
+            });
+        let tree = BlockTree::from_nodes(nodes);
+        let no_batch = WriteBatch::default();
+        let orphans = store
+            .scan::<OrphanEntry>(Table::Orphans)
+            .context("failed to read the orphan pool")?
+            .into_iter()
+            .map(|(_, entry)| entry);
+        let clock = store
+            .get(&no_batch, Table::Meta, ORPHAN_CLOCK_KEY)
+            .context("failed to read the orphan clock")?
+            .unwrap_or(0);
+        let orphans = OrphanPool::restore(orphan_pool, orphans, clock);
+        let canonical_tip: Option<BlockID> = store
+            .get(&no_batch, Table::Meta, TIP_KEY)
+            .context("failed to read the canonical tip")?;
+        if let Some(tip) = &canonical_tip {
+            if !tree.contains(tip) {
+                anyhow::bail!("canonical tip {} has no stored header", tip);
+            }
+        }
+        Ok((tree, orphans, canonical_tip))
+    }
+
+    fn from_parts(
+        store: S,
+        config: ServiceConfig,
+        mut tree: BlockTree,
+        orphans: OrphanPool,
+        canonical_tip: Option<BlockID>,
+    ) -> anyhow::Result<Self> {
+        let path = store.path().map(|path| path.to_string_lossy().to_string());
+        let mut indexers: Vec<Box<dyn Indexer>> =
+            vec![Box::new(BalanceIndexer), Box::new(HistoryIndexer)];
+        for indexer in config.indexers {
+            if indexers.iter().any(|other| other.name() == indexer.name()) {
+                anyhow::bail!("indexer name {} is already taken", indexer.name());
+            }
+            indexers.push(indexer);
+        }
+        if config.fork_choice.uses_subtree_sizes() {
+            tree.track_subtree_sizes();
+        }
+        Ok(Self {
+            tree,
+            orphans,
+            canonical_tip,
+            fork_choice: config.fork_choice,
+            validity_policy: config.validity_policy,
+            block_reward: config.block_reward,
+            store,
+            batch: WriteBatch::default(),
+            events: EventBus::default(),
+            indexers,
+            path,
+        })
+    }
+
+    /// Load the committed state again, discarding the changes of an ingest which failed.
+    fn reload(&mut self) -> anyhow::Result<()> {
+        let (mut tree, orphans, canonical_tip) = Self::load(&self.store, self.orphans.config())?;
+        if self.fork_choice.uses_subtree_sizes() {
+            tree.track_subtree_sizes();
+        }
+        self.tree = tree;
+        self.orphans = orphans;
+        self.canonical_tip = canonical_tip;
+        Ok(())
+    }
+
+    /// The tip of the canonical chain.
+    pub fn canonical_tip(&self) -> Option<&BlockID> {
+        self.canonical_tip.as_ref()
+    }
+
+    /// The id of the block at the given height of the canonical chain.
+    pub fn canonical_block_at(&self, height: u64) -> anyhow::Result<Option<BlockID>> {
+        self.read(Table::Canonical, &height_key(height))
+    }
+
+    pub fn get_block(&self, block_id: &str) -> anyhow::Result<Option<Block>> {
+        self.read(Table::Blocks, block_id)
+    }
+
+    /// The accounts changed by a block.
+    pub fn get_diff(&self, block_id: &str) -> anyhow::Result<Option<StateDiff>> {
+        self.read(Table::Diffs, block_id)
+    }
+
+    /// Every block held by the service: the connected blocks in the order they joined the
+    /// tree, then the orphans oldest first. Ingesting them in this order into an empty
+    /// service rebuilds the same tree, down to the ties between forks.
+    pub fn export_blocks(&self) -> anyhow::Result<Vec<Block>> {
+        let mut nodes: Vec<&BlockNode> = self.tree.iter().collect();
+        nodes.sort_by_key(|node| node.seq);
+        let mut blocks = Vec::with_capacity(nodes.len() + self.orphans.len());
+        for node in nodes {
+            let block = self
+                .get_block(&node.block_id)?
+                .with_context(|| format!("block {} is missing from the storage", node.block_id))?;
+            blocks.push(block);
+        }
+        for pending in self.orphans.pending() {
+            let block = self.get_orphan(&pending.block_id)?.with_context(|| {
+                format!("orphan {} is missing from the storage", pending.block_id)
+            })?;
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    /// A block waiting in the orphan pool.
+    pub fn get_orphan(&self, block_id: &str) -> anyhow::Result<Option<Block>> {
+        let entry: Option<OrphanEntry> = self.read(Table::Orphans, block_id)?;
+        Ok(entry.map(|entry| entry.block))
+    }
+
+    /// The blocks refused by an ingest and not accepted since, by block id.
+    pub fn get_rejected_blocks(&self) -> anyhow::Result<Vec<RejectedBlock>> {
+        let rejected = self.store.scan::<RejectedBlock>(Table::Rejected)?;
+        Ok(rejected.into_iter().map(|(_, block)| block).collect())
+    }
+
+    /// Forget a block, as if it had never been ingested: every record is deleted and every other
+    /// block is replayed in the order of `export_blocks`, so that its descendants wait in the
+    /// orphan pool again. The deletions and the replay are committed together, leaving the
+    /// storage as it was if the replay fails. Subscribers are not told about the replay.
+    pub fn forget_block(&mut self, block_id: &str) -> anyhow::Result<()> {
+        let blocks = self.export_blocks()?;
+        if !blocks.iter().any(|block| block.block_id == block_id) {
+            anyhow::bail!("unknown block {}", block_id);
+        }
+        let result = self.replay_without(&blocks, block_id);
+        self.events.discard();
+        if result.is_err() {
+            self.batch = WriteBatch::default();
+            self.reload()
+                .context("failed to reload the state after a failed replay")?;
+        }
+        result
+    }
+
+    fn replay_without(&mut self, blocks: &[Block], block_id: &str) -> anyhow::Result<()> {
+        // the rejected blocks were never part of the tree and stay rejected.
+        for table in Table::ALL
+            .into_iter()
+            .filter(|table| *table != Table::Rejected)
+        {
+            for (key, _) in self.store.scan_raw(table)? {
+                self.batch.delete(table, &key);
+            }
+        }
+        let clock = self.orphans.clock();
+        self.batch.put(Table::Meta, ORPHAN_CLOCK_KEY, &clock)?;
+        self.tree = BlockTree::default();
+        if self.fork_choice.uses_subtree_sizes() {
+            self.tree.track_subtree_sizes();
+        }
+        self.orphans = OrphanPool::restore(self.orphans.config(), Vec::new(), clock);
+        self.canonical_tip = None;
+        for block in blocks.iter().filter(|block| block.block_id != block_id) {
+            match self.attach_block(block) {
+                Ok(_) => {}
+                Err(error) if error.is::<IngestError>() => {}
+                Err(error) => return Err(error.context("failed to replay the blocks")),
+            }
+        }
+        self.update_db()
+    }
+
+    /// The accounts with a balance record at the canonical tip.
+    pub fn accounts(&self) -> anyhow::Result<Vec<String>> {
+        let accounts = self.store.scan_raw(Table::Balances)?;
+        Ok(accounts.into_iter().map(|(account, _)| account).collect())
+    }
+
+    fn read<T: serde::de::DeserializeOwned>(
+        &self,
+        table: Table,
+        key: &str,
+    ) -> anyhow::Result<Option<T>> {
+        self.store.get(&self.batch, table, key)
+    }
+
+    /// Balance of an account right after the given block, found in the diff of the closest
+    /// ancestor which changed it. `None` stands for the empty state before any genesis block.
+    fn balance_at(
+        &self,
+        account: &str,
+        asset: &str,
+        block_id: Option<&str>,
+    ) -> anyhow::Result<u64> {
+        let Some(block_id) = block_id else {
+            return Ok(0);
+        };
+        if self.canonical_tip().map(String::as_str) == Some(block_id) {
+            let account: Option<Account> = self.read(Table::Balances, account)?;
+            return Ok(account.map_or(0, |account| account.balance_of(asset)));
+        }
+        for node in self.tree.ancestors(block_id) {
+            if let Some(change) = self.get_diff(node.id())?.and_then(|diff| {
+                diff.changes(asset)
+                    .and_then(|changes| changes.get(account).copied())
+            }) {
+                return Ok(change.after);
+            }
+        }
+        Ok(0)
+    }
+
+    /// Last nonce of an account right after the given block, found like `balance_at`.
+    fn nonce_at(&self, account: &str, block_id: Option<&str>) -> anyhow::Result<Option<u64>> {
+        let Some(block_id) = block_id else {
+            return Ok(None);
+        };
+        if self.canonical_tip().map(String::as_str) == Some(block_id) {
+            return Ok(self.account(account)?.nonce);
+        }
+        for node in self.tree.ancestors(block_id) {
+            if let Some(change) = self
+                .get_diff(node.id())?
+                .and_then(|diff| diff.nonces.get(account).copied())
+            {
+                return Ok(Some(change.after));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether a transaction was applied by the given block or one of its ancestors. Only the
+    /// blocks of a fork are walked; below the fork point the canonical index tells whether a
+    /// block is an ancestor.
+    fn applied_on_chain(&self, tx_id: &str, block_id: Option<&str>) -> anyhow::Result<bool> {
+        let Some(block_id) = block_id else {
+            return Ok(false);
+        };
+        let locations: Vec<TxLocation> = self.read(Table::Transactions, tx_id)?.unwrap_or_default();
+        let applied: Vec<&BlockNode> = locations
+            .iter()
+            .filter(|location| location.status == TxStatus::Applied)
+            .filter_map(|location| self.tree.get(&location.block_id))
+            .collect();
+        if applied.is_empty() {
+            return Ok(false);
+        }
+        let mut fork_point = None;
+        for node in self.tree.ancestors(block_id) {
+            if self.canonical_block_at(node.height)?.as_ref() == Some(&node.block_id) {
+                fork_point = Some(node.height);
+                break;
+            }
+            if applied.iter().any(|other| other.block_id == node.block_id) {
+                return Ok(true);
+            }
+        }
+        let Some(fork_point) = fork_point else {
+            return Ok(false);
+        };
+        for node in applied {
+            if node.height <= fork_point
+                && self.canonical_block_at(node.height)?.as_ref() == Some(&node.block_id)
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Connect a block to the tree, or hold it in the orphan pool if its parent is unknown.
+    fn attach_block(&mut self, block: &Block) -> anyhow::Result<IngestReport> {
+        match &block.parent_id {
+            // hold orphaned blocks until their parent arrives.
+            Some(parent) if !self.tree.contains(parent) => {
+                let Some((entry, evicted)) = self.orphans.insert(block) else {
+                    return Err(IngestError::UnknownParent {
+                        block_id: block.block_id.clone(),
+                        parent_id: parent.clone(),
+                    }
+                    .into());
+                };
+                for evicted in evicted {
+                    self.batch.delete(Table::Orphans, &evicted);
+                }
+                self.batch.put(Table::Orphans, &block.block_id, &entry)?;
+                Ok(IngestReport {
+                    block_id: block.block_id.clone(),
+                    status: BlockStatus::Pending {
+                        missing_parent: parent.clone(),
+                    },
+                    transactions: Vec::new(),
+                    reattached: Vec::new(),
+                })
+            }
+            _ => {
+                let mut report = self.connect_block(block)?;
+                // reattach the descendants that were waiting for this block.
+                let mut connected = vec![block.block_id.clone()];
+                while let Some(id) = connected.pop() {
+                    for child in self.orphans.take_children(&id) {
+                        self.batch.delete(Table::Orphans, &child.block_id);
+                        match self.connect_block(&child) {
+                            Ok(child_report) => {
+                                connected.push(child.block_id);
+                                report.reattached.push(child_report);
                             }
-                            fork.push(_block.clone());
-                            // rewind all txs
-                            let mut new_state: HashMap<String, Account> = HashMap::new();
-                            for block in fork.clone() {
-                                for tx in &block.transactions {
-                                    state_transition(&mut new_state, tx);
-                                }
+                            Err(error) => {
+                                let error: IngestError = error.downcast()?;
+                                self.record_rejection(&child, &error)?;
+                                report.reattached.push(IngestReport {
+                                    block_id: child.block_id.clone(),
+                                    status: BlockStatus::Rejected(error),
+                                    transactions: Vec::new(),
+                                    reattached: Vec::new(),
+                                });
+                                self.reject_orphans_below(&child.block_id, &mut report.reattached)?;
                             }
-                            self.states.push(new_state);
-                            self.chains.push(fork);
-                            self.leaf_blocks
-                                .insert(_block.block_id.clone(), self.chains.len() - 1);
                         }
                     }
                 }
+                self.update_canonical_chain()?;
+                Ok(report)
             }
-        } else {
-            let new_chain = vec![_block.clone()];
-            self.chains.push(new_chain);
-            self.leaf_blocks
-                .insert(_block.block_id.clone(), self.chains.len() - 1);
-            let mut new_state: HashMap<String, Account> = HashMap::new();
-            for tx in &_block.transactions {
-                state_transition(&mut new_state, tx);
+        }
+    }
+
+    /// Drop the orphans descending from a rejected block, which can never connect, reporting
+    /// each of them as rejected.
+    fn reject_orphans_below(
+        &mut self,
+        block_id: &str,
+        reports: &mut Vec<IngestReport>,
+    ) -> anyhow::Result<()> {
+        let mut rejected = vec![block_id.to_string()];
+        while let Some(parent_id) = rejected.pop() {
+            for child in self.orphans.take_children(&parent_id) {
+                self.batch.delete(Table::Orphans, &child.block_id);
+                let error = IngestError::UnknownParent {
+                    block_id: child.block_id.clone(),
+                    parent_id: parent_id.clone(),
+                };
+                self.record_rejection(&child, &error)?;
+                reports.push(IngestReport {
+                    block_id: child.block_id.clone(),
+                    status: BlockStatus::Rejected(error),
+                    transactions: Vec::new(),
+                    reattached: Vec::new(),
+                });
+                rejected.push(child.block_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remember a block refused by an ingest, or forget an earlier refusal of a block it took.
+    fn record_outcome(
+        &mut self,
+        block: &Block,
+        report: &anyhow::Result<IngestReport>,
+    ) -> anyhow::Result<()> {
+        match report {
+            Ok(_) => {
+                self.batch.delete(Table::Rejected, &block.block_id);
+                Ok(())
             }
-            self.states.push(new_state);
+            Err(error) => match error.downcast_ref::<IngestError>() {
+                Some(error) => self.record_rejection(block, error),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Remember a refused block, unless it has no id or shares the id of a known block.
+    fn record_rejection(&mut self, block: &Block, error: &IngestError) -> anyhow::Result<()> {
+        if block.block_id.is_empty()
+            || self.tree.contains(&block.block_id)
+            || self.orphans.contains(&block.block_id)
+        {
+            return Ok(());
+        }
+        let rejected = RejectedBlock {
+            block_id: block.block_id.clone(),
+            parent_id: block.parent_id.clone(),
+            transactions: block.transactions.len(),
+            error: error.clone(),
+        };
+        self.batch.put(Table::Rejected, &block.block_id, &rejected)
+    }
+
+    /// Add a block whose parent is already in the tree and record the accounts it changes.
+    fn connect_block(&mut self, block: &Block) -> anyhow::Result<IngestReport> {
+        let parent = block.parent_id.as_deref();
+        let mut state = BlockState::new(
+            |account: &str, asset: &str| self.balance_at(account, asset, parent),
+            |account: &str| self.nonce_at(account, parent),
+        );
+        let height = parent
+            .and_then(|parent| self.tree.get(parent))
+            .map_or(0, |parent| parent.height + 1);
+        let mut transactions = Vec::new();
+        let mut applied = Vec::new();
+        let mut supply: BTreeMap<AssetID, Supply> = BTreeMap::new();
+        let mut fees = BlockFees::default();
+        let producer = block.producer.as_deref();
+        for (i, tx) in block.transactions.iter().enumerate() {
+            // a transaction applies once along a chain, a fork may include it again.
+            let duplicate = block.transactions[..i]
+                .iter()
+                .any(|earlier| earlier.tx_id() == tx.tx_id())
+                || self.applied_on_chain(tx.tx_id(), parent)?;
+            let result = if duplicate {
+                Err(IngestError::DuplicateTransaction(tx.tx_id().clone()).into())
+            } else {
+                state_transition(&mut state, tx, producer)
+            };
+            let status = match result {
+                Ok((changes, nonce)) => {
+                    supply.entry(tx.asset().to_string()).or_default().record(tx);
+                    let fee = tx.fee() as u128;
+                    fees.fees += fee;
+                    if producer.is_none() {
+                        supply.entry(NATIVE_ASSET.to_string()).or_default().burned += fee;
+                    }
+                    applied.push(AppliedTransaction {
+                        index: i,
+                        tx: tx.clone(),
+                        changes,
+                        nonce,
+                    });
+                    TxStatus::Applied
+                }
+                // storage faults are not the transaction's fault.
+                Err(error) => match error.downcast::<IngestError>()? {
+                    error if self.validity_policy == ValidityPolicy::RejectBlock => {
+                        return Err(error.into())
+                    }
+                    error => TxStatus::Rejected(error),
+                },
+            };
+            transactions.push(TxOutcome {
+                tx_id: tx.tx_id().clone(),
+                status,
+            });
+        }
+        let reward = self.block_reward.reward_at(height);
+        if let Some(producer) = producer.filter(|_| reward > 0) {
+            let tx = reward_transaction(block, producer, reward);
+            // a reward the producer cannot hold makes the whole block invalid.
+            let (changes, _) = state_transition(&mut state, &tx, None)?;
+            supply
+                .entry(NATIVE_ASSET.to_string())
+                .or_default()
+                .record(&tx);
+            fees.reward = reward;
+            applied.push(AppliedTransaction {
+                index: block.transactions.len(),
+                tx,
+                changes,
+                nonce: None,
+            });
         }
-        self.update_db();
-        // dbg!("Block ID: ", &_block.block_id);
-        // dbg!("States: ", &self.states);
-        // dbg!("Chains: ", &self.chains);
-        // dbg!("Leaf nodes: ", &self.leaf_blocks);
-        anyhow::Ok(())
+        let diff = state.diff;
+        let weight = self.fork_choice.block_weight(block);
+        let Some(node) = self.tree.insert(block, weight, &supply, fees) else {
+            anyhow::bail!("block {} cannot be connected", block.block_id);
+        };
+        self.batch.put(Table::Headers, &block.block_id, node)?;
+        self.batch.put(Table::Blocks, &block.block_id, block)?;
+        self.batch.put(Table::Diffs, &block.block_id, &diff)?;
+        self.batch.put(Table::Applied, &block.block_id, &applied)?;
+        self.batch
+            .put(Table::Children, &block.block_id, &Vec::<BlockID>::new())?;
+        if let Some(parent) = parent.and_then(|parent| self.tree.get(parent)) {
+            self.batch
+                .put(Table::Children, parent.id(), &parent.children)?;
+        }
+        for (index, outcome) in transactions.iter().enumerate() {
+            let mut locations: Vec<TxLocation> = self
+                .read(Table::Transactions, &outcome.tx_id)?
+                .unwrap_or_default();
+            locations.push(TxLocation {
+                block_id: block.block_id.clone(),
+                index,
+                status: outcome.status.clone(),
+            });
+            self.batch
+                .put(Table::Transactions, &outcome.tx_id, &locations)?;
+        }
+        Ok(IngestReport {
+            block_id: block.block_id.clone(),
+            status: BlockStatus::Connected { height },
+            transactions,
+            reattached: Vec::new(),
+        })
     }
 
-    fn get_balance(&self, _account: &str) -> anyhow::Result<Self::Balance> {
-        if self.states.is_empty() {
-            return anyhow::Ok(0);
+    /// Select the canonical tip with the fork-choice rule and move the account state to it.
+    fn update_canonical_chain(&mut self) -> anyhow::Result<()> {
+        let Some(new_tip) = self.fork_choice.select_tip(&self.tree) else {
+            return Ok(());
+        };
+        let new_tip = new_tip.id().clone();
+        if self.canonical_tip() == Some(&new_tip) {
+            return Ok(());
+        }
+        let (disconnect, connect) = self
+            .tree
+            .route(self.canonical_tip().map(String::as_str), &new_tip);
+        let depth = disconnect.len() as u64;
+        let fork_point = self
+            .tree
+            .get(connect.first().unwrap_or(&new_tip))
+            .and_then(|node| node.parent_id().cloned());
+        for block_id in disconnect {
+            let (block, height, applied) = self.connected_block(&block_id)?;
+            self.run_indexers(|indexer, ctx| {
+                indexer.block_disconnected(ctx, &block, height, &applied)
+            })?;
+            self.batch.delete(Table::Canonical, &height_key(height));
+            self.events
+                .emit(ChainEvent::BlockDisconnected { block_id, height });
         }
-        // find canonical chain
-        let mut max_len = 0;
-        let mut idx: usize = 0;
-        for (i, state) in self.states.iter().enumerate() {
-            if state.len() >= max_len {
-                max_len = state.len();
-                idx = i;
+        for block_id in connect {
+            let (block, height, applied) = self.connected_block(&block_id)?;
+            self.run_indexers(|indexer, ctx| {
+                indexer.block_connected(ctx, &block, height)?;
+                for tx in &applied {
+                    indexer.transaction_applied(ctx, &block, height, tx)?;
+                }
+                Ok(())
+            })?;
+            self.batch
+                .put(Table::Canonical, &height_key(height), &block_id)?;
+            self.events
+                .emit(ChainEvent::BlockConnected { block_id, height });
+        }
+        self.batch.put(Table::Meta, TIP_KEY, &new_tip)?;
+        let old_tip = self.canonical_tip.replace(new_tip.clone());
+        self.events.emit(ChainEvent::TipChanged {
+            old_tip: old_tip.clone(),
+            new_tip: new_tip.clone(),
+            height: self.tree.get(&new_tip).map_or(0, |node| node.height),
+        });
+        if let Some(old_tip) = old_tip.filter(|_| depth > 0) {
+            self.events.emit(ChainEvent::ReorgCompleted {
+                old_tip,
+                new_tip,
+                fork_point,
+                depth,
+            });
+        }
+        Ok(())
+    }
+
+    /// An account at the canonical tip.
+    fn account(&self, account: &str) -> anyhow::Result<Account> {
+        let stored = self.read(Table::Balances, account)?;
+        Ok(stored.unwrap_or_else(|| Account::empty(account)))
+    }
+
+    /// A block of the tree with its height and applied transactions.
+    fn connected_block(
+        &self,
+        block_id: &str,
+    ) -> anyhow::Result<(Block, u64, Vec<AppliedTransaction>)> {
+        let (Some(block), Some(node)) = (self.get_block(block_id)?, self.tree.get(block_id)) else {
+            anyhow::bail!("block {} is not connected", block_id);
+        };
+        let applied = self.read(Table::Applied, block_id)?.unwrap_or_default();
+        Ok((block, node.height, applied))
+    }
+
+    /// Run every indexer in order, each with its own context over the ongoing ingest.
+    fn run_indexers(
+        &mut self,
+        mut run: impl FnMut(&mut dyn Indexer, &mut IndexContext) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        for indexer in &mut self.indexers {
+            let name = indexer.name().to_string();
+            let mut ctx = IndexContext::new(&self.store, &mut self.batch, &name);
+            run(indexer.as_mut(), &mut ctx).with_context(|| format!("indexer {} failed", name))?;
+        }
+        Ok(())
+    }
+}
+
+/// Check the parts of a block that do not depend on the chain it extends.
+fn validate_block(block: &Block) -> Result<(), IngestError> {
+    let malformed = |reason: &str| IngestError::MalformedBlock {
+        block_id: block.block_id.clone(),
+        reason: reason.to_string(),
+    };
+    if block.block_id.is_empty() {
+        return Err(malformed("empty block id"));
+    }
+    if block.parent_id.as_ref() == Some(&block.block_id) {
+        return Err(malformed("block is its own parent"));
+    }
+    if block.transactions.iter().any(|tx| tx.tx_id().is_empty()) {
+        return Err(malformed("empty transaction id"));
+    }
+    Ok(())
+}
+
+/// Coins a transaction takes from and gives to one balance.
+struct Move<'a> {
+    account: &'a str,
+    asset: &'a str,
+    debit: u128,
+    credit: u128,
+}
+
+/// Add coins taken from or given to a balance, merged with the earlier moves of the balance.
+fn add_move<'a>(
+    moves: &mut Vec<Move<'a>>,
+    account: &'a str,
+    asset: &'a str,
+    debit: u64,
+    credit: u64,
+) {
+    match moves
+        .iter_mut()
+        .find(|other| other.account == account && other.asset == asset)
+    {
+        Some(other) => {
+            other.debit += debit as u128;
+            other.credit += credit as u128;
+        }
+        None => moves.push(Move {
+            account,
+            asset,
+            debit: debit as u128,
+            credit: credit as u128,
+        }),
+    }
+}
+
+// Blockchain as a state machine, transition into a new state.
+// A rejected transaction fails with an `IngestError` and leaves the state untouched, an applied
+// one returns the balances it changed in the order it touches them and the nonce it used.
+fn state_transition<F, N>(
+    state: &mut BlockState<F, N>,
+    tx: &Transaction,
+    producer: Option<&str>,
+) -> anyhow::Result<(Vec<AccountChange>, Option<NonceChange>)>
+where
+    F: Fn(&str, &str) -> anyhow::Result<u64>,
+    N: Fn(&str) -> anyhow::Result<Option<u64>>,
+{
+    let nonce = match tx.nonce() {
+        Some((account, nonce)) => {
+            let last = state.nonce(account)?;
+            if last.is_some_and(|last| nonce <= last) {
+                return Err(IngestError::StaleNonce {
+                    tx_id: tx.tx_id().clone(),
+                    account: account.clone(),
+                    nonce,
+                    expected: last.map_or(0, |last| last.saturating_add(1)),
+                }
+                .into());
+            }
+            Some((
+                account,
+                NonceChange {
+                    before: last,
+                    after: nonce,
+                },
+            ))
+        }
+        None => None,
+    };
+    let asset = tx.asset();
+    let mut moves = Vec::new();
+    match tx {
+        Transaction::Mint { to, amount, .. } => add_move(&mut moves, to, asset, 0, *amount),
+        Transaction::Transfer {
+            from, to, amount, ..
+        } => {
+            add_move(&mut moves, from, asset, *amount, 0);
+            add_move(&mut moves, to, asset, 0, *amount);
+            let fee = tx.fee();
+            if fee > 0 {
+                add_move(&mut moves, from, NATIVE_ASSET, fee, 0);
+                if let Some(producer) = producer {
+                    add_move(&mut moves, producer, NATIVE_ASSET, 0, fee);
+                }
             }
         }
-        let last_state = &self.states[idx];
-        let account = last_state.get(_account).unwrap();
-        anyhow::Ok(account.balance)
+        Transaction::Burn { from, amount, .. } => add_move(&mut moves, from, asset, *amount, 0),
+    }
+    let mut changes = Vec::with_capacity(moves.len());
+    for coins in &moves {
+        let before = state.balance(coins.account, coins.asset)?;
+        // the balance held before the transaction covers what it spends, whatever it receives.
+        if coins.debit > before as u128 {
+            return Err(IngestError::InsufficientBalance {
+                tx_id: tx.tx_id().clone(),
+                account: coins.account.to_string(),
+                balance: before,
+                amount: u64::try_from(coins.debit).unwrap_or(u64::MAX),
+            }
+            .into());
+        }
+        let after = u64::try_from(before as u128 - coins.debit + coins.credit).map_err(|_| {
+            IngestError::Overflow {
+                tx_id: tx.tx_id().clone(),
+                account: coins.account.to_string(),
+            }
+        })?;
+        changes.push(AccountChange {
+            account: coins.account.to_string(),
+            asset: Some(coins.asset)
+                .filter(|&asset| asset != NATIVE_ASSET)
+                .map(str::to_string),
+            before,
+            after,
+        });
+    }
+    for (coins, change) in moves.iter().zip(&changes) {
+        state.set_balance(coins.account, coins.asset, change.after)?;
+    }
+    if let Some((account, change)) = &nonce {
+        state.set_nonce(account, change.after)?;
     }
+    Ok((changes, nonce.map(|(_, change)| change)))
 }
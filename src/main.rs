@@ -1,56 +1,204 @@
 use std::io;
+use std::path::{Path, PathBuf};
 
-use blockchain_explorer::Block;
-use blockchain_explorer::Service;
+use anyhow::Context;
+
+use blockchain_explorer::api::open_data_dir;
+use blockchain_explorer::cli::{self, Invocation, USAGE};
+use blockchain_explorer::output::{write_events, Format};
+use blockchain_explorer::repl::Repl;
+use blockchain_explorer::scenario::{verify_scenario, Scenario};
+use blockchain_explorer::stream::Stream;
+use blockchain_explorer::{Service, ServiceConfig};
+
+/// Commands served by an in-memory service, listed before the data directory ones.
+const MEMORY_USAGE: &str = "\
+usage: blockchain-explorer [play] [--format json|ndjson|csv|table] < scenario.json
+       blockchain-explorer rpc       JSON-RPC requests on stdin, one per line
+       blockchain-explorer stream    NDJSON commands on stdin, one per line
+       blockchain-explorer verify <scenario.json>...";
+
+/// What the binary runs, parsed from its arguments.
+enum Mode {
+    /// Play out the scenario read from stdin, writing its events in a format.
+    Play(Format),
+    Rpc,
+    Stream,
+    Verify(Vec<String>),
+    /// Explore a data directory interactively, or an in-memory service without one.
+    Repl {
+        data_dir: Option<PathBuf>,
+        recover: bool,
+    },
+    /// A subcommand operating on a data directory.
+    Data(Invocation),
+}
+
+impl Mode {
+    /// Parse the arguments, whose options may come before or after the command.
+    fn parse(args: &[String]) -> anyhow::Result<Self> {
+        let mut name = None;
+        let mut rest = Vec::with_capacity(args.len());
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.strip_prefix("--") {
+                Some("recover" | "memory") => rest.push(arg.clone()),
+                Some(_) => {
+                    rest.push(arg.clone());
+                    rest.extend(args.next().cloned());
+                }
+                None if name.is_none() => name = Some(arg.as_str()),
+                None => rest.push(arg.clone()),
+            }
+        }
+        match name.unwrap_or("play") {
+            "play" => match rest.as_slice() {
+                [] => Ok(Mode::Play(Format::default())),
+                [option, format] if option == "--format" => Ok(Mode::Play(format.parse()?)),
+                _ => anyhow::bail!("play only takes --format"),
+            },
+            "rpc" | "stream" if !rest.is_empty() => {
+                anyhow::bail!("{} takes no argument", name.unwrap_or_default())
+            }
+            "rpc" => Ok(Mode::Rpc),
+            "stream" => Ok(Mode::Stream),
+            "verify" if rest.is_empty() => anyhow::bail!("verify takes scenario files"),
+            "verify" if rest.iter().any(|arg| arg.starts_with("--")) => {
+                anyhow::bail!("verify takes no option")
+            }
+            "verify" => Ok(Mode::Verify(rest)),
+            "repl" => {
+                let mut data_dir = Some(PathBuf::from(cli::DEFAULT_DATA_DIR));
+                let mut memory = false;
+                let mut recover = false;
+                let mut rest = rest.into_iter();
+                while let Some(arg) = rest.next() {
+                    match arg.as_str() {
+                        "--memory" => memory = true,
+                        "--recover" => recover = true,
+                        "--data-dir" => data_dir = rest.next().map(PathBuf::from),
+                        _ => anyhow::bail!("unknown argument {} of repl", arg),
+                    }
+                }
+                if memory {
+                    data_dir = None;
+                }
+                Ok(Mode::Repl { data_dir, recover })
+            }
+            name => Invocation::parse(name, &rest)?
+                .map(Mode::Data)
+                .with_context(|| format!("unknown command {}", name)),
+        }
+    }
+}
 
 fn main() {
-    let mut service = blockchain_explorer::ServiceImpl::new();
-    let scenario: Scenario =
-        serde_json::from_str(&io::read_to_string(io::stdin()).expect("Failed to read stdin"))
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mode = match Mode::parse(&args) {
+        Ok(mode) => mode,
+        Err(error) => {
+            eprintln!("{:#}", error);
+            eprintln!("{}", MEMORY_USAGE);
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let memory =
+        || blockchain_explorer::MemoryService::new().expect("Failed to create the service");
+    match mode {
+        Mode::Play(format) => {
+            let scenario: Scenario = serde_json::from_str(
+                &io::read_to_string(io::stdin()).expect("Failed to read stdin"),
+            )
             .expect("Failed to deserialize input");
-
-    for event in scenario.play_out(&mut service) {
-        println!("{:?}", event);
+            let events = scenario.play_out(&mut memory());
+            write_events(format, &events, io::stdout().lock()).expect("Failed to write the events");
+        }
+        Mode::Rpc => {
+            // one JSON-RPC request or batch per line of stdin, one response per line of stdout.
+            blockchain_explorer::rpc::serve_lines(&mut memory(), io::stdin().lock(), io::stdout())
+                .expect("Failed to serve JSON-RPC");
+        }
+        Mode::Stream => {
+            // one command per line of stdin, one result per line of stdout, in order.
+            Stream::new(&mut memory())
+                .serve_lines(io::stdin().lock(), io::stdout())
+                .expect("Failed to run the stream");
+        }
+        Mode::Verify(files) => {
+            // every file is verified, even after a failure.
+            let failures = files.iter().filter(|file| !verify(file)).count();
+            if failures > 0 {
+                std::process::exit(1);
+            }
+        }
+        Mode::Repl { data_dir, recover } => {
+            let result = match data_dir {
+                Some(data_dir) => repl(&data_dir, recover),
+                None => Repl::new(&mut memory()).run(),
+            };
+            if let Err(error) = result {
+                eprintln!("error: {:#}", error);
+                std::process::exit(1);
+            }
+        }
+        Mode::Data(invocation) => std::process::exit(run(invocation)),
     }
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct Scenario {
-    blocks: Vec<Block>,
-    queries: Vec<String>,
+/// Run the REPL against the service stored in a data directory.
+fn repl(data_dir: &Path, recover: bool) -> anyhow::Result<()> {
+    let (mut service, report) = open_data_dir(data_dir, ServiceConfig::default(), recover)?;
+    if let Some(report) = report.filter(|report| report.rebuilt) {
+        eprintln!("recovered the data directory: {:?}", report);
+    }
+    Repl::new(&mut service).run()
 }
 
-impl Scenario {
-    fn play_out<S: Service>(&self, service: &mut S) -> Vec<Event> {
-        let insertion_events: Vec<Event> = self
-            .blocks
-            .iter()
-            .map(|block| match service.ingest_block(block) {
-                Ok(()) => Event::BlockIngestion(block.block_id.to_string()),
-                Err(e) => Event::IngestionError(block.block_id.to_string(), e.to_string()),
-            })
-            .collect();
-
-        let query_events: Vec<Event> = self
-            .queries
-            .iter()
-            .map(|query| match service.get_balance(query) {
-                Ok(result) => Event::QueryResult(query.to_string(), format!("{:?}", result)),
-                Err(e) => Event::QueryError(query.to_string(), e.to_string()),
-            })
-            .collect();
-
-        [insertion_events, query_events]
-            .into_iter()
-            .flatten()
-            .collect()
+/// Run a subcommand against its data directory, returning the exit code.
+fn run(invocation: Invocation) -> i32 {
+    let result = open_data_dir(
+        &invocation.data_dir,
+        ServiceConfig::default(),
+        invocation.recover,
+    )
+    .and_then(|(mut service, report)| {
+        if let Some(report) = report.filter(|report| report.rebuilt) {
+            eprintln!("recovered the data directory: {:?}", report);
+        }
+        cli::run(&mut service, &invocation.command, io::stdout().lock())
+    });
+    match result {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(error) => {
+            eprintln!("error: {:#}", error);
+            1
+        }
     }
 }
 
-#[derive(Debug)]
-enum Event {
-    BlockIngestion(String),
-    IngestionError(String, String),
-    QueryResult(String, String),
-    QueryError(String, String),
+/// Check the expectations of a scenario file, printing its mismatches.
+fn verify(file: &str) -> bool {
+    let result = std::fs::read_to_string(file)
+        .map_err(anyhow::Error::from)
+        .and_then(|input| Ok(serde_json::from_str::<Scenario>(&input)?))
+        .and_then(|scenario| verify_scenario(&scenario));
+    match result {
+        Ok(mismatches) if mismatches.is_empty() => {
+            println!("PASS {}", file);
+            true
+        }
+        Ok(mismatches) => {
+            println!("FAIL {}", file);
+            for mismatch in mismatches {
+                println!("  {}", mismatch);
+            }
+            false
+        }
+        Err(error) => {
+            println!("ERROR {}: {:#}", file, error);
+            false
+        }
+    }
 }
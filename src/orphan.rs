@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use crate::{Block, BlockID};
+
+/// Limits of the orphan pool.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+pub struct OrphanPoolConfig {
+    /// Maximum number of blocks held in the pool. The oldest block is evicted when it is full.
+    pub max_blocks: usize,
+    /// Number of ingested blocks after which a block still waiting for its parent is discarded.
+    pub max_age: u64,
+}
+
+impl Default for OrphanPoolConfig {
+    fn default() -> Self {
+        Self {
+            max_blocks: 1024,
+            max_age: 1024,
+        }
+    }
+}
+
+/// A block waiting in the orphan pool for its parent to arrive.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PendingBlock {
+    pub block_id: BlockID,
+    pub missing_parent: BlockID,
+    /// Number of blocks ingested when this one arrived, including itself.
+    pub received_at: u64,
+}
+
+/// A block held in the orphan pool, as persisted in the store.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct OrphanEntry {
+    pub block: Block,
+    pub received_at: u64,
+}
+
+/// Blocks whose parent is unknown, keyed by the id of the missing parent.
+#[derive(Default, Debug)]
+pub struct OrphanPool {
+    config: OrphanPoolConfig,
+    by_parent: HashMap<BlockID, Vec<OrphanEntry>>,
+    /// Number of blocks ingested so far, used as the clock for expiring orphans.
+    clock: u64,
+}
+
+impl OrphanPool {
+    pub fn new(config: OrphanPoolConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// Restore a pool from its persisted entries and clock.
+    pub fn restore(
+        config: OrphanPoolConfig,
+        entries: impl IntoIterator<Item = OrphanEntry>,
+        clock: u64,
+    ) -> Self {
+        let mut pool = Self {
+            config,
+            clock,
+            ..Default::default()
+        };
+        for entry in entries {
+            // The stored clock may have been lost in recovery; never let it fall behind an entry.
+            pool.clock = pool.clock.max(entry.received_at);
+            if let Some(parent_id) = entry.block.parent_id.clone() {
+                pool.by_parent.entry(parent_id).or_default().push(entry);
+            }
+        }
+        for entries in pool.by_parent.values_mut() {
+            entries.sort_by_key(|entry| entry.received_at);
+        }
+        pool
+    }
+
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    pub fn config(&self) -> OrphanPoolConfig {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: OrphanPoolConfig) {
+        self.config = config;
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_parent.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_parent.is_empty()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.by_parent
+            .values()
+            .flatten()
+            .any(|entry| entry.block.block_id == id)
+    }
+
+    /// Advance the clock by one ingested block and discard expired orphans.
+    /// Returns the ids of the discarded blocks.
+    pub fn tick(&mut self) -> Vec<BlockID> {
+        self.clock += 1;
+        let (clock, max_age) = (self.clock, self.config.max_age);
+        let mut expired = Vec::new();
+        self.by_parent.retain(|_, entries| {
+            entries.retain(|entry| {
+                let keep = clock.saturating_sub(entry.received_at) <= max_age;
+                if !keep {
+                    expired.push(entry.block.block_id.clone());
+                }
+                keep
+            });
+            !entries.is_empty()
+        });
+        expired
+    }
+
+    /// Hold a block until its parent arrives. Returns the entry to persist and the ids of the
+    /// blocks evicted to make room for it, or `None` if the pool is disabled.
+    pub fn insert(&mut self, block: &Block) -> Option<(OrphanEntry, Vec<BlockID>)> {
+        let parent_id = block.parent_id.as_ref()?;
+        if self.config.max_blocks == 0 {
+            return None;
+        }
+        let mut evicted = Vec::new();
+        while self.len() >= self.config.max_blocks {
+            evicted.extend(self.evict_oldest());
+        }
+        let entry = OrphanEntry {
+            block: block.clone(),
+            received_at: self.clock,
+        };
+        self.by_parent
+            .entry(parent_id.clone())
+            .or_default()
+            .push(entry.clone());
+        Some((entry, evicted))
+    }
+
+    /// Remove and return the blocks waiting for the given parent.
+    pub fn take_children(&mut self, parent_id: &str) -> Vec<Block> {
+        self.by_parent
+            .remove(parent_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|entry| entry.block)
+            .collect()
+    }
+
+    /// The blocks in the pool, oldest first.
+    pub fn pending(&self) -> Vec<PendingBlock> {
+        let mut pending: Vec<PendingBlock> = self
+            .by_parent
+            .iter()
+            .flat_map(|(parent_id, entries)| {
+                entries.iter().map(move |entry| PendingBlock {
+                    block_id: entry.block.block_id.clone(),
+                    missing_parent: parent_id.clone(),
+                    received_at: entry.received_at,
+                })
+            })
+            .collect();
+        pending.sort_by(|a, b| (a.received_at, &a.block_id).cmp(&(b.received_at, &b.block_id)));
+        pending
+    }
+
+    fn evict_oldest(&mut self) -> Option<BlockID> {
+        let oldest = self
+            .by_parent
+            .iter()
+            .flat_map(|(parent_id, entries)| {
+                entries
+                    .iter()
+                    .enumerate()
+                    .map(move |(i, entry)| (entry.received_at, parent_id, i))
+            })
+            .min();
+        if let Some((_, parent_id, i)) = oldest {
+            let parent_id = parent_id.clone();
+            let entries = self.by_parent.get_mut(&parent_id).unwrap();
+            let entry = entries.remove(i);
+            if entries.is_empty() {
+                self.by_parent.remove(&parent_id);
+            }
+            return Some(entry.block.block_id);
+        }
+        None
+    }
+}
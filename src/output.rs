@@ -0,0 +1,279 @@
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::scenario::Event;
+
+/// How the binary prints the events of a scenario.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// One `Debug` line per event, meant for people rather than scripts.
+    #[default]
+    Debug,
+    /// A single JSON array of events.
+    Json,
+    /// One JSON event per line.
+    Ndjson,
+    /// A header and one row per event, with the columns of `Row`.
+    Csv,
+    /// The rows of `Csv` as an aligned text table, leaving out columns empty in every row.
+    Table,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> anyhow::Result<Self> {
+        match format {
+            "debug" => Ok(Format::Debug),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            "table" => Ok(Format::Table),
+            _ => anyhow::bail!(
+                "unknown format {}, expected json, ndjson, csv, table or debug",
+                format
+            ),
+        }
+    }
+}
+
+/// An event flattened into fixed columns. A history page takes one row per entry, the last
+/// one carrying the cursor of the next page.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Row {
+    pub event: &'static str,
+    pub block_id: Option<String>,
+    pub height: Option<u64>,
+    pub canonical_tip: Option<String>,
+    pub missing_parent: Option<String>,
+    pub account: Option<String>,
+    pub tx_id: Option<String>,
+    pub counterparty: Option<String>,
+    pub delta: Option<i128>,
+    pub balance: Option<u64>,
+    pub next_cursor: Option<String>,
+    pub error_kind: Option<String>,
+    pub error: Option<String>,
+    /// The asset of a history entry, empty for the native one. Last so that the columns
+    /// before it keep their positions.
+    pub asset: Option<String>,
+}
+
+const COLUMNS: [&str; 14] = [
+    "event",
+    "block_id",
+    "height",
+    "canonical_tip",
+    "missing_parent",
+    "account",
+    "tx_id",
+    "counterparty",
+    "delta",
+    "balance",
+    "next_cursor",
+    "error_kind",
+    "error",
+    "asset",
+];
+
+impl Row {
+    pub fn of_event(event: &Event) -> Vec<Row> {
+        let row = match event {
+            Event::BlockIngestion {
+                block_id,
+                height,
+                missing_parent,
+                canonical_tip,
+            } => Row {
+                event: "block_ingestion",
+                block_id: Some(block_id.clone()),
+                height: *height,
+                canonical_tip: canonical_tip.clone(),
+                missing_parent: missing_parent.clone(),
+                ..Default::default()
+            },
+            Event::IngestionError {
+                block_id,
+                error_kind,
+                error,
+            } => Row {
+                event: "ingestion_error",
+                block_id: Some(block_id.clone()),
+                error_kind: error_kind.clone(),
+                error: Some(error.clone()),
+                ..Default::default()
+            },
+            Event::TransactionRejected {
+                block_id,
+                tx_id,
+                error_kind,
+                error,
+            } => Row {
+                event: "transaction_rejected",
+                block_id: Some(block_id.clone()),
+                tx_id: Some(tx_id.clone()),
+                error_kind: Some(error_kind.clone()),
+                error: Some(error.clone()),
+                ..Default::default()
+            },
+            Event::QueryResult {
+                account,
+                balance,
+                block_id,
+                height,
+            } => Row {
+                event: "query_result",
+                block_id: block_id.clone(),
+                height: *height,
+                account: Some(account.clone()),
+                balance: Some(*balance),
+                ..Default::default()
+            },
+            Event::QueryError { account, error } => Row {
+                event: "query_error",
+                account: Some(account.clone()),
+                error: Some(error.clone()),
+                ..Default::default()
+            },
+            Event::HistoryResult { account, page } => {
+                let mut rows: Vec<Row> = page
+                    .entries
+                    .iter()
+                    .map(|entry| Row {
+                        event: "history_result",
+                        block_id: Some(entry.block_id.clone()),
+                        height: Some(entry.height),
+                        account: Some(account.clone()),
+                        tx_id: Some(entry.tx_id.clone()),
+                        counterparty: entry.counterparty.clone(),
+                        delta: Some(entry.delta),
+                        balance: Some(entry.balance),
+                        asset: entry.asset.clone(),
+                        ..Default::default()
+                    })
+                    .collect();
+                if rows.is_empty() {
+                    rows.push(Row {
+                        event: "history_result",
+                        account: Some(account.clone()),
+                        ..Default::default()
+                    });
+                }
+                if let Some(last) = rows.last_mut() {
+                    last.next_cursor = page.next_cursor.as_ref().map(|cursor| cursor.to_string());
+                }
+                return rows;
+            }
+            Event::HistoryError { account, error } => Row {
+                event: "history_error",
+                account: Some(account.clone()),
+                error: Some(error.clone()),
+                ..Default::default()
+            },
+        };
+        vec![row]
+    }
+
+    /// The cells in the order of `COLUMNS`, empty for `None`.
+    fn cells(&self) -> [String; 14] {
+        fn cell(value: &Option<impl fmt::Display>) -> String {
+            value.as_ref().map_or(String::new(), ToString::to_string)
+        }
+        [
+            self.event.to_string(),
+            cell(&self.block_id),
+            cell(&self.height),
+            cell(&self.canonical_tip),
+            cell(&self.missing_parent),
+            cell(&self.account),
+            cell(&self.tx_id),
+            cell(&self.counterparty),
+            cell(&self.delta),
+            cell(&self.balance),
+            cell(&self.next_cursor),
+            cell(&self.error_kind),
+            cell(&self.error),
+            cell(&self.asset),
+        ]
+    }
+}
+
+/// Print events in the given format.
+pub fn write_events(
+    format: Format,
+    events: &[Event],
+    mut output: impl Write,
+) -> anyhow::Result<()> {
+    match format {
+        Format::Debug => {
+            for event in events {
+                writeln!(output, "{:?}", event)?;
+            }
+        }
+        Format::Json => {
+            serde_json::to_writer_pretty(&mut output, events)?;
+            writeln!(output)?;
+        }
+        Format::Ndjson => {
+            for event in events {
+                serde_json::to_writer(&mut output, event)?;
+                writeln!(output)?;
+            }
+        }
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(output);
+            writer.write_record(COLUMNS)?;
+            for row in events.iter().flat_map(Row::of_event) {
+                writer.write_record(row.cells())?;
+            }
+            writer.flush()?;
+        }
+        Format::Table => write_table(events, output)?,
+    }
+    Ok(())
+}
+
+fn write_table(events: &[Event], mut output: impl Write) -> std::io::Result<()> {
+    let rows: Vec<[String; 14]> = events
+        .iter()
+        .flat_map(Row::of_event)
+        .map(|row| row.cells())
+        .collect();
+    let columns: Vec<usize> = (0..COLUMNS.len())
+        .filter(|&column| rows.iter().any(|row| !row[column].is_empty()))
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .map(|&column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .chain([COLUMNS[column].len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+    writeln!(
+        output,
+        "{}",
+        line(columns.iter().map(|&column| COLUMNS[column]).collect())
+    )?;
+    let rule: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+    writeln!(output, "{}", rule.join("  "))?;
+    for row in &rows {
+        writeln!(
+            output,
+            "{}",
+            line(columns.iter().map(|&column| row[column].as_str()).collect())
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,238 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+
+use crate::history::HistoryEntry;
+use crate::indexer::AppliedTransaction;
+use crate::ingest::{IngestError, RejectedBlock};
+use crate::orphan::OrphanEntry;
+use crate::state::StateDiff;
+use crate::store::{Storage, Table, WriteBatch};
+use crate::tree::{BlockNode, BlockTree};
+use crate::tx_index::TxLocation;
+use crate::{Account, Block, BlockID, Service, ServiceConfig, ServiceImpl, TIP_KEY};
+
+/// What `ServiceImpl::recover` found in a storage and what it did about it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Records which could not be decoded, by table and key. They are deleted.
+    pub corrupted: Vec<(Table, String)>,
+    /// Records which should exist next to another one but do not, by table and key.
+    pub missing: Vec<(Table, String)>,
+    /// Blocks found intact in the storage.
+    pub salvaged_blocks: usize,
+    /// Salvaged blocks still waiting in the orphan pool after the rebuild, since their
+    /// ancestry was lost.
+    pub pending_blocks: usize,
+    /// Whether the indexes were rebuilt from the stored blocks.
+    pub rebuilt: bool,
+}
+
+/// The decodable records of a storage.
+#[derive(Default)]
+struct Salvage {
+    blocks: Vec<Block>,
+    /// Arrival order of the blocks with an intact header.
+    seqs: HashMap<BlockID, u64>,
+    corrupted: Vec<(Table, String)>,
+    missing: Vec<(Table, String)>,
+}
+
+impl Salvage {
+    fn scan<S: Storage>(store: &S) -> anyhow::Result<Self> {
+        let mut salvage = Salvage::default();
+        let blocks: Vec<(String, Block)> = salvage.decode(store, Table::Blocks)?;
+        let headers: Vec<(String, BlockNode)> = salvage.decode(store, Table::Headers)?;
+        let diffs: Vec<(String, StateDiff)> = salvage.decode(store, Table::Diffs)?;
+        let children: Vec<(String, Vec<BlockID>)> = salvage.decode(store, Table::Children)?;
+        let canonical: Vec<(String, BlockID)> = salvage.decode(store, Table::Canonical)?;
+        salvage.decode::<S, Account>(store, Table::Balances)?;
+        salvage.decode::<S, OrphanEntry>(store, Table::Orphans)?;
+        salvage.decode::<S, Vec<TxLocation>>(store, Table::Transactions)?;
+        salvage.decode::<S, Vec<AppliedTransaction>>(store, Table::Applied)?;
+        salvage.decode::<S, HistoryEntry>(store, Table::History)?;
+        salvage.decode::<S, serde_json::Value>(store, Table::Indexers)?;
+        salvage.decode::<S, RejectedBlock>(store, Table::Rejected)?;
+        for (key, bytes) in store.scan_raw(Table::Meta)? {
+            let decoded = match key.as_str() {
+                TIP_KEY => serde_json::from_slice::<BlockID>(&bytes).is_ok(),
+                _ => serde_json::from_slice::<u64>(&bytes).is_ok(),
+            };
+            if !decoded {
+                salvage.corrupted.push((Table::Meta, key));
+            }
+        }
+
+        let headers: HashMap<String, BlockNode> = headers.into_iter().collect();
+        let diffs: HashSet<String> = diffs.into_iter().map(|(id, _)| id).collect();
+        for (id, _) in &blocks {
+            if !headers.contains_key(id) {
+                salvage.missing.push((Table::Headers, id.clone()));
+            }
+            if !diffs.contains(id) {
+                salvage.missing.push((Table::Diffs, id.clone()));
+            }
+        }
+        let block_ids: HashSet<&String> = blocks.iter().map(|(id, _)| id).collect();
+        for id in headers.keys().filter(|id| !block_ids.contains(id)) {
+            salvage.missing.push((Table::Blocks, id.clone()));
+        }
+        let indexed = children
+            .iter()
+            .map(|(id, _)| id)
+            .chain(canonical.iter().map(|(_, id)| id));
+        for id in indexed {
+            if !headers.contains_key(id) {
+                salvage.missing.push((Table::Headers, id.clone()));
+            }
+        }
+        let mut seen = HashSet::new();
+        salvage.missing.retain(|record| seen.insert(record.clone()));
+
+        salvage.seqs = headers
+            .into_iter()
+            .map(|(id, node)| (id, node.seq))
+            .collect();
+        salvage.blocks = blocks.into_iter().map(|(_, block)| block).collect();
+        Ok(salvage)
+    }
+
+    /// Decode every record of a table, recording those which fail.
+    fn decode<S: Storage, T: DeserializeOwned>(
+        &mut self,
+        store: &S,
+        table: Table,
+    ) -> anyhow::Result<Vec<(String, T)>> {
+        let mut records = Vec::new();
+        for (key, bytes) in store.scan_raw(table)? {
+            match serde_json::from_slice(&bytes) {
+                Ok(value) => records.push((key, value)),
+                Err(_) => self.corrupted.push((table, key)),
+            }
+        }
+        Ok(records)
+    }
+
+    /// The salvaged blocks with parents before children, in their original arrival order
+    /// where it is known.
+    fn replay_order(self) -> Vec<Block> {
+        let order = |block: &Block| {
+            let seq = self.seqs.get(&block.block_id).copied();
+            (seq.unwrap_or(u64::MAX), block.block_id.clone())
+        };
+        let ids: HashSet<&BlockID> = self.blocks.iter().map(|block| &block.block_id).collect();
+        let mut children: HashMap<&BlockID, Vec<&Block>> = HashMap::new();
+        let mut ready = BTreeSet::new();
+        let mut by_id = HashMap::new();
+        for block in &self.blocks {
+            by_id.insert(&block.block_id, block);
+            match &block.parent_id {
+                Some(parent) if ids.contains(parent) && parent != &block.block_id => {
+                    children.entry(parent).or_default().push(block)
+                }
+                _ => {
+                    ready.insert(order(block));
+                }
+            }
+        }
+        let mut replay = Vec::with_capacity(self.blocks.len());
+        let mut visited = HashSet::new();
+        while let Some(next) = ready.pop_first() {
+            let block = by_id[&next.1];
+            visited.insert(&block.block_id);
+            for child in children.remove(&block.block_id).unwrap_or_default() {
+                ready.insert(order(child));
+            }
+            replay.push(block.clone());
+        }
+        // blocks caught in a parent cycle can only wait in the orphan pool.
+        let mut rest: Vec<&Block> = self
+            .blocks
+            .iter()
+            .filter(|block| !visited.contains(&block.block_id))
+            .collect();
+        rest.sort_by_key(|block| order(block));
+        replay.extend(rest.into_iter().cloned());
+        replay
+    }
+}
+
+impl<S: Storage> ServiceImpl<S> {
+    /// Open a storage, rebuilding its indexes from the stored blocks if any record is corrupted
+    /// or missing. Corrupted records are deleted, blocks whose ancestry was lost wait in the
+    /// orphan pool.
+    pub fn recover(mut store: S, config: ServiceConfig) -> anyhow::Result<(Self, RecoveryReport)> {
+        let salvage = Salvage::scan(&store).context("failed to scan the storage")?;
+        let mut report = RecoveryReport {
+            corrupted: salvage.corrupted.clone(),
+            missing: salvage.missing.clone(),
+            salvaged_blocks: salvage.blocks.len(),
+            ..RecoveryReport::default()
+        };
+        if report.corrupted.is_empty() && report.missing.is_empty() {
+            if let Ok((tree, orphans, canonical_tip)) = Self::load(&store, config.orphan_pool) {
+                report.pending_blocks = orphans.len();
+                let service = Self::from_parts(store, config, tree, orphans, canonical_tip)?;
+                return Ok((service, report));
+            }
+        }
+
+        // drop the indexes and the corrupted records, keeping the blocks and the orphan pool.
+        let mut batch = WriteBatch::default();
+        for table in [
+            Table::Headers,
+            Table::Children,
+            Table::Diffs,
+            Table::Balances,
+            Table::Canonical,
+            Table::Transactions,
+            Table::Applied,
+            Table::History,
+            Table::Indexers,
+        ] {
+            for (key, _) in store.scan_raw(table)? {
+                batch.delete(table, &key);
+            }
+        }
+        batch.delete(Table::Meta, TIP_KEY);
+        for (table, key) in &report.corrupted {
+            batch.delete(*table, key);
+        }
+        store
+            .commit(batch)
+            .context("failed to clear the corrupted indexes")?;
+
+        let (_, orphans, _) = Self::load(&store, config.orphan_pool)?;
+        let mut service = Self::from_parts(store, config, BlockTree::default(), orphans, None)?;
+        for block in salvage.replay_order() {
+            // the replay must not age the orphan pool, so blocks skip `ingest_block`.
+            if service.orphans.contains(&block.block_id) {
+                continue;
+            }
+            match service.attach_block(&block) {
+                Ok(_) => {}
+                Err(error) if error.is::<IngestError>() => {}
+                Err(error) => return Err(error.context("failed to replay the stored blocks")),
+            }
+            service.update_db()?;
+            // nobody can have subscribed to the service being recovered yet.
+            service.events.discard();
+        }
+        report.pending_blocks = service.orphans.len();
+        report.rebuilt = true;
+        Ok((service, report))
+    }
+
+    /// Open the blockchain data stored in a database, recovering it if needed.
+    pub fn recover_db(
+        dir: &str,
+        db_filename: &str,
+        config: ServiceConfig,
+    ) -> anyhow::Result<(Self, RecoveryReport)> {
+        let store = S::open(Path::new(dir), db_filename)
+            .with_context(|| format!("failed to open storage {} in {}", db_filename, dir))?;
+        Self::recover(store, config)
+    }
+}
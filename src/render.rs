@@ -0,0 +1,380 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::str::FromStr;
+
+use crate::store::Storage;
+use crate::tree::BlockNode;
+use crate::{BlockID, Service, ServiceImpl};
+
+/// Where a block of a drawing stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeStatus {
+    /// In the block tree.
+    Connected,
+    /// In the orphan pool, waiting for its parent.
+    Pending,
+    /// The parent a pending block waits for, known only by its id.
+    Missing,
+    /// Refused by an ingest, see `ServiceImpl::get_rejected_blocks`.
+    Rejected,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TreeNode {
+    pub block_id: BlockID,
+    pub parent_id: Option<BlockID>,
+    pub status: NodeStatus,
+    /// `None` for the blocks outside the tree.
+    pub height: Option<u64>,
+    pub transactions: usize,
+    pub canonical: bool,
+    pub tip: bool,
+    /// Why a rejected block was refused.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A parent to child link. Links to pending and rejected blocks do not join them to the tree.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TreeEdge {
+    pub from: BlockID,
+    pub to: BlockID,
+    pub canonical: bool,
+}
+
+/// A snapshot of the block tree, the orphan pool and any rejected blocks, drawn as Graphviz
+/// DOT, ASCII or serialized as a JSON node and edge list. Nodes are listed in arrival order,
+/// so forks are drawn oldest first.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ForkTree {
+    pub canonical_tip: Option<BlockID>,
+    pub nodes: Vec<TreeNode>,
+    pub edges: Vec<TreeEdge>,
+    /// A block to point out, such as the focus of the REPL.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub highlight: Option<BlockID>,
+}
+
+/// Format of a drawing of the block tree.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TreeFormat {
+    #[default]
+    Ascii,
+    Dot,
+    Json,
+}
+
+impl FromStr for TreeFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(format: &str) -> anyhow::Result<Self> {
+        match format {
+            "ascii" => Ok(TreeFormat::Ascii),
+            "dot" => Ok(TreeFormat::Dot),
+            "json" => Ok(TreeFormat::Json),
+            _ => anyhow::bail!(
+                "unknown tree format {}, expected ascii, dot or json",
+                format
+            ),
+        }
+    }
+}
+
+impl ForkTree {
+    /// Snapshot the blocks of a service. With a `depth`, only the connected blocks at most
+    /// `depth` below the height of the canonical tip are kept, and the oldest kept block of
+    /// each fork is drawn as a root.
+    pub fn of_service<S: Storage>(
+        service: &ServiceImpl<S>,
+        depth: Option<u64>,
+    ) -> anyhow::Result<Self> {
+        let tree = &service.tree;
+        let canonical_tip = service.canonical_tip().cloned();
+        let canonical: HashSet<&BlockID> = canonical_tip
+            .iter()
+            .flat_map(|tip| tree.ancestors(tip))
+            .map(|node| &node.block_id)
+            .collect();
+        let tip_height = canonical_tip
+            .as_ref()
+            .and_then(|tip| tree.get(tip))
+            .map_or(0, |tip| tip.height);
+        let min_height = depth.map_or(0, |depth| tip_height.saturating_sub(depth));
+
+        let mut connected: Vec<&BlockNode> = tree
+            .iter()
+            .filter(|node| node.height >= min_height)
+            .collect();
+        connected.sort_by_key(|node| node.seq);
+        let mut nodes = Vec::new();
+        let mut edges = Vec::new();
+        for node in connected {
+            let block = service.get_block(&node.block_id)?;
+            nodes.push(TreeNode {
+                block_id: node.block_id.clone(),
+                parent_id: node.parent_id.clone(),
+                status: NodeStatus::Connected,
+                height: Some(node.height),
+                transactions: block.map_or(0, |block| block.transactions.len()),
+                canonical: canonical.contains(&node.block_id),
+                tip: tree.is_tip(&node.block_id),
+                error: None,
+            });
+            if let Some(parent) = node.parent_id.as_ref().filter(|_| node.height > min_height) {
+                edges.push(TreeEdge {
+                    from: parent.clone(),
+                    to: node.block_id.clone(),
+                    canonical: canonical.contains(&node.block_id),
+                });
+            }
+        }
+
+        let mut missing = BTreeSet::new();
+        for pending in service.get_pending_blocks()? {
+            let parent = pending.missing_parent;
+            nodes.push(TreeNode {
+                block_id: pending.block_id.clone(),
+                parent_id: Some(parent.clone()),
+                status: NodeStatus::Pending,
+                height: None,
+                transactions: service
+                    .get_orphan(&pending.block_id)?
+                    .map_or(0, |block| block.transactions.len()),
+                canonical: false,
+                tip: false,
+                error: None,
+            });
+            edges.push(TreeEdge {
+                from: parent.clone(),
+                to: pending.block_id,
+                canonical: false,
+            });
+            missing.insert(parent);
+        }
+        let mut drawn: HashSet<BlockID> = nodes.iter().map(|node| node.block_id.clone()).collect();
+        for parent in missing {
+            if !drawn.contains(&parent) {
+                drawn.insert(parent.clone());
+                nodes.push(TreeNode {
+                    block_id: parent,
+                    parent_id: None,
+                    status: NodeStatus::Missing,
+                    height: None,
+                    transactions: 0,
+                    canonical: false,
+                    tip: false,
+                    error: None,
+                });
+            }
+        }
+        // a rejected block is linked to its parent when the parent is drawn.
+        for rejected in service.get_rejected_blocks()? {
+            if let Some(parent) = rejected
+                .parent_id
+                .as_ref()
+                .filter(|parent| drawn.contains(*parent))
+            {
+                edges.push(TreeEdge {
+                    from: parent.clone(),
+                    to: rejected.block_id.clone(),
+                    canonical: false,
+                });
+            }
+            nodes.push(TreeNode {
+                block_id: rejected.block_id,
+                parent_id: rejected.parent_id,
+                status: NodeStatus::Rejected,
+                height: None,
+                transactions: rejected.transactions,
+                canonical: false,
+                tip: false,
+                error: Some(rejected.error.to_string()),
+            });
+        }
+        Ok(ForkTree {
+            canonical_tip,
+            nodes,
+            edges,
+            highlight: None,
+        })
+    }
+
+    /// The nodes by block id.
+    fn index(&self) -> HashMap<&str, &TreeNode> {
+        self.nodes
+            .iter()
+            .map(|node| (node.block_id.as_str(), node))
+            .collect()
+    }
+
+    pub fn render(&self, format: TreeFormat) -> String {
+        match format {
+            TreeFormat::Ascii => self.to_ascii(),
+            TreeFormat::Dot => self.to_dot(),
+            TreeFormat::Json => serde_json::to_string_pretty(self).unwrap_or_default(),
+        }
+    }
+
+    /// The connected blocks one per line below their parent, `*` marking the canonical chain,
+    /// followed by the pending and the rejected blocks. A chain stays in its column and only
+    /// the children of a fork are indented, under branches.
+    pub fn to_ascii(&self) -> String {
+        let index = self.index();
+        // the connected children of each block, in arrival order.
+        let mut children: HashMap<&str, Vec<&TreeNode>> = HashMap::new();
+        for edge in &self.edges {
+            if let Some(child) = index
+                .get(edge.to.as_str())
+                .filter(|child| child.status == NodeStatus::Connected)
+            {
+                children.entry(edge.from.as_str()).or_default().push(child);
+            }
+        }
+        let linked: HashSet<&str> = self.edges.iter().map(|edge| edge.to.as_str()).collect();
+        let mut output = String::new();
+        let roots = self.nodes.iter().filter(|node| {
+            node.status == NodeStatus::Connected && !linked.contains(node.block_id.as_str())
+        });
+        for root in roots {
+            // a root with a parent is where a depth limit cut its fork.
+            let first = if root.parent_id.is_some() { "… " } else { "" };
+            self.draw(root, first, &children, &mut output);
+        }
+        for node in &self.nodes {
+            match (node.status, &node.parent_id, &node.error) {
+                (NodeStatus::Pending, Some(parent), _) => {
+                    let _ = writeln!(output, "{} pending, waiting for {}", node.block_id, parent);
+                }
+                (NodeStatus::Rejected, _, Some(error)) => {
+                    let _ = writeln!(output, "{} rejected: {}", node.block_id, error);
+                }
+                _ => {}
+            }
+        }
+        if output.is_empty() {
+            output.push_str("no blocks\n");
+        }
+        output.trim_end().to_string()
+    }
+
+    /// Draw a block and its descendants depth first, with an explicit stack so that long chains
+    /// do not exhaust the call stack. Each entry holds the prefix of the line of a block and the
+    /// prefix of the lines below it.
+    fn draw(
+        &self,
+        root: &TreeNode,
+        first: &str,
+        children: &HashMap<&str, Vec<&TreeNode>>,
+        output: &mut String,
+    ) {
+        let indent = " ".repeat(first.chars().count());
+        let mut stack = vec![(root, first.to_string(), indent)];
+        while let Some((node, first, rest)) = stack.pop() {
+            output.push_str(&first);
+            output.push_str(&node.block_id);
+            if node.canonical {
+                output.push_str(" *");
+            }
+            if self.highlight.as_ref() == Some(&node.block_id) {
+                output.push_str(" <- focus");
+            }
+            output.push('\n');
+            match children.get(node.block_id.as_str()).map(Vec::as_slice) {
+                None | Some([]) => {}
+                Some([child]) => stack.push((child, rest.clone(), rest)),
+                Some(forks) => {
+                    // pushed in reverse so that the oldest fork is drawn first.
+                    for (i, child) in forks.iter().enumerate().rev() {
+                        let (branch, indent) = if i + 1 == forks.len() {
+                            ("└─ ", "   ")
+                        } else {
+                            ("├─ ", "│  ")
+                        };
+                        stack.push((
+                            child,
+                            format!("{}{}", rest, branch),
+                            format!("{}{}", rest, indent),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    /// A Graphviz digraph: the canonical chain in bold blue with its tip filled, pending
+    /// blocks dashed under a dotted placeholder for their missing parent, and rejected blocks
+    /// filled in red.
+    pub fn to_dot(&self) -> String {
+        let index = self.index();
+        let mut output = String::from("digraph blocks {\n  node [shape=box];\n");
+        for node in &self.nodes {
+            // the `\n` added below are line breaks, only the id is escaped.
+            let mut label = escape(&node.block_id);
+            let style = match node.status {
+                NodeStatus::Connected => {
+                    let height = node.height.unwrap_or_default();
+                    label += &format!("\\nheight {}, {} txs", height, node.transactions);
+                    match (
+                        node.canonical,
+                        self.canonical_tip.as_ref() == Some(&node.block_id),
+                    ) {
+                        (true, true) => "style=\"bold,filled\", color=blue, fillcolor=lightblue",
+                        (true, false) => "style=bold, color=blue",
+                        _ => "",
+                    }
+                }
+                NodeStatus::Pending => {
+                    label += &format!("\\npending, {} txs", node.transactions);
+                    "style=dashed, color=orange"
+                }
+                NodeStatus::Missing => {
+                    label += "\\nmissing";
+                    "shape=ellipse, style=dotted, color=gray"
+                }
+                NodeStatus::Rejected => {
+                    label += "\\nrejected";
+                    "style=filled, color=red, fillcolor=mistyrose"
+                }
+            };
+            let mut attributes = format!("label=\"{}\"", label);
+            if !style.is_empty() {
+                attributes += ", ";
+                attributes += style;
+            }
+            if self.highlight.as_ref() == Some(&node.block_id) {
+                attributes += ", peripheries=2";
+            }
+            if let Some(error) = &node.error {
+                attributes += &format!(", tooltip=\"{}\"", escape(error));
+            }
+            let _ = writeln!(output, "  \"{}\" [{}];", escape(&node.block_id), attributes);
+        }
+        for edge in &self.edges {
+            let style = match index.get(edge.to.as_str()).map(|node| node.status) {
+                _ if edge.canonical => " [style=bold, color=blue]",
+                Some(NodeStatus::Pending) => " [style=dashed, color=orange]",
+                Some(NodeStatus::Rejected) => " [style=dotted, color=red]",
+                _ => "",
+            };
+            let _ = writeln!(
+                output,
+                "  \"{}\" -> \"{}\"{};",
+                escape(&edge.from),
+                escape(&edge.to),
+                style
+            );
+        }
+        output.push_str("}\n");
+        output
+    }
+}
+
+/// Escape a string for a quoted DOT id or label: backslashes first, so that the ones added
+/// for quotes and line breaks are kept.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
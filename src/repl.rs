@@ -0,0 +1,518 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+
+use anyhow::Context as _;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::ingest::{BlockStatus, IngestReport};
+use crate::render::ForkTree;
+use crate::store::Storage;
+use crate::tree::BlockNode;
+use crate::{Block, BlockID, Service, ServiceImpl, Transaction, NATIVE_ASSET};
+
+/// The commands of the REPL, their arguments and what they do, as listed by `help`.
+const COMMANDS: [(&str, &str, &str); 10] = [
+    (
+        "ingest",
+        "<block> <parent|-> [mint:TO:AMOUNT | transfer:FROM:TO:AMOUNT[:FEE] | burn:FROM:AMOUNT]... \
+         | <block JSON>",
+        "ingest a block, naming its transactions <block>0, <block>1...",
+    ),
+    (
+        "balance",
+        "<account>[:ASSET] [block]",
+        "balance in the native asset or ASSET at the focused block or after a block",
+    ),
+    (
+        "tree",
+        "[depth]",
+        "the fork tree down to depth heights below the tip, * marks the canonical chain",
+    ),
+    (
+        "goto",
+        "[block]",
+        "focus a block, or the canonical tip again",
+    ),
+    (
+        "diff",
+        "[block]",
+        "balances and nonces changed by the focused block or a block",
+    ),
+    ("undo", "", "forget the last block ingested in this session"),
+    ("tips", "", "the tips of all forks"),
+    ("pending", "", "the blocks waiting for their parent"),
+    ("help", "", "this list"),
+    ("quit", "", "leave, also on end of input"),
+];
+
+/// An interactive session over a service. Commands are plain lines such as `balance Alice C`,
+/// see `help`. Queries look at the focused block, the canonical tip unless `goto` moved it.
+pub struct Repl<'a, S: Storage> {
+    service: &'a mut ServiceImpl<S>,
+    focus: Option<BlockID>,
+    /// Blocks ingested during the session, most recent last, for `undo`.
+    ingested: Vec<BlockID>,
+}
+
+impl<'a, S: Storage> Repl<'a, S> {
+    pub fn new(service: &'a mut ServiceImpl<S>) -> Self {
+        Self {
+            service,
+            focus: None,
+            ingested: Vec::new(),
+        }
+    }
+
+    /// Run one command line, returning what it prints.
+    pub fn execute(&mut self, line: &str) -> anyhow::Result<String> {
+        let line = line.trim();
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        match (command, args.as_slice()) {
+            ("ingest", _) if rest.starts_with('{') => {
+                let block = serde_json::from_str(rest).context("malformed block")?;
+                self.ingest(&block)
+            }
+            ("ingest", [block_id, parent_id, transactions @ ..]) => {
+                let block = parse_block(block_id, parent_id, transactions)?;
+                self.ingest(&block)
+            }
+            ("balance", [account]) => {
+                let block_id = self.focus().context("no block ingested yet")?;
+                self.balance(account, &block_id)
+            }
+            ("balance", [account, block_id]) => self.balance(account, block_id),
+            ("tree", []) => self.tree(None),
+            ("tree", [depth]) if depth.parse::<u64>().is_ok() => self.tree(depth.parse().ok()),
+            ("goto", []) => {
+                self.focus = None;
+                let tip = self.focus().context("no block ingested yet")?;
+                self.describe(&tip)
+            }
+            ("goto", [block_id]) => {
+                let description = self.describe(block_id)?;
+                self.focus = Some(block_id.to_string());
+                Ok(description)
+            }
+            ("diff", []) => {
+                let block_id = self.focus().context("no block ingested yet")?;
+                self.diff(&block_id)
+            }
+            ("diff", [block_id]) => self.diff(block_id),
+            ("undo", []) => self.undo(),
+            ("tips", []) => Ok(self.tips()),
+            ("pending", []) => self.pending(),
+            ("help", []) => Ok(help()),
+            _ if COMMANDS.iter().any(|(name, _, _)| *name == command) => {
+                let usage = format!("{} {}", command, usage(command));
+                anyhow::bail!("usage: {}", usage.trim_end())
+            }
+            _ => anyhow::bail!("unknown command {}, see help", command),
+        }
+    }
+
+    /// The focused block: the one `goto` moved to, or the canonical tip.
+    fn focus(&self) -> Option<BlockID> {
+        self.focus
+            .clone()
+            .or_else(|| self.service.canonical_tip().cloned())
+    }
+
+    fn ingest(&mut self, block: &Block) -> anyhow::Result<String> {
+        let report = self.service.ingest_block(block)?;
+        self.ingested.push(block.block_id.clone());
+        let mut output = String::new();
+        self.report(&report, &mut output);
+        match self.service.canonical_tip() {
+            Some(tip) => write!(output, "canonical tip {}", tip)?,
+            None => write!(output, "no canonical tip")?,
+        }
+        Ok(output)
+    }
+
+    fn report(&self, report: &IngestReport, output: &mut String) {
+        let line = match &report.status {
+            BlockStatus::Connected { height } => {
+                format!("{} connected at height {}", report.block_id, height)
+            }
+            BlockStatus::Pending { missing_parent } => {
+                format!(
+                    "{} pending, waiting for {}",
+                    report.block_id, missing_parent
+                )
+            }
+            BlockStatus::Rejected(error) => format!("{} rejected: {}", report.block_id, error),
+        };
+        output.push_str(&line);
+        output.push('\n');
+        for (tx_id, error) in report.rejected_transactions() {
+            output.push_str(&format!("  {} skipped: {}\n", tx_id, error));
+        }
+        for reattached in &report.reattached {
+            self.report(reattached, output);
+        }
+    }
+
+    /// The balance of `ACCOUNT` or `ACCOUNT:ASSET`, naming the asset unless it is the native one.
+    fn balance(&self, account: &str, block_id: &str) -> anyhow::Result<String> {
+        let (account, asset) = account.split_once(':').unwrap_or((account, NATIVE_ASSET));
+        let balance = self
+            .service
+            .get_balance_at_block(account, asset, block_id)?;
+        if asset == NATIVE_ASSET {
+            Ok(format!("{}: {} after {}", account, balance, block_id))
+        } else {
+            Ok(format!(
+                "{}: {} {} after {}",
+                account, balance, asset, block_id
+            ))
+        }
+    }
+
+    /// The fork tree, see `ForkTree::to_ascii`.
+    fn tree(&self, depth: Option<u64>) -> anyhow::Result<String> {
+        let mut tree = ForkTree::of_service(self.service, depth)?;
+        tree.highlight = self.focus.clone();
+        Ok(tree.to_ascii())
+    }
+
+    fn describe(&self, block_id: &str) -> anyhow::Result<String> {
+        let (Some(node), Some(block)) = (
+            self.service.tree.get(block_id),
+            self.service.get_block(block_id)?,
+        ) else {
+            anyhow::bail!("unknown block {}", block_id);
+        };
+        let canonical = self.service.canonical_block_at(node.height)?.as_deref() == Some(block_id);
+        let mut output = format!(
+            "{} at height {}, parent {}, {}",
+            block_id,
+            node.height,
+            node.parent_id.as_deref().unwrap_or("none"),
+            if canonical {
+                "canonical"
+            } else {
+                "off the canonical chain"
+            },
+        );
+        for tx in &block.transactions {
+            let coins = |amount: &u64| match tx.asset() {
+                NATIVE_ASSET => amount.to_string(),
+                asset => format!("{} {}", amount, asset),
+            };
+            let line = match tx {
+                Transaction::Mint {
+                    tx_id, to, amount, ..
+                } => format!("{}: mint {} to {}", tx_id, coins(amount), to),
+                Transaction::Transfer {
+                    tx_id,
+                    from,
+                    to,
+                    amount,
+                    fee,
+                    ..
+                } => format!(
+                    "{}: transfer {} from {} to {}{}",
+                    tx_id,
+                    coins(amount),
+                    from,
+                    to,
+                    fee.map_or(String::new(), |fee| format!(", fee {}", fee))
+                ),
+                Transaction::Burn {
+                    tx_id,
+                    from,
+                    amount,
+                    ..
+                } => format!("{}: burn {} from {}", tx_id, coins(amount), from),
+            };
+            output.push_str("\n  ");
+            output.push_str(&line);
+        }
+        Ok(output)
+    }
+
+    fn diff(&self, block_id: &str) -> anyhow::Result<String> {
+        let diff = self
+            .service
+            .get_diff(block_id)?
+            .with_context(|| format!("unknown block {}", block_id))?;
+        let accounts: BTreeSet<&String> = diff
+            .balances
+            .keys()
+            .chain(diff.assets.values().flat_map(BTreeMap::keys))
+            .chain(diff.nonces.keys())
+            .collect();
+        let mut output = format!("{} changed {} account(s)", block_id, accounts.len());
+        let native = diff
+            .balances
+            .iter()
+            .map(|(account, change)| (account.clone(), change));
+        let assets = diff.assets.iter().flat_map(|(asset, changes)| {
+            changes
+                .iter()
+                .map(move |(account, change)| (format!("{} {}", account, asset), change))
+        });
+        for (label, change) in native.chain(assets) {
+            let delta = change.after as i128 - change.before as i128;
+            write!(
+                output,
+                "\n  {}: {} -> {} ({:+})",
+                label, change.before, change.after, delta
+            )?;
+        }
+        for (account, change) in &diff.nonces {
+            let before = change
+                .before
+                .map_or("none".to_string(), |nonce| nonce.to_string());
+            write!(
+                output,
+                "\n  {} nonce: {} -> {}",
+                account, before, change.after
+            )?;
+        }
+        Ok(output)
+    }
+
+    fn undo(&mut self) -> anyhow::Result<String> {
+        let block_id = self
+            .ingested
+            .last()
+            .context("nothing ingested in this session")?
+            .clone();
+        self.service.forget_block(&block_id)?;
+        self.ingested.pop();
+        if self
+            .focus
+            .as_ref()
+            .is_some_and(|focus| !self.service.tree.contains(focus))
+        {
+            self.focus = None;
+        }
+        let tip = self.service.canonical_tip().map_or("none", String::as_str);
+        Ok(format!("forgot {}, canonical tip {}", block_id, tip))
+    }
+
+    fn tips(&self) -> String {
+        let mut tips: Vec<&BlockNode> = self.service.tree.tips().collect();
+        tips.sort_by_key(|tip| tip.seq);
+        if tips.is_empty() {
+            return "no blocks".to_string();
+        }
+        let canonical_tip = self.service.canonical_tip();
+        tips.iter()
+            .map(|tip| {
+                let marker = if Some(&tip.block_id) == canonical_tip {
+                    " *"
+                } else {
+                    ""
+                };
+                format!("{} at height {}{}", tip.block_id, tip.height, marker)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn pending(&self) -> anyhow::Result<String> {
+        let pending = self.service.get_pending_blocks()?;
+        if pending.is_empty() {
+            return Ok("no pending blocks".to_string());
+        }
+        Ok(pending
+            .iter()
+            .map(|pending| {
+                format!(
+                    "{} waiting for {}",
+                    pending.block_id, pending.missing_parent
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    /// Block ids and accounts offered by tab completion.
+    fn words(&self) -> anyhow::Result<Vec<String>> {
+        let mut words: Vec<String> = self
+            .service
+            .tree
+            .iter()
+            .map(|node| node.block_id.clone())
+            .collect();
+        words.extend(
+            self.service
+                .orphans
+                .pending()
+                .into_iter()
+                .map(|pending| pending.block_id),
+        );
+        words.extend(self.service.accounts()?);
+        words.sort();
+        words.dedup();
+        Ok(words)
+    }
+
+    fn prompt(&self) -> String {
+        match &self.focus {
+            Some(focus) => format!("explorer@{}> ", focus),
+            None => "explorer> ".to_string(),
+        }
+    }
+
+    /// Read commands from the terminal, with line history and tab completion, until `quit` or
+    /// the end of the input.
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        let mut editor: Editor<Completions, DefaultHistory> = Editor::new()?;
+        loop {
+            editor.set_helper(Some(Completions {
+                words: self.words()?,
+            }));
+            let line = match editor.readline(&self.prompt()) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => return Ok(()),
+                Err(error) => return Err(error.into()),
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            editor.add_history_entry(line)?;
+            if matches!(line, "quit" | "exit") {
+                return Ok(());
+            }
+            match self.execute(line) {
+                Ok(output) => println!("{}", output),
+                Err(error) => println!("error: {:#}", error),
+            }
+        }
+    }
+}
+
+/// Build a block from `ingest` arguments, `-` standing for no parent.
+fn parse_block(block_id: &str, parent_id: &str, transactions: &[&str]) -> anyhow::Result<Block> {
+    let transactions = transactions
+        .iter()
+        .enumerate()
+        .map(|(i, tx)| {
+            let tx_id = format!("{}{}", block_id, i);
+            let amount = |amount: &str| {
+                amount
+                    .parse()
+                    .with_context(|| format!("invalid amount {} in {}", amount, tx))
+            };
+            match tx.split(':').collect::<Vec<_>>().as_slice() {
+                ["mint", to, value] => Ok(Transaction::Mint {
+                    tx_id,
+                    to: to.to_string(),
+                    amount: amount(value)?,
+                    asset: None,
+                }),
+                ["transfer", from, to, value, fee @ ..] if fee.len() <= 1 => {
+                    Ok(Transaction::Transfer {
+                        tx_id,
+                        from: from.to_string(),
+                        to: to.to_string(),
+                        amount: amount(value)?,
+                        asset: None,
+                        fee: fee.first().copied().map(amount).transpose()?,
+                        nonce: None,
+                    })
+                }
+                ["burn", from, value] => Ok(Transaction::Burn {
+                    tx_id,
+                    from: from.to_string(),
+                    amount: amount(value)?,
+                    asset: None,
+                }),
+                _ => anyhow::bail!(
+                    "invalid transaction {}, expected mint:TO:AMOUNT, transfer:FROM:TO:AMOUNT[:FEE] or \
+                     burn:FROM:AMOUNT",
+                    tx
+                ),
+            }
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(Block {
+        block_id: block_id.to_string(),
+        parent_id: (parent_id != "-").then(|| parent_id.to_string()),
+        transactions,
+        producer: None,
+    })
+}
+
+fn usage(command: &str) -> &'static str {
+    COMMANDS
+        .iter()
+        .find(|(name, _, _)| *name == command)
+        .map_or("", |(_, args, _)| args)
+}
+
+fn help() -> String {
+    COMMANDS
+        .iter()
+        .map(|(name, args, description)| {
+            let usage = format!("{} {}", name, args);
+            format!("{}\n    {}", usage.trim_end(), description)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The completions of the word before `pos` and where it starts: command names at the start
+/// of the line, `words` after it.
+pub(crate) fn complete(words: &[String], line: &str, pos: usize) -> (usize, Vec<String>) {
+    // accounts also follow the colons of a transaction.
+    let start = line[..pos]
+        .rfind(|c: char| c.is_whitespace() || c == ':')
+        .map_or(0, |i| i + 1);
+    let prefix = &line[start..pos];
+    let candidates: Vec<String> = if start == 0 {
+        COMMANDS
+            .iter()
+            .map(|(name, _, _)| name.to_string())
+            .filter(|name| name.starts_with(prefix))
+            .collect()
+    } else {
+        words
+            .iter()
+            .filter(|word| word.starts_with(prefix))
+            .cloned()
+            .collect()
+    };
+    (start, candidates)
+}
+
+/// Completes the words of a line, see `complete`.
+struct Completions {
+    words: Vec<String>,
+}
+
+impl Completer for Completions {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(complete(&self.words, line, pos))
+    }
+}
+
+impl Hinter for Completions {
+    type Hint = String;
+}
+
+impl Highlighter for Completions {}
+
+impl Validator for Completions {}
+
+impl Helper for Completions {}
@@ -0,0 +1,296 @@
+use std::io::{BufRead, Write};
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+
+use crate::api::{self, ApiError, BalanceQuery};
+use crate::history::HistoryQuery;
+use crate::ingest::IngestError;
+use crate::store::Storage;
+use crate::{Block, BlockID, ServiceImpl, TransactionID};
+
+/// The request is not valid JSON.
+pub const PARSE_ERROR: i64 = -32700;
+/// The request is not a valid JSON-RPC 2.0 request object.
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// An unknown block, transaction, height or fork.
+pub const NOT_FOUND: i64 = -32001;
+/// A history cursor invalidated by a reorg.
+pub const STALE_CURSOR: i64 = -32002;
+/// Ingestion errors, one code per `IngestError` variant. The error is also in `data`.
+pub const UNKNOWN_PARENT: i64 = -32010;
+pub const DUPLICATE_BLOCK: i64 = -32011;
+pub const DUPLICATE_TRANSACTION: i64 = -32012;
+pub const INSUFFICIENT_BALANCE: i64 = -32013;
+pub const OVERFLOW: i64 = -32014;
+pub const MALFORMED_BLOCK: i64 = -32015;
+pub const STALE_NONCE: i64 = -32016;
+
+/// A JSON-RPC 2.0 error object.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+}
+
+impl From<ApiError> for RpcError {
+    fn from(error: ApiError) -> Self {
+        if let Some(rejection) = &error.ingest_error {
+            let code = match rejection {
+                IngestError::UnknownParent { .. } => UNKNOWN_PARENT,
+                IngestError::DuplicateBlock(_) => DUPLICATE_BLOCK,
+                IngestError::DuplicateTransaction(_) => DUPLICATE_TRANSACTION,
+                IngestError::InsufficientBalance { .. } => INSUFFICIENT_BALANCE,
+                IngestError::Overflow { .. } => OVERFLOW,
+                IngestError::MalformedBlock { .. } => MALFORMED_BLOCK,
+                IngestError::StaleNonce { .. } => STALE_NONCE,
+            };
+            return Self {
+                code,
+                message: error.message,
+                data: serde_json::to_value(rejection).ok(),
+            };
+        }
+        let code = match error.status {
+            400 => INVALID_PARAMS,
+            404 => NOT_FOUND,
+            409 => STALE_CURSOR,
+            _ => INTERNAL_ERROR,
+        };
+        Self::new(code, error.message)
+    }
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IngestParams {
+    block: Block,
+}
+
+#[derive(serde::Deserialize)]
+struct BalanceParams {
+    account: String,
+    #[serde(flatten)]
+    query: BalanceQuery,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AccountParams {
+    account: String,
+}
+
+#[derive(serde::Deserialize)]
+struct SupplyParams {
+    #[serde(flatten)]
+    query: BalanceQuery,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockParams {
+    block_id: BlockID,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TransactionParams {
+    tx_id: TransactionID,
+}
+
+#[derive(serde::Deserialize)]
+struct HistoryParams {
+    account: String,
+    #[serde(flatten)]
+    query: HistoryQuery,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TreeParams {
+    #[serde(default)]
+    depth: Option<u64>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoParams {}
+
+/// Decode the named parameters of a method. Omitted parameters and an empty array are an
+/// empty object; other arrays are rejected, since every method takes its parameters by name.
+fn params<T: DeserializeOwned>(params: Option<Value>) -> Result<T, RpcError> {
+    let params = match params {
+        None => Value::Object(Map::new()),
+        Some(Value::Array(values)) if values.is_empty() => Value::Object(Map::new()),
+        Some(Value::Object(params)) => Value::Object(params),
+        Some(_) => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                "positional parameters are not supported, name them in an object",
+            ))
+        }
+    };
+    serde_json::from_value(params).map_err(|error| RpcError::new(INVALID_PARAMS, error.to_string()))
+}
+
+/// Call a method of the explorer.
+pub fn call<S: Storage>(
+    service: &mut ServiceImpl<S>,
+    method: &str,
+    args: Option<Value>,
+) -> Result<Value, RpcError> {
+    let result = match method {
+        "explorer_ingestBlock" => {
+            let IngestParams { block } = params(args)?;
+            api::ingest(service, &block)
+        }
+        "explorer_getBalance" => {
+            let BalanceParams { account, query } = params(args)?;
+            api::balance(service, &account, &query)
+        }
+        "explorer_getBlock" => {
+            let BlockParams { block_id } = params(args)?;
+            api::block_info(service, &block_id)
+        }
+        "explorer_getTransaction" => {
+            let TransactionParams { tx_id } = params(args)?;
+            api::transaction(service, &tx_id)
+        }
+        "explorer_getAccountHistory" => {
+            let HistoryParams { account, query } = params(args)?;
+            api::history(service, &account, &query)
+        }
+        "explorer_getPendingBlocks" => {
+            let NoParams {} = params(args)?;
+            api::pending(service)
+        }
+        "explorer_getTips" => {
+            let NoParams {} = params(args)?;
+            api::tips(service)
+        }
+        "explorer_getForks" => {
+            let NoParams {} = params(args)?;
+            api::forks(service)
+        }
+        "explorer_getPortfolio" => {
+            let AccountParams { account } = params(args)?;
+            api::portfolio(service, &account)
+        }
+        "explorer_getAccountFees" => {
+            let AccountParams { account } = params(args)?;
+            api::account_fees(service, &account)
+        }
+        "explorer_getNextNonce" => {
+            let AccountParams { account } = params(args)?;
+            api::next_nonce(service, &account)
+        }
+        "explorer_getSupply" => {
+            let SupplyParams { query } = params(args)?;
+            api::supply(service, &query)
+        }
+        "explorer_getTree" => {
+            let TreeParams { depth } = params(args)?;
+            api::tree(service, depth)
+        }
+        _ => {
+            return Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {}", method),
+            ))
+        }
+    };
+    result.map_err(RpcError::from)
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(error) => json!({ "jsonrpc": "2.0", "error": error, "id": id }),
+    }
+}
+
+/// Answer a single request object, or nothing for a notification.
+fn handle_one<S: Storage>(service: &mut ServiceImpl<S>, request: Value) -> Option<Value> {
+    let Value::Object(mut request) = request else {
+        let error = RpcError::new(INVALID_REQUEST, "expected a request object");
+        return Some(response(Value::Null, Err(error)));
+    };
+    let id = request.remove("id");
+    let valid_id = matches!(
+        id,
+        None | Some(Value::Null | Value::String(_) | Value::Number(_))
+    );
+    let method = match request.remove("method") {
+        Some(Value::String(method)) => Some(method),
+        _ => None,
+    };
+    let args = request.remove("params");
+    let valid = valid_id
+        && request.remove("jsonrpc") == Some(json!("2.0"))
+        && request.is_empty()
+        && matches!(args, None | Some(Value::Array(_) | Value::Object(_)));
+    let (Some(method), true) = (method, valid) else {
+        let id = id.filter(|_| valid_id).unwrap_or(Value::Null);
+        let error = RpcError::new(INVALID_REQUEST, "invalid JSON-RPC 2.0 request");
+        return Some(response(id, Err(error)));
+    };
+    let result = call(service, &method, args);
+    id.map(|id| response(id, result))
+}
+
+/// Answer a JSON-RPC 2.0 request or batch, or nothing when it only holds notifications.
+pub fn handle<S: Storage>(service: &mut ServiceImpl<S>, request: &str) -> Option<Value> {
+    match serde_json::from_str(request) {
+        Err(error) => {
+            let error = RpcError::new(PARSE_ERROR, error.to_string());
+            Some(response(Value::Null, Err(error)))
+        }
+        Ok(Value::Array(batch)) if batch.is_empty() => {
+            let error = RpcError::new(INVALID_REQUEST, "empty batch");
+            Some(response(Value::Null, Err(error)))
+        }
+        Ok(Value::Array(batch)) => {
+            let responses: Vec<Value> = batch
+                .into_iter()
+                .filter_map(|request| handle_one(service, request))
+                .collect();
+            (!responses.is_empty()).then_some(Value::Array(responses))
+        }
+        Ok(request) => handle_one(service, request),
+    }
+}
+
+/// Serve JSON-RPC over line-delimited streams: one request or batch per input line, one
+/// response per output line. Blank lines are skipped.
+pub fn serve_lines<S: Storage>(
+    service: &mut ServiceImpl<S>,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> std::io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle(service, &line) {
+            writeln!(output, "{}", response)?;
+            output.flush()?;
+        }
+    }
+    Ok(())
+}
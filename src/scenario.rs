@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::history::{HistoryPage, HistoryQuery};
+use crate::ingest::{BlockStatus, IngestError, IngestReport};
+use crate::store::Storage;
+use crate::{Block, BlockID, Service, ServiceImpl, TransactionID, NATIVE_ASSET};
+
+/// Blocks to ingest in order, then balances and histories to query, with optional
+/// expectations checked by `verify`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Scenario {
+    pub blocks: Vec<ScenarioBlock>,
+    pub queries: Vec<String>,
+    #[serde(default)]
+    pub history: Vec<HistoryRequest>,
+    /// The state once every block is ingested.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<Expectation>,
+}
+
+/// A block of a scenario, with what its ingest should lead to.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ScenarioBlock {
+    #[serde(flatten)]
+    pub block: Block,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect: Option<Expectation>,
+}
+
+/// A page of the history of an account to print after the balances.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct HistoryRequest {
+    pub account: String,
+    #[serde(flatten)]
+    pub query: HistoryQuery,
+}
+
+/// The expected state after a block or at the end of a scenario. Fields left out are not
+/// checked, except that a block expecting no `error` must be ingested successfully.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Expectation {
+    /// Balances at the canonical tip.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub balances: BTreeMap<String, u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical_tip: Option<BlockID>,
+    /// Kind of the `IngestError` rejecting the block, e.g. `DuplicateBlock`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Transactions of the block skipped as invalid, in block order.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rejected: Option<Vec<TransactionID>>,
+}
+
+/// A difference between the expected and the actual outcome of a scenario.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// Where in the scenario, e.g. `after block C` or `at the end`.
+    pub step: String,
+    /// What was checked, e.g. `balance of Alice`.
+    pub check: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}: expected {}, got {}",
+            self.step, self.check, self.expected, self.actual
+        )
+    }
+}
+
+/// What playing out a scenario printed, one record per block ingested, transaction rejected
+/// and query run, tagged by `event`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A block was ingested, or reattached once its parent arrived. `height` is `None` while it
+    /// waits for `missing_parent`, and `canonical_tip` is the tip once the ingest, reattached
+    /// blocks included, is over.
+    BlockIngestion {
+        block_id: BlockID,
+        height: Option<u64>,
+        missing_parent: Option<BlockID>,
+        canonical_tip: Option<BlockID>,
+    },
+    /// `error_kind` is the `IngestError` variant, `None` for a storage fault.
+    IngestionError {
+        block_id: BlockID,
+        error_kind: Option<String>,
+        error: String,
+    },
+    TransactionRejected {
+        block_id: BlockID,
+        tx_id: TransactionID,
+        error_kind: String,
+        error: String,
+    },
+    /// The balance of an account at the canonical tip.
+    QueryResult {
+        account: String,
+        balance: u64,
+        block_id: Option<BlockID>,
+        height: Option<u64>,
+    },
+    QueryError {
+        account: String,
+        error: String,
+    },
+    HistoryResult {
+        account: String,
+        page: HistoryPage,
+    },
+    HistoryError {
+        account: String,
+        error: String,
+    },
+}
+
+impl Event {
+    /// The events of an ingest report and of the orphans it reattached, in order.
+    fn of_report<S: Storage>(service: &ServiceImpl<S>, report: &IngestReport) -> Vec<Event> {
+        let (height, missing_parent) = match &report.status {
+            BlockStatus::Connected { height } => (Some(*height), None),
+            BlockStatus::Pending { missing_parent } => (None, Some(missing_parent.clone())),
+            BlockStatus::Rejected(_) => (None, None),
+        };
+        let ingestion = match &report.status {
+            BlockStatus::Rejected(error) => Event::rejection(&report.block_id, error),
+            _ => Event::BlockIngestion {
+                block_id: report.block_id.clone(),
+                height,
+                missing_parent,
+                canonical_tip: service.canonical_tip().cloned(),
+            },
+        };
+        let rejections =
+            report
+                .rejected_transactions()
+                .map(|(tx_id, error)| Event::TransactionRejected {
+                    block_id: report.block_id.clone(),
+                    tx_id: tx_id.clone(),
+                    error_kind: error.kind().to_string(),
+                    error: error.to_string(),
+                });
+        let reattached = report
+            .reattached
+            .iter()
+            .flat_map(|reattached| Event::of_report(service, reattached));
+        std::iter::once(ingestion)
+            .chain(rejections)
+            .chain(reattached)
+            .collect()
+    }
+
+    fn rejection(block_id: &str, error: &IngestError) -> Event {
+        Event::IngestionError {
+            block_id: block_id.to_string(),
+            error_kind: Some(error.kind().to_string()),
+            error: error.to_string(),
+        }
+    }
+}
+
+impl Scenario {
+    pub fn play_out<S: Storage>(&self, service: &mut ServiceImpl<S>) -> Vec<Event> {
+        let mut events = Vec::new();
+        for entry in &self.blocks {
+            let block = &entry.block;
+            match service.ingest_block(block) {
+                Ok(report) => events.extend(Event::of_report(service, &report)),
+                Err(error) => events.push(match error.downcast_ref::<IngestError>() {
+                    Some(rejection) => Event::rejection(&block.block_id, rejection),
+                    None => Event::IngestionError {
+                        block_id: block.block_id.clone(),
+                        error_kind: None,
+                        error: error.to_string(),
+                    },
+                }),
+            }
+        }
+
+        let tip = service.canonical_tip().cloned();
+        let height = tip
+            .as_ref()
+            .and_then(|tip| service.tree.get(tip))
+            .map(|tip| tip.height);
+        for account in &self.queries {
+            events.push(match service.get_balance(account, NATIVE_ASSET) {
+                Ok(balance) => Event::QueryResult {
+                    account: account.clone(),
+                    balance,
+                    block_id: tip.clone(),
+                    height,
+                },
+                Err(e) => Event::QueryError {
+                    account: account.clone(),
+                    error: e.to_string(),
+                },
+            });
+        }
+
+        for request in &self.history {
+            let account = request.account.clone();
+            events.push(
+                match service.get_account_history(&request.account, &request.query) {
+                    Ok(page) => Event::HistoryResult { account, page },
+                    Err(e) => Event::HistoryError {
+                        account,
+                        error: e.to_string(),
+                    },
+                },
+            );
+        }
+        events
+    }
+
+    /// Ingest the blocks, checking the expectations after each block and at the end. Storage
+    /// faults abort the verification.
+    pub fn verify<S: Storage>(
+        &self,
+        service: &mut ServiceImpl<S>,
+    ) -> anyhow::Result<Vec<Mismatch>> {
+        let mut mismatches = Vec::new();
+        for entry in &self.blocks {
+            let result = service.ingest_block(&entry.block);
+            let Some(expect) = &entry.expect else {
+                match result {
+                    Err(error) if !error.is::<IngestError>() => return Err(error),
+                    _ => continue,
+                }
+            };
+            let step = format!("after block {}", entry.block.block_id);
+            let report = match result {
+                Ok(report) => Some(report),
+                Err(error) => match error.downcast::<IngestError>() {
+                    Ok(rejection) => {
+                        let actual = format!("{} ({})", rejection.kind(), rejection);
+                        if expect.error.as_deref() != Some(rejection.kind()) {
+                            let expected = expect.error.as_deref().unwrap_or("no error");
+                            mismatches.push(mismatch(&step, "error", expected, actual));
+                        }
+                        None
+                    }
+                    Err(error) => return Err(error),
+                },
+            };
+            if let Some(report) = &report {
+                if let Some(expected) = &expect.error {
+                    mismatches.push(mismatch(&step, "error", expected, "no error"));
+                }
+                if let Some(expected) = &expect.rejected {
+                    let actual: Vec<&TransactionID> = report
+                        .rejected_transactions()
+                        .map(|(tx_id, _)| tx_id)
+                        .collect();
+                    if actual.iter().copied().ne(expected.iter()) {
+                        mismatches.push(mismatch(
+                            &step,
+                            "rejected transactions",
+                            format!("{:?}", expected),
+                            format!("{:?}", actual),
+                        ));
+                    }
+                }
+            }
+            Self::check_state(service, &step, expect, &mut mismatches)?;
+        }
+        if let Some(expect) = &self.expect {
+            if expect.error.is_some() || expect.rejected.is_some() {
+                anyhow::bail!("errors and rejected transactions are only expected of a block");
+            }
+            Self::check_state(service, "at the end", expect, &mut mismatches)?;
+        }
+        Ok(mismatches)
+    }
+
+    fn check_state<S: Storage>(
+        service: &ServiceImpl<S>,
+        step: &str,
+        expect: &Expectation,
+        mismatches: &mut Vec<Mismatch>,
+    ) -> anyhow::Result<()> {
+        if let Some(expected) = &expect.canonical_tip {
+            let actual = service.canonical_tip().map_or("no tip", String::as_str);
+            if actual != expected {
+                mismatches.push(mismatch(step, "canonical tip", expected, actual));
+            }
+        }
+        for (account, expected) in &expect.balances {
+            let actual = service.get_balance(account, NATIVE_ASSET)?;
+            if actual != *expected {
+                let check = format!("balance of {}", account);
+                mismatches.push(mismatch(step, &check, expected, actual));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check a scenario against a fresh in-memory service.
+pub fn verify_scenario(scenario: &Scenario) -> anyhow::Result<Vec<Mismatch>> {
+    let mut service = crate::MemoryService::new()?;
+    scenario.verify(&mut service)
+}
+
+fn mismatch(
+    step: &str,
+    check: &str,
+    expected: impl fmt::Display,
+    actual: impl fmt::Display,
+) -> Mismatch {
+    Mismatch {
+        step: step.to_string(),
+        check: check.to_string(),
+        expected: expected.to_string(),
+        actual: actual.to_string(),
+    }
+}
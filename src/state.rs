@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::ops::Add;
+
+use crate::{AssetID, Transaction, NATIVE_ASSET};
+
+/// Balance of an account before and after a block was applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct BalanceChange {
+    pub before: u64,
+    pub after: u64,
+}
+
+/// Last nonce of an account before and after a block or a transaction, `None` before its
+/// first nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NonceChange {
+    pub before: Option<u64>,
+    pub after: u64,
+}
+
+/// The accounts changed by a single block. Applying `after` moves the state from the parent
+/// to the block, applying `before` moves it back.
+#[derive(Default, Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StateDiff {
+    /// Balances in the native asset.
+    pub balances: BTreeMap<String, BalanceChange>,
+    /// Balances in the other assets, by asset.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub assets: BTreeMap<AssetID, BTreeMap<String, BalanceChange>>,
+    /// Last nonces of the senders of transfers with a nonce.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub nonces: BTreeMap<String, NonceChange>,
+}
+
+impl StateDiff {
+    /// The balances of an asset changed by the block.
+    pub fn changes(&self, asset: &str) -> Option<&BTreeMap<String, BalanceChange>> {
+        if asset == NATIVE_ASSET {
+            Some(&self.balances)
+        } else {
+            self.assets.get(asset)
+        }
+    }
+
+    fn changes_mut(&mut self, asset: &str) -> &mut BTreeMap<String, BalanceChange> {
+        if asset == NATIVE_ASSET {
+            &mut self.balances
+        } else {
+            self.assets.entry(asset.to_string()).or_default()
+        }
+    }
+}
+
+/// Coins of an asset minted and burned by a block, or from the genesis block up to a block.
+/// Totals are wider than balances since coins may be minted again once burned.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Supply {
+    pub minted: u128,
+    pub burned: u128,
+}
+
+impl Supply {
+    /// Coins held by the accounts.
+    pub fn circulating(&self) -> u128 {
+        self.minted.saturating_sub(self.burned)
+    }
+
+    /// Count the coins an applied transaction creates or destroys.
+    pub fn record(&mut self, tx: &Transaction) {
+        match tx {
+            Transaction::Mint { amount, .. } => self.minted += *amount as u128,
+            Transaction::Burn { amount, .. } => self.burned += *amount as u128,
+            Transaction::Transfer { .. } => {}
+        }
+    }
+}
+
+impl Add for Supply {
+    type Output = Supply;
+
+    fn add(self, other: Supply) -> Supply {
+        Supply {
+            minted: self.minted + other.minted,
+            burned: self.burned + other.burned,
+        }
+    }
+}
+
+/// The state of a block being applied on top of its parent's state.
+pub struct BlockState<F, N>
+where
+    F: Fn(&str, &str) -> anyhow::Result<u64>,
+    N: Fn(&str) -> anyhow::Result<Option<u64>>,
+{
+    /// Balance of an account in an asset in the parent's state.
+    parent_balance: F,
+    /// Last nonce of an account in the parent's state.
+    parent_nonce: N,
+    pub diff: StateDiff,
+}
+
+impl<F, N> BlockState<F, N>
+where
+    F: Fn(&str, &str) -> anyhow::Result<u64>,
+    N: Fn(&str) -> anyhow::Result<Option<u64>>,
+{
+    pub fn new(parent_balance: F, parent_nonce: N) -> Self {
+        Self {
+            parent_balance,
+            parent_nonce,
+            diff: StateDiff::default(),
+        }
+    }
+
+    pub fn balance(&self, account: &str, asset: &str) -> anyhow::Result<u64> {
+        match self
+            .diff
+            .changes(asset)
+            .and_then(|changes| changes.get(account))
+        {
+            Some(change) => Ok(change.after),
+            None => (self.parent_balance)(account, asset),
+        }
+    }
+
+    pub fn set_balance(&mut self, account: &str, asset: &str, balance: u64) -> anyhow::Result<()> {
+        if let Some(change) = self.diff.changes_mut(asset).get_mut(account) {
+            change.after = balance;
+        } else {
+            let before = (self.parent_balance)(account, asset)?;
+            let change = BalanceChange {
+                before,
+                after: balance,
+            };
+            self.diff
+                .changes_mut(asset)
+                .insert(account.to_string(), change);
+        }
+        Ok(())
+    }
+
+    pub fn nonce(&self, account: &str) -> anyhow::Result<Option<u64>> {
+        match self.diff.nonces.get(account) {
+            Some(change) => Ok(Some(change.after)),
+            None => (self.parent_nonce)(account),
+        }
+    }
+
+    pub fn set_nonce(&mut self, account: &str, nonce: u64) -> anyhow::Result<()> {
+        if let Some(change) = self.diff.nonces.get_mut(account) {
+            change.after = nonce;
+        } else {
+            let before = (self.parent_nonce)(account)?;
+            let change = NonceChange {
+                before,
+                after: nonce,
+            };
+            self.diff.nonces.insert(account.to_string(), change);
+        }
+        Ok(())
+    }
+}
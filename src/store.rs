@@ -0,0 +1,324 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use lmdb::{
+    Cursor, Database, DatabaseFlags, Environment, Transaction as DBTransaction, WriteFlags,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tempdir::TempDir;
+
+/// The kinds of records of a storage. Every value is serialized with `serde_json`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Table {
+    /// Block id to `Block`.
+    Blocks,
+    /// Block id to `BlockNode`, the position of the block in the tree.
+    Headers,
+    /// Block id to the ids of its children.
+    Children,
+    /// Block id to the `StateDiff` of the block.
+    Diffs,
+    /// Account id to `Account` at the tip of the canonical chain.
+    Balances,
+    /// Zero-padded height to the id of the canonical block at that height.
+    Canonical,
+    /// Block id to the orphan block waiting for its parent.
+    Orphans,
+    /// Miscellaneous values such as the canonical tip.
+    Meta,
+    /// Transaction id to the `TxLocation`s of the connected blocks including it.
+    Transactions,
+    /// Block id to its `AppliedTransaction`s.
+    Applied,
+    /// Account id and position to the `HistoryEntry` of the account on the canonical chain.
+    History,
+    /// Records of the custom indexers, keyed by indexer name and key.
+    Indexers,
+    /// Block id to the `RejectedBlock` refused by an ingest.
+    Rejected,
+}
+
+impl Table {
+    pub const ALL: [Table; 13] = [
+        Table::Blocks,
+        Table::Headers,
+        Table::Children,
+        Table::Diffs,
+        Table::Balances,
+        Table::Canonical,
+        Table::Orphans,
+        Table::Meta,
+        Table::Transactions,
+        Table::Applied,
+        Table::History,
+        Table::Indexers,
+        Table::Rejected,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Table::Blocks => "blocks",
+            Table::Headers => "headers",
+            Table::Children => "children",
+            Table::Diffs => "diffs",
+            Table::Balances => "balances",
+            Table::Canonical => "canonical",
+            Table::Orphans => "orphans",
+            Table::Meta => "meta",
+            Table::Transactions => "transactions",
+            Table::Applied => "applied",
+            Table::History => "history",
+            Table::Indexers => "indexers",
+            Table::Rejected => "rejected",
+        }
+    }
+}
+
+/// Key of a height in `Table::Canonical`, padded so that keys sort by height.
+pub fn height_key(height: u64) -> String {
+    format!("{:020}", height)
+}
+
+/// Writes collected during an ingest and committed to the store in a single transaction.
+/// Reads made through the batch see its pending writes.
+#[derive(Default, Debug)]
+pub struct WriteBatch {
+    writes: HashMap<(Table, String), Option<Vec<u8>>>,
+}
+
+impl WriteBatch {
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    pub fn put<T: Serialize>(&mut self, table: Table, key: &str, value: &T) -> anyhow::Result<()> {
+        let bytes = serde_json::to_vec(value)
+            .with_context(|| format!("failed to serialize {} in {}", key, table.name()))?;
+        self.writes.insert((table, key.to_string()), Some(bytes));
+        Ok(())
+    }
+
+    pub fn delete(&mut self, table: Table, key: &str) {
+        self.writes.insert((table, key.to_string()), None);
+    }
+
+    /// The pending write for a key: `Some(None)` if it is being deleted.
+    fn get(&self, table: Table, key: &str) -> Option<Option<&[u8]>> {
+        self.writes
+            .get(&(table, key.to_string()))
+            .map(|value| value.as_deref())
+    }
+}
+
+impl IntoIterator for WriteBatch {
+    /// A table, a key and the new value, `None` deleting the record.
+    type Item = ((Table, String), Option<Vec<u8>>);
+    type IntoIter = std::collections::hash_map::IntoIter<(Table, String), Option<Vec<u8>>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.writes.into_iter()
+    }
+}
+
+/// Where a service keeps its blocks, state and metadata.
+pub trait Storage: std::fmt::Debug {
+    /// Create an empty storage for a service which does not need to outlive the process.
+    fn temporary() -> anyhow::Result<Self>
+    where
+        Self: Sized;
+
+    /// Open the storage named `name` in `dir`, creating it if needed.
+    fn open(dir: &Path, name: &str) -> anyhow::Result<Self>
+    where
+        Self: Sized;
+
+    /// Directory holding the data, if it is kept on disk.
+    fn path(&self) -> Option<&Path>;
+
+    /// The committed value of a record.
+    fn get_raw(&self, table: Table, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// All committed records of a table, ordered by key.
+    fn scan_raw(&self, table: Table) -> anyhow::Result<Vec<(String, Vec<u8>)>>;
+
+    /// Apply all writes of the batch atomically.
+    fn commit(&mut self, batch: WriteBatch) -> anyhow::Result<()>;
+
+    /// Read a record, looking at the pending writes of `batch` first.
+    fn get<T: DeserializeOwned>(
+        &self,
+        batch: &WriteBatch,
+        table: Table,
+        key: &str,
+    ) -> anyhow::Result<Option<T>>
+    where
+        Self: Sized,
+    {
+        read(self, batch, table, key)
+    }
+
+    /// All committed records of a table, ordered by key.
+    fn scan<T: DeserializeOwned>(&self, table: Table) -> anyhow::Result<Vec<(String, T)>>
+    where
+        Self: Sized,
+    {
+        self.scan_raw(table)?
+            .into_iter()
+            .map(|(key, bytes)| Ok((key, serde_json::from_slice(&bytes)?)))
+            .collect()
+    }
+}
+
+/// Read a record, looking at the pending writes of `batch` first.
+pub fn read<T: DeserializeOwned>(
+    store: &dyn Storage,
+    batch: &WriteBatch,
+    table: Table,
+    key: &str,
+) -> anyhow::Result<Option<T>> {
+    if let Some(pending) = batch.get(table, key) {
+        return Ok(pending.map(serde_json::from_slice).transpose()?);
+    }
+    match store.get_raw(table, key)? {
+        Some(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+/// Records kept in one LMDB environment with a database per table.
+#[derive(Debug)]
+pub struct LmdbStorage {
+    env: Environment,
+    dbs: HashMap<Table, Database>,
+    path: PathBuf,
+}
+
+impl Storage for LmdbStorage {
+    /// Open a storage in a new temporary directory, which is left on disk.
+    fn temporary() -> anyhow::Result<Self> {
+        let path = TempDir::new(crate::DIR_NAME)?.into_path();
+        Self::open(&path, crate::DB_NAME)
+    }
+
+    /// Open the storage in `dir`, prefixing the name of every database with `name`.
+    fn open(dir: &Path, name: &str) -> anyhow::Result<Self> {
+        let mut builder = Environment::new();
+        builder.set_max_dbs(32);
+        builder.set_map_size(1 << 30);
+        let env = builder.open(dir)?;
+        let mut dbs = HashMap::new();
+        for table in Table::ALL {
+            let db = env.create_db(
+                Some(&format!("{}.{}", name, table.name())),
+                DatabaseFlags::empty(),
+            )?;
+            dbs.insert(table, db);
+        }
+        Ok(Self {
+            env,
+            dbs,
+            path: dir.to_path_buf(),
+        })
+    }
+
+    fn path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    fn get_raw(&self, table: Table, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let rotxn = self.env.begin_ro_txn()?;
+        let value = match rotxn.get(self.dbs[&table], &key) {
+            Ok(bytes) => Some(bytes.to_vec()),
+            Err(lmdb::Error::NotFound) => None,
+            Err(e) => return Err(e.into()),
+        };
+        Ok(value)
+    }
+
+    fn scan_raw(&self, table: Table) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let rotxn = self.env.begin_ro_txn()?;
+        let mut cursor = rotxn.open_ro_cursor(self.dbs[&table])?;
+        let mut records = Vec::new();
+        // a fresh cursor starts at the first record, and unlike `iter_start` allows an empty table.
+        for (key, value) in cursor.iter() {
+            records.push((String::from_utf8(key.to_vec())?, value.to_vec()));
+        }
+        Ok(records)
+    }
+
+    /// Apply all writes of the batch in one write transaction.
+    fn commit(&mut self, batch: WriteBatch) -> anyhow::Result<()> {
+        let mut rwtxn = self.env.begin_rw_txn()?;
+        for ((table, key), value) in batch {
+            let db = self.dbs[&table];
+            match value {
+                Some(bytes) => rwtxn.put(db, &key, &bytes, WriteFlags::empty())?,
+                None => match rwtxn.del(db, &key, None) {
+                    Ok(()) | Err(lmdb::Error::NotFound) => {}
+                    Err(e) => return Err(e.into()),
+                },
+            }
+        }
+        rwtxn.commit()?;
+        Ok(())
+    }
+}
+
+/// Records kept in memory only, lost when the storage is dropped.
+#[derive(Default, Debug, Clone)]
+pub struct MemoryStorage {
+    tables: HashMap<Table, BTreeMap<String, Vec<u8>>>,
+}
+
+impl Storage for MemoryStorage {
+    fn temporary() -> anyhow::Result<Self> {
+        Ok(Self::default())
+    }
+
+    /// Always fails: there is nothing on disk to open.
+    fn open(dir: &Path, name: &str) -> anyhow::Result<Self> {
+        anyhow::bail!(
+            "in-memory storage cannot open {} in {}",
+            name,
+            dir.display()
+        )
+    }
+
+    fn path(&self) -> Option<&Path> {
+        None
+    }
+
+    fn get_raw(&self, table: Table, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .tables
+            .get(&table)
+            .and_then(|records| records.get(key))
+            .cloned())
+    }
+
+    fn scan_raw(&self, table: Table) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        Ok(self
+            .tables
+            .get(&table)
+            .map(|records| {
+                records
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn commit(&mut self, batch: WriteBatch) -> anyhow::Result<()> {
+        for ((table, key), value) in batch {
+            let records = self.tables.entry(table).or_default();
+            match value {
+                Some(bytes) => records.insert(key, bytes),
+                None => records.remove(&key),
+            };
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::BTreeSet;
+use std::io::{BufRead, Write};
+
+use serde_json::{json, Map, Value};
+
+use crate::api::{self, ApiError, BalanceQuery};
+use crate::store::Storage;
+use crate::{Block, BlockID, Service, ServiceImpl, NATIVE_ASSET};
+
+/// A command of a stream, one JSON object per line tagged by `command`, e.g.
+/// `{"command": "balance", "account": "Alice", "height": 1}`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    Ingest {
+        block: Block,
+    },
+    /// The balance at the canonical tip, or where the `BalanceQuery` fields say.
+    Balance {
+        account: String,
+        #[serde(flatten)]
+        query: BalanceQuery,
+    },
+    Block {
+        block_id: BlockID,
+    },
+    /// The canonical tip, the tips of all forks, the pending blocks and the balances of
+    /// `accounts`, by default every account named by a block ingested so far in the stream.
+    Snapshot {
+        #[serde(default)]
+        accounts: Option<Vec<String>>,
+    },
+}
+
+impl Command {
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Ingest { .. } => "ingest",
+            Command::Balance { .. } => "balance",
+            Command::Block { .. } => "block",
+            Command::Snapshot { .. } => "snapshot",
+        }
+    }
+}
+
+/// Runs the commands of a stream against a service, remembering the accounts it has seen.
+pub struct Stream<'a, S: Storage> {
+    service: &'a mut ServiceImpl<S>,
+    accounts: BTreeSet<String>,
+}
+
+impl<'a, S: Storage> Stream<'a, S> {
+    pub fn new(service: &'a mut ServiceImpl<S>) -> Self {
+        Self {
+            service,
+            accounts: BTreeSet::new(),
+        }
+    }
+
+    pub fn run(&mut self, command: &Command) -> Result<Value, ApiError> {
+        match command {
+            Command::Ingest { block } => {
+                for tx in &block.transactions {
+                    self.accounts.extend(tx.accounts().into_iter().cloned());
+                }
+                api::ingest(self.service, block)
+            }
+            Command::Balance { account, query } => api::balance(self.service, account, query),
+            Command::Block { block_id } => api::block_info(self.service, block_id),
+            Command::Snapshot { accounts } => {
+                let accounts = match accounts {
+                    Some(accounts) => accounts.iter().collect::<BTreeSet<_>>(),
+                    None => self.accounts.iter().collect(),
+                };
+                self.snapshot(accounts)
+            }
+        }
+    }
+
+    fn snapshot(&self, accounts: BTreeSet<&String>) -> Result<Value, ApiError> {
+        let service = &*self.service;
+        let canonical_tip = service.canonical_tip();
+        let height = canonical_tip
+            .and_then(|tip| service.tree.get(tip))
+            .map(|tip| tip.height);
+        let mut tips: Vec<&BlockID> = service.tree.tips().map(|tip| &tip.block_id).collect();
+        tips.sort();
+        let pending: Vec<BlockID> = service
+            .get_pending_blocks()?
+            .into_iter()
+            .map(|pending| pending.block_id)
+            .collect();
+        let mut balances = Map::new();
+        for account in accounts {
+            balances.insert(
+                account.clone(),
+                json!(service.get_balance(account, NATIVE_ASSET)?),
+            );
+        }
+        Ok(json!({
+            "canonical_tip": canonical_tip,
+            "height": height,
+            "tips": tips,
+            "pending": pending,
+            "balances": balances,
+        }))
+    }
+
+    /// Run one command per input line, in order, writing one JSON result per output line:
+    /// `{"line", "command", "result"}` on success, `{"line", "command", "error",
+    /// "ingest_error"}` on failure. A line which is not a command fails without a `command`.
+    /// Blank lines are skipped.
+    pub fn serve_lines(
+        &mut self,
+        input: impl BufRead,
+        mut output: impl Write,
+    ) -> std::io::Result<()> {
+        for (index, line) in input.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut record = Map::new();
+            record.insert("line".to_string(), json!(index + 1));
+            let result = match serde_json::from_str::<Command>(&line) {
+                Ok(command) => {
+                    record.insert("command".to_string(), json!(command.name()));
+                    self.run(&command)
+                }
+                Err(error) => Err(ApiError::bad_request(format!(
+                    "malformed command: {}",
+                    error
+                ))),
+            };
+            match result {
+                Ok(result) => {
+                    record.insert("result".to_string(), result);
+                }
+                Err(error) => {
+                    record.insert("error".to_string(), json!(error.message));
+                    record.insert("ingest_error".to_string(), json!(error.ingest_error));
+                }
+            }
+            writeln!(output, "{}", Value::Object(record))?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
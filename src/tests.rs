@@ -1,7 +1,26 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     use crate::blocks;
-    use crate::{Service, ServiceImpl, DB_NAME};
+    use crate::events::ChainEvent;
+    use crate::fees::{AccountFees, BlockFees, RewardSchedule};
+    use crate::fork_choice::{ForkChoice, Ghost, HeaviestChain, LongestChain, TieBreak};
+    use crate::history::{Direction, HistoryCursor, HistoryQuery};
+    use crate::indexer::{AppliedTransaction, IndexContext, Indexer};
+    use crate::ingest::{BlockStatus, IngestError, RejectedBlock, TxStatus, ValidityPolicy};
+    use crate::orphan::OrphanPoolConfig;
+    use crate::render::{ForkTree, NodeStatus, TreeEdge, TreeFormat, TreeNode};
+    use crate::repl::complete;
+    use crate::state::Supply;
+    use crate::store::{LmdbStorage, MemoryStorage, Storage, Table, WriteBatch};
+    use crate::tree::BlockTree;
+    use crate::ws::{Hub, Push, Subscription};
+    use crate::{
+        Block, LmdbService, MemoryService, Service, ServiceConfig, ServiceImpl, Transaction,
+        DB_NAME, NATIVE_ASSET, ORPHAN_CLOCK_KEY,
+    };
+    use std::path::Path;
+    use std::sync::{Arc, Mutex};
 
     fn assert_balances<S: Service>(
         service: &S,
@@ -9,13 +28,19 @@ mod tests {
         expected_balance_bob: anyhow::Result<S::Balance>,
     ) {
         if let Ok(balance) = expected_balance_alice {
-            assert_eq!(service.get_balance(&blocks::ALICE).unwrap(), balance);
+            assert_eq!(
+                service.get_balance(&blocks::ALICE, NATIVE_ASSET).unwrap(),
+                balance
+            );
         } else {
             panic!("Alice's balance is not available!");
         }
 
         if let Ok(balance) = expected_balance_bob {
-            assert_eq!(service.get_balance(&blocks::BOB).unwrap(), balance);
+            assert_eq!(
+                service.get_balance(&blocks::BOB, NATIVE_ASSET).unwrap(),
+                balance
+            );
         } else {
             panic!("Bob's balance is not available!");
         }
@@ -23,21 +48,82 @@ mod tests {
 
     #[test]
     fn service_restart() {
-        let mut service = ServiceImpl::new();
+        let mut service = LmdbService::new().unwrap();
         service.ingest_block(&blocks::BLOCK_A).unwrap();
         service.ingest_block(&blocks::BLOCK_B).unwrap();
         assert_balances(&service, anyhow::Ok(10), anyhow::Ok(0));
         // get temporary dir path.
         let dir = service.path.unwrap();
         // restart service.
-        let service = ServiceImpl::from_db(dir.as_str(), DB_NAME);
+        let service = LmdbService::from_db(dir.as_str(), DB_NAME).unwrap();
         assert_balances(&service, anyhow::Ok(10), anyhow::Ok(0));
     }
 
+    #[test]
+    fn service_restart_with_forks_and_orphans() {
+        let mut service = LmdbService::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        let dir = service.path.clone().unwrap();
+        drop(service);
+        // the tree, the orphan pool and the canonical tip survive the restart.
+        let mut service = LmdbService::from_db(dir.as_str(), DB_NAME).unwrap();
+        assert_eq!(service.canonical_tip().unwrap(), "B");
+        assert_eq!(service.get_pending_blocks().unwrap().len(), 1);
+        let block = service.get_block("B").unwrap().unwrap();
+        assert_eq!(block.parent_id.as_deref(), Some("A"));
+        assert_eq!(
+            service
+                .get_balance_at_height(&blocks::ALICE, NATIVE_ASSET, 0)
+                .unwrap(),
+            5
+        );
+        // D reattaches after the restart and the reorg is persisted as well.
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        drop(service);
+        let service = LmdbService::from_db(dir.as_str(), DB_NAME).unwrap();
+        assert_eq!(service.canonical_tip().unwrap(), "D");
+        assert!(service.get_pending_blocks().unwrap().is_empty());
+        assert_balances(&service, anyhow::Ok(6), anyhow::Ok(4));
+        assert_eq!(
+            service
+                .get_balance_on_fork(&blocks::ALICE, NATIVE_ASSET, "B")
+                .unwrap(),
+            10
+        );
+        assert_eq!(
+            service.canonical_block_at(2).unwrap(),
+            Some("D".to_string())
+        );
+    }
+
+    #[test]
+    fn storage_backends_agree() {
+        fn play<S: Storage>() -> ServiceImpl<S> {
+            let mut service = ServiceImpl::<S>::new().unwrap();
+            for block in [&*blocks::BLOCK_A, &*blocks::BLOCK_B, &*blocks::BLOCK_D] {
+                service.ingest_block(block).unwrap();
+            }
+            service.ingest_block(&blocks::BLOCK_C).unwrap();
+            service
+        }
+        let memory = play::<MemoryStorage>();
+        let lmdb = play::<LmdbStorage>();
+        // the in-memory backend never touches disk.
+        assert!(memory.path.is_none());
+        assert!(lmdb.path.is_some());
+        assert_balances(&memory, anyhow::Ok(6), anyhow::Ok(4));
+        assert_balances(&lmdb, anyhow::Ok(6), anyhow::Ok(4));
+        assert_eq!(memory.canonical_tip(), lmdb.canonical_tip());
+        assert_eq!(memory.get_diff("C").unwrap(), lmdb.get_diff("C").unwrap());
+        assert!(MemoryStorage::open(Path::new(lmdb.path.as_ref().unwrap()), DB_NAME).is_err());
+    }
+
     #[test]
     fn test_1() {
         // single block
-        let mut service = ServiceImpl::new();
+        let mut service = MemoryService::new().unwrap();
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
         service.ingest_block(&blocks::BLOCK_A).unwrap();
         assert_balances(&service, anyhow::Ok(5), anyhow::Ok(5));
@@ -46,7 +132,7 @@ mod tests {
     #[test]
     fn test_2() {
         // single chain of blocks
-        let mut service = ServiceImpl::new();
+        let mut service = MemoryService::new().unwrap();
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
         service.ingest_block(&blocks::BLOCK_A).unwrap();
         assert_balances(&service, anyhow::Ok(5), anyhow::Ok(5));
@@ -57,7 +143,7 @@ mod tests {
     #[test]
     fn test_3() {
         // multiple forks
-        let mut service = ServiceImpl::new();
+        let mut service = MemoryService::new().unwrap();
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
         service.ingest_block(&blocks::BLOCK_A).unwrap();
         assert_balances(&service, anyhow::Ok(5), anyhow::Ok(5));
@@ -70,7 +156,7 @@ mod tests {
     #[test]
     fn test_4() {
         // multiple forks, different order
-        let mut service = ServiceImpl::new();
+        let mut service = MemoryService::new().unwrap();
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
         service.ingest_block(&blocks::BLOCK_A).unwrap();
         assert_balances(&service, anyhow::Ok(5), anyhow::Ok(5));
@@ -83,7 +169,7 @@ mod tests {
     #[test]
     fn test_5() {
         // multiple forks where the longest chain changes mid stream
-        let mut service = ServiceImpl::new();
+        let mut service = MemoryService::new().unwrap();
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
         service.ingest_block(&blocks::BLOCK_A).unwrap();
         assert_balances(&service, anyhow::Ok(5), anyhow::Ok(5));
@@ -98,7 +184,7 @@ mod tests {
     #[test]
     fn test_6() {
         // multiple forks with the longest fork arriving out of order
-        let mut service = ServiceImpl::new();
+        let mut service = MemoryService::new().unwrap();
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
         service.ingest_block(&blocks::BLOCK_A).unwrap();
         assert_balances(&service, anyhow::Ok(5), anyhow::Ok(5));
@@ -106,14 +192,15 @@ mod tests {
         assert_balances(&service, anyhow::Ok(10), anyhow::Ok(0));
         service.ingest_block(&blocks::BLOCK_D).unwrap();
         assert_balances(&service, anyhow::Ok(10), anyhow::Ok(0));
+        // D was waiting for C and is reattached with it.
         service.ingest_block(&blocks::BLOCK_C).unwrap();
-        assert_balances(&service, anyhow::Ok(8), anyhow::Ok(2));
+        assert_balances(&service, anyhow::Ok(6), anyhow::Ok(4));
     }
 
     // multiple forks where the genesis block is the last to arrive
     #[test]
     fn test_7() {
-        let mut service = ServiceImpl::new();
+        let mut service = MemoryService::new().unwrap();
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
         service.ingest_block(&blocks::BLOCK_D).unwrap();
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
@@ -121,17 +208,1760 @@ mod tests {
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
         service.ingest_block(&blocks::BLOCK_C).unwrap();
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
+        // all waiting blocks are reattached once the genesis block arrives.
         service.ingest_block(&blocks::BLOCK_A).unwrap();
-        assert_balances(&service, anyhow::Ok(5), anyhow::Ok(5));
+        assert_balances(&service, anyhow::Ok(6), anyhow::Ok(4));
     }
 
     #[test]
-    fn test_8(){
-        let mut service = ServiceImpl::new();
+    fn test_8() {
+        let mut service = MemoryService::new().unwrap();
         assert_balances(&service, anyhow::Ok(0), anyhow::Ok(0));
         service.ingest_block(&blocks::BLOCK_A).unwrap();
         assert_balances(&service, anyhow::Ok(5), anyhow::Ok(5));
         service.ingest_block(&blocks::BLOCK_E).unwrap();
         assert_balances(&service, anyhow::Ok(1), anyhow::Ok(7));
     }
+
+    #[test]
+    fn block_tree_shares_ancestry() {
+        let mut service = MemoryService::new().unwrap();
+        for block in [
+            &*blocks::BLOCK_A,
+            &*blocks::BLOCK_B,
+            &*blocks::BLOCK_C,
+            &*blocks::BLOCK_D,
+        ] {
+            service.ingest_block(block).unwrap();
+        }
+        // every block is stored once, no matter how many forks contain it.
+        assert_eq!(service.tree.len(), 4);
+        let a = service.tree.get("A").unwrap();
+        assert_eq!(a.children, vec!["B".to_string(), "C".to_string()]);
+        let d = service.tree.get("D").unwrap();
+        assert_eq!(d.height, 2);
+        assert_eq!(d.cumulative_txs, 4);
+        let tips: Vec<_> = service.tree.tips().map(|node| node.id().as_str()).collect();
+        assert_eq!(tips, vec!["B", "D"]);
+        assert_eq!(service.canonical_tip().unwrap(), "D");
+    }
+
+    #[test]
+    fn orphan_pool_lists_pending_blocks() {
+        let mut service = MemoryService::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        let pending = service.get_pending_blocks().unwrap();
+        let pending: Vec<_> = pending
+            .iter()
+            .map(|block| (block.block_id.as_str(), block.missing_parent.as_str()))
+            .collect();
+        assert_eq!(pending, vec![("D", "C"), ("B", "A")]);
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        let pending = service.get_pending_blocks().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].missing_parent, "C");
+    }
+
+    #[test]
+    fn orphan_pool_limits() {
+        let mut service = MemoryService::with_config(ServiceConfig {
+            orphan_pool: OrphanPoolConfig {
+                max_blocks: 1,
+                max_age: 1,
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        // B evicts D from a full pool.
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        assert_eq!(service.orphans.len(), 1);
+        assert!(service.orphans.contains("B"));
+        // B expires after two more ingested blocks.
+        service.ingest_block(&blocks::BLOCK_E).unwrap();
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        assert_eq!(service.orphans.len(), 1);
+        assert!(service.orphans.contains("C"));
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        assert!(service.tree.contains("C"));
+        assert!(!service.tree.contains("B"));
+        assert!(!service.tree.contains("D"));
+    }
+
+    fn with_fork_choice(fork_choice: impl ForkChoice + 'static) -> MemoryService {
+        MemoryService::with_config(ServiceConfig {
+            fork_choice: Box::new(fork_choice),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn empty_block(id: &str, parent: &str) -> Block {
+        Block {
+            block_id: id.to_string(),
+            parent_id: Some(parent.to_string()),
+            transactions: Vec::new(),
+            producer: None,
+        }
+    }
+
+    #[test]
+    fn fork_choice_longest_chain_tie_break() {
+        let mut service = with_fork_choice(LongestChain {
+            tie_break: TieBreak::FirstSeen,
+        });
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        // B and C have the same height, B was seen first.
+        assert_eq!(service.canonical_tip().unwrap(), "B");
+        assert_balances(&service, anyhow::Ok(10), anyhow::Ok(0));
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        assert_eq!(service.canonical_tip().unwrap(), "D");
+    }
+
+    #[test]
+    fn fork_choice_heaviest_chain() {
+        fn transferred(block: &Block) -> u64 {
+            block
+                .transactions
+                .iter()
+                .map(|tx| match tx {
+                    Transaction::Transfer { amount, .. } => *amount,
+                    Transaction::Mint { .. } | Transaction::Burn { .. } => 0,
+                })
+                .sum()
+        }
+        let mut service = with_fork_choice(HeaviestChain {
+            weight: transferred,
+            tie_break: TieBreak::LatestSeen,
+        });
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        // A -> B transfers 10 coins, A -> C only 8.
+        assert_eq!(service.canonical_tip().unwrap(), "B");
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        // A -> C -> D ties with A -> B and is newer.
+        assert_eq!(service.canonical_tip().unwrap(), "D");
+
+        // weights summing past u64::MAX saturate instead of overflowing.
+        let mut service = with_fork_choice(HeaviestChain {
+            weight: |_| u64::MAX,
+            tie_break: TieBreak::FirstSeen,
+        });
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        let b = service.tree.get("B").unwrap();
+        assert_eq!(b.cumulative_weight, u64::MAX);
+    }
+
+    #[test]
+    fn fork_choice_ghost() {
+        let mut service = with_fork_choice(Ghost::default());
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        for block in [
+            empty_block("B", "A"),
+            empty_block("B1", "B"),
+            empty_block("B2", "B1"),
+            empty_block("C", "A"),
+            empty_block("C1", "C"),
+            empty_block("C2", "C"),
+            empty_block("C3", "C"),
+        ] {
+            service.ingest_block(&block).unwrap();
+        }
+        // the subtree of C has the most blocks even though B2 ends the longest chain.
+        assert_eq!(service.canonical_tip().unwrap(), "C3");
+        let longest = LongestChain::default();
+        assert_eq!(longest.select_tip(&service.tree).unwrap().id(), "B2");
+        let sizes = |tree: &BlockTree| -> Vec<u64> {
+            ["A", "B", "C", "C3"]
+                .iter()
+                .map(|id| tree.get(id).unwrap().subtree_size)
+                .collect()
+        };
+        assert_eq!(sizes(&service.tree), [8, 3, 4, 1]);
+        // the sizes kept on insertion match the ones counted when the tree is loaded.
+        let mut loaded = BlockTree::from_nodes(service.tree.iter().cloned());
+        loaded.track_subtree_sizes();
+        assert_eq!(sizes(&loaded), sizes(&service.tree));
+        // the other rules do not pay for walking up to the genesis block on every insert.
+        let mut service = MemoryService::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        assert_eq!(service.tree.get("A").unwrap().subtree_size, 0);
+    }
+
+    #[test]
+    fn historical_balances() {
+        let mut service = MemoryService::new().unwrap();
+        for block in [
+            &*blocks::BLOCK_A,
+            &*blocks::BLOCK_B,
+            &*blocks::BLOCK_C,
+            &*blocks::BLOCK_D,
+        ] {
+            service.ingest_block(block).unwrap();
+        }
+        // as of a block, on any fork.
+        assert_eq!(
+            service
+                .get_balance_at_block(&blocks::BOB, NATIVE_ASSET, "A")
+                .unwrap(),
+            5
+        );
+        assert_eq!(
+            service
+                .get_balance_at_block(&blocks::BOB, NATIVE_ASSET, "B")
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            service
+                .get_balance_at_block(&blocks::BOB, NATIVE_ASSET, "C")
+                .unwrap(),
+            2
+        );
+        assert!(service
+            .get_balance_at_block(&blocks::BOB, NATIVE_ASSET, "X")
+            .is_err());
+        // as of a height of the canonical chain A -> C -> D.
+        assert_eq!(
+            service
+                .get_balance_at_height(&blocks::ALICE, NATIVE_ASSET, 0)
+                .unwrap(),
+            5
+        );
+        assert_eq!(
+            service
+                .get_balance_at_height(&blocks::ALICE, NATIVE_ASSET, 1)
+                .unwrap(),
+            8
+        );
+        assert_eq!(
+            service
+                .get_balance_at_height(&blocks::ALICE, NATIVE_ASSET, 2)
+                .unwrap(),
+            6
+        );
+        assert!(service
+            .get_balance_at_height(&blocks::ALICE, NATIVE_ASSET, 3)
+            .is_err());
+        // on the tip of a fork.
+        assert_eq!(
+            service
+                .get_balance_on_fork(&blocks::ALICE, NATIVE_ASSET, "B")
+                .unwrap(),
+            10
+        );
+        assert_eq!(
+            service
+                .get_balance_on_fork(&blocks::ALICE, NATIVE_ASSET, "D")
+                .unwrap(),
+            6
+        );
+        assert!(service
+            .get_balance_on_fork(&blocks::ALICE, NATIVE_ASSET, "C")
+            .is_err());
+        // blocks only record the accounts they changed.
+        let diff = service.get_diff("C").unwrap().unwrap();
+        assert_eq!(diff.balances.len(), 2);
+        assert_eq!(diff.balances["Bob"].before, 5);
+        assert_eq!(diff.balances["Bob"].after, 2);
+    }
+
+    fn ingest_error<S: Service>(service: &mut S, block: &Block) -> IngestError {
+        service
+            .ingest_block(block)
+            .unwrap_err()
+            .downcast::<IngestError>()
+            .unwrap()
+    }
+
+    fn transfer(tx_id: &str, from: &str, to: &str, amount: u64) -> Transaction {
+        Transaction::Transfer {
+            tx_id: tx_id.to_string(),
+            from: from.to_string(),
+            to: to.to_string(),
+            amount,
+            asset: None,
+            fee: None,
+            nonce: None,
+        }
+    }
+
+    #[test]
+    fn ingestion_errors() {
+        let mut service = MemoryService::with_config(ServiceConfig {
+            orphan_pool: OrphanPoolConfig {
+                max_blocks: 0,
+                max_age: 0,
+            },
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            ingest_error(&mut service, &blocks::BLOCK_B),
+            IngestError::UnknownParent {
+                block_id: "B".to_string(),
+                parent_id: "A".to_string(),
+            }
+        );
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        assert_eq!(
+            ingest_error(&mut service, &blocks::BLOCK_A),
+            IngestError::DuplicateBlock("A".to_string())
+        );
+        let mut block = empty_block("F", "F");
+        assert!(matches!(
+            ingest_error(&mut service, &block),
+            IngestError::MalformedBlock { .. }
+        ));
+        block.parent_id = Some("A".to_string());
+        block.transactions = vec![Transaction::Mint {
+            tx_id: "F0".to_string(),
+            to: blocks::ALICE.to_string(),
+            amount: u64::MAX,
+            asset: None,
+        }];
+        let report = service.ingest_block(&block).unwrap();
+        assert_eq!(
+            report.transactions[0].status,
+            TxStatus::Rejected(IngestError::Overflow {
+                tx_id: "F0".to_string(),
+                account: blocks::ALICE.to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn rejected_transactions_are_reported() {
+        let mut service = MemoryService::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        let mut block = empty_block("F", "A");
+        block.transactions = vec![
+            transfer("F0", &blocks::BOB, &blocks::ALICE, 6),
+            transfer("F1", &blocks::BOB, &blocks::ALICE, 1),
+            transfer("F1", &blocks::BOB, &blocks::ALICE, 1),
+        ];
+        let report = service.ingest_block(&block).unwrap();
+        assert_eq!(report.status, BlockStatus::Connected { height: 1 });
+        let rejected: Vec<_> = report.rejected_transactions().collect();
+        assert_eq!(
+            rejected,
+            vec![
+                (
+                    &"F0".to_string(),
+                    &IngestError::InsufficientBalance {
+                        tx_id: "F0".to_string(),
+                        account: blocks::BOB.to_string(),
+                        balance: 5,
+                        amount: 6,
+                    }
+                ),
+                (
+                    &"F1".to_string(),
+                    &IngestError::DuplicateTransaction("F1".to_string())
+                ),
+            ]
+        );
+        // a skipped transfer neither debits the sender nor credits the recipient.
+        assert_balances(&service, anyhow::Ok(6), anyhow::Ok(4));
+    }
+
+    #[test]
+    fn reject_block_policy() {
+        let mut service = MemoryService::with_config(ServiceConfig {
+            validity_policy: ValidityPolicy::RejectBlock,
+            ..Default::default()
+        })
+        .unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        let mut block = empty_block("F", "A");
+        block.transactions = vec![
+            transfer("F0", &blocks::BOB, &blocks::ALICE, 1),
+            transfer("F1", &blocks::BOB, &blocks::ALICE, 6),
+        ];
+        assert!(matches!(
+            ingest_error(&mut service, &block),
+            IngestError::InsufficientBalance { .. }
+        ));
+        assert!(!service.tree.contains("F"));
+        assert_balances(&service, anyhow::Ok(5), anyhow::Ok(5));
+
+        // the orphans waiting for a rejected block are rejected along with it.
+        let mut service = MemoryService::with_config(ServiceConfig {
+            validity_policy: ValidityPolicy::RejectBlock,
+            ..Default::default()
+        })
+        .unwrap();
+        service.ingest_block(&block).unwrap();
+        service.ingest_block(&empty_block("G", "F")).unwrap();
+        service.ingest_block(&empty_block("H", "G")).unwrap();
+        let report = service.ingest_block(&blocks::BLOCK_A).unwrap();
+        let reattached: Vec<_> = report
+            .reattached
+            .iter()
+            .map(|report| (report.block_id.as_str(), &report.status))
+            .collect();
+        assert!(matches!(
+            reattached.as_slice(),
+            [
+                (
+                    "F",
+                    BlockStatus::Rejected(IngestError::InsufficientBalance { .. })
+                ),
+                (
+                    "G",
+                    BlockStatus::Rejected(IngestError::UnknownParent { .. })
+                ),
+                (
+                    "H",
+                    BlockStatus::Rejected(IngestError::UnknownParent { .. })
+                ),
+            ]
+        ));
+        assert!(service.orphans.is_empty());
+        assert!(service.get_pending_blocks().unwrap().is_empty());
+        let rejected: Vec<String> = service
+            .get_rejected_blocks()
+            .unwrap()
+            .into_iter()
+            .map(|rejected| rejected.block_id)
+            .collect();
+        for block_id in ["F", "G", "H"] {
+            assert!(rejected.iter().any(|rejected| rejected == block_id));
+        }
+    }
+
+    /// In-memory storage whose commits fail on demand.
+    #[derive(Debug, Default)]
+    struct FaultyStorage {
+        inner: MemoryStorage,
+        fail_commits: bool,
+    }
+
+    impl Storage for FaultyStorage {
+        fn temporary() -> anyhow::Result<Self> {
+            Ok(Self::default())
+        }
+
+        fn open(dir: &Path, name: &str) -> anyhow::Result<Self> {
+            MemoryStorage::open(dir, name).map(|inner| Self {
+                inner,
+                fail_commits: false,
+            })
+        }
+
+        fn path(&self) -> Option<&Path> {
+            None
+        }
+
+        fn get_raw(&self, table: Table, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+            self.inner.get_raw(table, key)
+        }
+
+        fn scan_raw(&self, table: Table) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+            self.inner.scan_raw(table)
+        }
+
+        fn commit(&mut self, batch: WriteBatch) -> anyhow::Result<()> {
+            if self.fail_commits {
+                anyhow::bail!("disk full");
+            }
+            self.inner.commit(batch)
+        }
+    }
+
+    #[test]
+    fn storage_faults_are_reported() {
+        assert!(LmdbService::from_db("./no/such/dir", DB_NAME).is_err());
+        let mut service = ServiceImpl::<FaultyStorage>::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        service.store.fail_commits = true;
+        let error = service.ingest_block(&blocks::BLOCK_C).unwrap_err();
+        assert!(!error.is::<IngestError>());
+        // the failed ingest leaves no trace.
+        assert!(!service.tree.contains("C"));
+        assert_eq!(service.canonical_tip().unwrap(), "B");
+        assert_balances(&service, anyhow::Ok(10), anyhow::Ok(0));
+        service.store.fail_commits = false;
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        assert_balances(&service, anyhow::Ok(6), anyhow::Ok(4));
+    }
+
+    #[test]
+    fn recovery_rebuilds_corrupted_indexes() {
+        let mut service = LmdbService::new().unwrap();
+        for block in [
+            &*blocks::BLOCK_A,
+            &*blocks::BLOCK_B,
+            &*blocks::BLOCK_C,
+            &*blocks::BLOCK_D,
+        ] {
+            service.ingest_block(block).unwrap();
+        }
+        let dir = service.path.clone().unwrap();
+        drop(service);
+        // an intact storage is opened as is.
+        let (service, report) =
+            LmdbService::recover_db(dir.as_str(), DB_NAME, ServiceConfig::default()).unwrap();
+        assert!(!report.rebuilt);
+        assert_eq!(report.salvaged_blocks, 4);
+        drop(service);
+
+        let mut store = LmdbStorage::open(Path::new(&dir), DB_NAME).unwrap();
+        let mut batch = WriteBatch::default();
+        batch.put(Table::Blocks, "B", &"truncated").unwrap();
+        batch.put(Table::Headers, "C", &42).unwrap();
+        store.commit(batch).unwrap();
+        drop(store);
+        assert!(LmdbService::from_db(dir.as_str(), DB_NAME).is_err());
+
+        let (service, report) =
+            LmdbService::recover_db(dir.as_str(), DB_NAME, ServiceConfig::default()).unwrap();
+        assert!(report.rebuilt);
+        assert_eq!(
+            report.corrupted,
+            vec![
+                (Table::Blocks, "B".to_string()),
+                (Table::Headers, "C".to_string())
+            ]
+        );
+        assert_eq!(
+            report.missing,
+            vec![
+                (Table::Headers, "C".to_string()),
+                (Table::Blocks, "B".to_string())
+            ]
+        );
+        assert_eq!(report.salvaged_blocks, 3);
+        assert_eq!(report.pending_blocks, 0);
+        assert_eq!(service.tree.len(), 3);
+        assert_eq!(service.canonical_tip().unwrap(), "D");
+        assert_balances(&service, anyhow::Ok(6), anyhow::Ok(4));
+        drop(service);
+        // the rebuilt indexes are persisted.
+        let service = LmdbService::from_db(dir.as_str(), DB_NAME).unwrap();
+        assert_balances(&service, anyhow::Ok(6), anyhow::Ok(4));
+    }
+
+    #[test]
+    fn recovery_keeps_orphans_when_the_orphan_clock_is_lost() {
+        let mut service = LmdbService::new().unwrap();
+        for block in [&*blocks::BLOCK_A, &*blocks::BLOCK_D, &*blocks::BLOCK_B] {
+            service.ingest_block(block).unwrap();
+        }
+        let dir = service.path.clone().unwrap();
+        drop(service);
+
+        let mut store = LmdbStorage::open(Path::new(&dir), DB_NAME).unwrap();
+        let mut batch = WriteBatch::default();
+        batch
+            .put(Table::Meta, ORPHAN_CLOCK_KEY, &"truncated")
+            .unwrap();
+        store.commit(batch).unwrap();
+        drop(store);
+
+        let (mut service, report) =
+            LmdbService::recover_db(dir.as_str(), DB_NAME, ServiceConfig::default()).unwrap();
+        assert!(report.rebuilt);
+        assert_eq!(
+            report.corrupted,
+            vec![(Table::Meta, ORPHAN_CLOCK_KEY.to_string())]
+        );
+        assert_eq!(report.pending_blocks, 1);
+        assert!(service.orphans.clock() >= 2);
+        // the orphan neither expires nor underflows the clock when its parent arrives.
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        assert!(service.orphans.is_empty());
+        assert_eq!(service.canonical_tip().unwrap(), "D");
+        assert_balances(&service, anyhow::Ok(6), anyhow::Ok(4));
+    }
+
+    #[test]
+    fn transaction_lookup() {
+        let mut service = MemoryService::new().unwrap();
+        for block in [
+            &*blocks::BLOCK_A,
+            &*blocks::BLOCK_B,
+            &*blocks::BLOCK_C,
+            &*blocks::BLOCK_D,
+            &*blocks::BLOCK_E,
+        ] {
+            service.ingest_block(block).unwrap();
+        }
+        assert!(service.get_transaction("Z0").unwrap().is_empty());
+        let a0 = service.get_transaction("A0").unwrap();
+        assert_eq!(a0.len(), 2);
+        assert_eq!((a0[0].block_id.as_str(), a0[0].height), ("A", 0));
+        assert!(a0[0].canonical && a0[0].is_applied());
+        assert_eq!(a0[0].confirmations, 3);
+        // E reuses the transaction ids of A on another fork.
+        assert_eq!(a0[1].block_id, "E");
+        assert!(!a0[1].canonical && !a0[1].is_applied());
+        assert_eq!(a0[1].confirmations, 0);
+        let b0 = &service.get_transaction("B0").unwrap()[0];
+        assert_eq!(b0.status, TxStatus::Applied);
+        assert!(!b0.canonical);
+        assert_eq!(service.get_transaction("D0").unwrap()[0].confirmations, 1);
+
+        let mut block = empty_block("F", "D");
+        block.transactions = vec![transfer("F0", &blocks::BOB, &blocks::ALICE, 100)];
+        service.ingest_block(&block).unwrap();
+        let f0 = &service.get_transaction("F0").unwrap()[0];
+        assert!(f0.canonical && !f0.is_applied());
+        assert!(matches!(
+            f0.status,
+            TxStatus::Rejected(IngestError::InsufficientBalance { .. })
+        ));
+        assert_eq!(service.get_transaction("A0").unwrap()[0].confirmations, 4);
+    }
+
+    #[test]
+    fn account_history() {
+        let mut service = MemoryService::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        let history = |service: &MemoryService, query: &HistoryQuery| {
+            service.get_account_history(&blocks::ALICE, query).unwrap()
+        };
+        let page = history(&service, &HistoryQuery::default());
+        let tx_ids: Vec<_> = page
+            .entries
+            .iter()
+            .map(|entry| entry.tx_id.as_str())
+            .collect();
+        assert_eq!(tx_ids, vec!["B0", "A0", "A1"]);
+        assert_eq!(page.entries[0].counterparty.as_deref(), Some("Bob"));
+        assert_eq!((page.entries[0].delta, page.entries[0].balance), (5, 10));
+        assert_eq!((page.entries[1].delta, page.entries[1].balance), (-5, 5));
+        assert_eq!(page.entries[2].counterparty, None);
+        assert!(page.next_cursor.is_none());
+
+        // page forward two entries at a time.
+        let query = HistoryQuery {
+            limit: 2,
+            direction: Direction::Forward,
+            ..Default::default()
+        };
+        let first = history(&service, &query);
+        assert_eq!(first.entries.len(), 2);
+        let cursor = first.next_cursor.unwrap();
+        assert_eq!(cursor.to_string(), "1@A");
+        let second = history(
+            &service,
+            &HistoryQuery {
+                cursor: Some(cursor.clone()),
+                ..query.clone()
+            },
+        );
+        assert_eq!(second.entries[0].tx_id, "B0");
+        assert!(second.next_cursor.is_none());
+
+        // the reorg to D replaces B0 with the entries of C and D.
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        let page = history(&service, &HistoryQuery::default());
+        let tx_ids: Vec<_> = page
+            .entries
+            .iter()
+            .map(|entry| entry.tx_id.as_str())
+            .collect();
+        assert_eq!(tx_ids, vec!["D0", "C0", "A0", "A1"]);
+        assert_eq!(page.entries[0].balance, 6);
+        assert_eq!(page.entries[0].height, 2);
+        let stale = HistoryQuery {
+            cursor: Some(HistoryCursor {
+                position: 2,
+                block_id: "B".to_string(),
+            }),
+            ..query
+        };
+        assert!(service.get_account_history(&blocks::ALICE, &stale).is_err());
+        assert!(service
+            .get_account_history("Carol", &HistoryQuery::default())
+            .unwrap()
+            .entries
+            .is_empty());
+        // an empty page would have no cursor to continue from.
+        let empty = HistoryQuery {
+            limit: 0,
+            ..HistoryQuery::default()
+        };
+        assert!(service.get_account_history(&blocks::ALICE, &empty).is_err());
+    }
+
+    fn record_events<S: Service>(service: &mut S) -> Arc<Mutex<Vec<ChainEvent>>> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        service.subscribe(Box::new(move |event: &ChainEvent| {
+            recorded.lock().unwrap().push(event.clone())
+        }));
+        events
+    }
+
+    #[test]
+    fn chain_events() {
+        let mut service = ServiceImpl::<FaultyStorage>::new().unwrap();
+        let events = record_events(&mut service);
+        let take = || std::mem::take(&mut *events.lock().unwrap());
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        assert_eq!(
+            take(),
+            vec![
+                ChainEvent::BlockConnected {
+                    block_id: "A".to_string(),
+                    height: 0
+                },
+                ChainEvent::TipChanged {
+                    old_tip: None,
+                    new_tip: "A".to_string(),
+                    height: 0
+                },
+            ]
+        );
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        take();
+        // C ties with B and wins as the latest seen block.
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        assert_eq!(
+            take(),
+            vec![
+                ChainEvent::BlockDisconnected {
+                    block_id: "B".to_string(),
+                    height: 1
+                },
+                ChainEvent::BlockConnected {
+                    block_id: "C".to_string(),
+                    height: 1
+                },
+                ChainEvent::TipChanged {
+                    old_tip: Some("B".to_string()),
+                    new_tip: "C".to_string(),
+                    height: 1
+                },
+                ChainEvent::ReorgCompleted {
+                    old_tip: "B".to_string(),
+                    new_tip: "C".to_string(),
+                    fork_point: Some("A".to_string()),
+                    depth: 1
+                },
+            ]
+        );
+        // events of an ingest which was not committed are never delivered.
+        service.store.fail_commits = true;
+        service.ingest_block(&blocks::BLOCK_D).unwrap_err();
+        assert!(take().is_empty());
+        service.store.fail_commits = false;
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        assert_eq!(
+            take(),
+            vec![
+                ChainEvent::BlockConnected {
+                    block_id: "D".to_string(),
+                    height: 2
+                },
+                ChainEvent::TipChanged {
+                    old_tip: Some("C".to_string()),
+                    new_tip: "D".to_string(),
+                    height: 2
+                },
+            ]
+        );
+    }
+
+    /// Counts the coins each account sent on the canonical chain.
+    #[derive(Debug, Default)]
+    struct SentIndexer {
+        fail_on: Option<String>,
+    }
+
+    impl SentIndexer {
+        fn add(ctx: &mut IndexContext, tx: &AppliedTransaction, sign: i128) -> anyhow::Result<()> {
+            if let Transaction::Transfer { from, amount, .. } = &tx.tx {
+                let sent: i128 = ctx.get(from)?.unwrap_or(0);
+                ctx.put(from, &(sent + sign * *amount as i128))?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Indexer for SentIndexer {
+        fn name(&self) -> &str {
+            "sent"
+        }
+
+        fn block_connected(
+            &mut self,
+            _ctx: &mut IndexContext,
+            block: &Block,
+            _height: u64,
+        ) -> anyhow::Result<()> {
+            if self.fail_on.as_ref() == Some(&block.block_id) {
+                anyhow::bail!("cannot index {}", block.block_id);
+            }
+            Ok(())
+        }
+
+        fn transaction_applied(
+            &mut self,
+            ctx: &mut IndexContext,
+            _block: &Block,
+            _height: u64,
+            tx: &AppliedTransaction,
+        ) -> anyhow::Result<()> {
+            // the balances seen by indexers match the transaction.
+            for change in &tx.changes {
+                assert!(tx.tx.accounts().contains(&&change.account));
+            }
+            Self::add(ctx, tx, 1)
+        }
+
+        fn block_disconnected(
+            &mut self,
+            ctx: &mut IndexContext,
+            _block: &Block,
+            _height: u64,
+            transactions: &[AppliedTransaction],
+        ) -> anyhow::Result<()> {
+            for tx in transactions {
+                Self::add(ctx, tx, -1)?;
+            }
+            Ok(())
+        }
+    }
+
+    fn sent(service: &LmdbService, account: &str) -> i128 {
+        let mut batch = WriteBatch::default();
+        let ctx = IndexContext::new(&service.store, &mut batch, "sent");
+        ctx.get(account).unwrap().unwrap_or(0)
+    }
+
+    #[test]
+    fn custom_indexers() {
+        let with_indexer = |indexer: SentIndexer| ServiceConfig {
+            indexers: vec![Box::new(indexer)],
+            ..Default::default()
+        };
+        let mut service = LmdbService::with_config(with_indexer(SentIndexer {
+            fail_on: Some("D".to_string()),
+        }))
+        .unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        assert_eq!((sent(&service, "Alice"), sent(&service, "Bob")), (5, 5));
+        // the reorg to C undoes B.
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        assert_eq!((sent(&service, "Alice"), sent(&service, "Bob")), (5, 3));
+        // a failing indexer fails the whole ingest.
+        assert!(service.ingest_block(&blocks::BLOCK_D).is_err());
+        assert!(!service.tree.contains("D"));
+        assert_balances(&service, anyhow::Ok(8), anyhow::Ok(2));
+
+        // the state of the indexer survives a restart.
+        let dir = service.path.clone().unwrap();
+        drop(service);
+        let mut service =
+            LmdbService::from_db_with_config(&dir, DB_NAME, with_indexer(SentIndexer::default()))
+                .unwrap();
+        assert_eq!(sent(&service, "Bob"), 3);
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        assert_eq!((sent(&service, "Alice"), sent(&service, "Bob")), (7, 3));
+
+        let config = ServiceConfig {
+            indexers: vec![
+                Box::new(SentIndexer::default()),
+                Box::new(SentIndexer::default()),
+            ],
+            ..Default::default()
+        };
+        assert!(MemoryService::with_config(config).is_err());
+    }
+
+    /// A socket recording what is written to it.
+    struct Recorder<'a>(&'a mut Vec<u8>);
+
+    impl std::io::Read for Recorder<'_> {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            Ok(0)
+        }
+    }
+
+    impl std::io::Write for Recorder<'_> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn lagging_clients_are_dropped_with_a_resume_height() {
+        use tungstenite::protocol::Role;
+        use tungstenite::{Message, WebSocket};
+
+        let mut service = MemoryService::new().unwrap();
+        let events = record_events(&mut service);
+        let mut hub = Hub::new(1);
+        let subscription = Subscription {
+            tips: true,
+            ..Subscription::default()
+        };
+        let outbox = hub.subscribe(&service, subscription).unwrap();
+        // the client reads nothing while two tips are pushed into its queue of one message.
+        for block in [&*blocks::BLOCK_A, &*blocks::BLOCK_B] {
+            service.ingest_block(block).unwrap();
+            let events = std::mem::take(&mut *events.lock().unwrap());
+            hub.dispatch(&service, &events);
+        }
+
+        let mut written = Vec::new();
+        outbox.run(WebSocket::from_raw_socket(
+            Recorder(&mut written),
+            Role::Server,
+            None,
+        ));
+        let mut client =
+            WebSocket::from_raw_socket(std::io::Cursor::new(written), Role::Client, None);
+        let mut pushes = Vec::new();
+        while let Ok(Message::Text(text)) = client.read() {
+            pushes.push(serde_json::from_str::<Push>(&text).unwrap());
+        }
+        assert_eq!(
+            pushes,
+            vec![
+                Push::TipChanged {
+                    old_tip: None,
+                    new_tip: "A".to_string(),
+                    height: 0
+                },
+                Push::Lagged {
+                    resume_from_height: 1
+                },
+            ]
+        );
+
+        // resuming from that height replays what the client missed.
+        let subscription = Subscription {
+            tips: true,
+            from_height: Some(1),
+            ..Subscription::default()
+        };
+        let outbox = hub.subscribe(&service, subscription).unwrap();
+        drop(hub);
+        let mut written = Vec::new();
+        outbox.run(WebSocket::from_raw_socket(
+            Recorder(&mut written),
+            Role::Server,
+            None,
+        ));
+        let mut client =
+            WebSocket::from_raw_socket(std::io::Cursor::new(written), Role::Client, None);
+        let Message::Text(text) = client.read().unwrap() else {
+            panic!("expected a push");
+        };
+        assert_eq!(
+            serde_json::from_str::<Push>(&text).unwrap(),
+            Push::TipChanged {
+                old_tip: Some("A".to_string()),
+                new_tip: "B".to_string(),
+                height: 1
+            }
+        );
+    }
+
+    #[test]
+    fn failing_clients_are_dropped_alone() {
+        use tungstenite::protocol::frame::coding::CloseCode;
+        use tungstenite::protocol::Role;
+        use tungstenite::{Message, WebSocket};
+
+        let mut service = MemoryService::new().unwrap();
+        let events = record_events(&mut service);
+        let mut hub = Hub::new(8);
+        let tips = Subscription {
+            tips: true,
+            ..Subscription::default()
+        };
+        let confirmations = Subscription {
+            tips: true,
+            transactions: vec!["C0".to_string()],
+            ..Subscription::default()
+        };
+        let healthy = hub.subscribe(&service, tips).unwrap();
+        let failing = hub.subscribe(&service, confirmations).unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        hub.dispatch(&service, &std::mem::take(&mut *events.lock().unwrap()));
+        // the confirmations of C0 can no longer be read.
+        let mut batch = WriteBatch::default();
+        batch.put(Table::Transactions, "C0", &42).unwrap();
+        service.store.commit(batch).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        hub.dispatch(&service, &std::mem::take(&mut *events.lock().unwrap()));
+        drop(hub);
+
+        let read = |outbox: crate::ws::Outbox| {
+            let mut written = Vec::new();
+            outbox.run(WebSocket::from_raw_socket(
+                Recorder(&mut written),
+                Role::Server,
+                None,
+            ));
+            let mut client =
+                WebSocket::from_raw_socket(std::io::Cursor::new(written), Role::Client, None);
+            let mut tips = Vec::new();
+            loop {
+                match client.read().unwrap() {
+                    Message::Text(text) => match serde_json::from_str(&text).unwrap() {
+                        Push::TipChanged { new_tip, .. } => tips.push(new_tip),
+                        push => panic!("unexpected push {:?}", push),
+                    },
+                    Message::Close(frame) => return (tips, frame.unwrap().code),
+                    message => panic!("unexpected message {:?}", message),
+                }
+            }
+        };
+        assert_eq!(
+            read(healthy),
+            (vec!["A".to_string(), "B".to_string()], CloseCode::Away)
+        );
+        assert_eq!(read(failing), (vec!["A".to_string()], CloseCode::Error));
+    }
+
+    fn fork_tree() -> MemoryService {
+        fork_tree_with(ServiceConfig::default())
+    }
+
+    fn fork_tree_with(config: ServiceConfig) -> MemoryService {
+        let mut service = MemoryService::with_config(config).unwrap();
+        for block in [
+            &*blocks::BLOCK_A,
+            &*blocks::BLOCK_B,
+            &*blocks::BLOCK_C,
+            &*blocks::BLOCK_D,
+        ] {
+            service.ingest_block(block).unwrap();
+        }
+        let mut orphan = blocks::BLOCK_E.clone();
+        orphan.parent_id = Some("X".to_string());
+        service.ingest_block(&orphan).unwrap();
+        service
+    }
+
+    #[test]
+    fn fork_trees_are_drawn_as_ascii_and_json() {
+        let mut service = fork_tree_with(ServiceConfig {
+            validity_policy: ValidityPolicy::RejectBlock,
+            ..ServiceConfig::default()
+        });
+        let mut rejected = empty_block("F", "B");
+        rejected.transactions = blocks::BLOCK_B.transactions.clone();
+        assert!(service.ingest_block(&rejected).is_err());
+        let mut tree = ForkTree::of_service(&service, None).unwrap();
+        tree.highlight = Some("B".to_string());
+        assert_eq!(
+            tree.render(TreeFormat::Ascii),
+            "A *\n├─ B <- focus\n└─ C *\n   D *\n\
+             E pending, waiting for X\n\
+             F rejected: transaction B0 was already included"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&tree.render(TreeFormat::Json)).unwrap();
+        assert_eq!(json["canonical_tip"], "D");
+        let statuses: Vec<(&str, &str)> = json["nodes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|node| {
+                (
+                    node["block_id"].as_str().unwrap(),
+                    node["status"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("A", "connected"),
+                ("B", "connected"),
+                ("C", "connected"),
+                ("D", "connected"),
+                ("E", "pending"),
+                ("X", "missing"),
+                ("F", "rejected"),
+            ]
+        );
+        assert_eq!(json["nodes"][4]["transactions"], 2);
+        assert_eq!(
+            json["edges"][2],
+            serde_json::json!({"from": "C", "to": "D", "canonical": true})
+        );
+        assert_eq!(json["edges"].as_array().unwrap().len(), 5);
+        let parsed: ForkTree = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, tree);
+    }
+
+    #[test]
+    fn fork_trees_are_drawn_as_dot() {
+        let tree = ForkTree::of_service(&fork_tree(), None).unwrap();
+        let dot = tree.render(TreeFormat::Dot);
+        assert!(dot.starts_with("digraph blocks {\n"));
+        assert!(dot.ends_with("}\n"));
+        for line in [
+            "  \"A\" [label=\"A\\nheight 0, 2 txs\", style=bold, color=blue];",
+            "  \"B\" [label=\"B\\nheight 1, 1 txs\"];",
+            "  \"D\" [label=\"D\\nheight 2, 1 txs\", style=\"bold,filled\", color=blue, fillcolor=lightblue];",
+            "  \"E\" [label=\"E\\npending, 2 txs\", style=dashed, color=orange];",
+            "  \"X\" [label=\"X\\nmissing\", shape=ellipse, style=dotted, color=gray];",
+            "  \"A\" -> \"B\";",
+            "  \"A\" -> \"C\" [style=bold, color=blue];",
+            "  \"X\" -> \"E\" [style=dashed, color=orange];",
+        ] {
+            assert!(dot.contains(&format!("{}\n", line)), "{} not in\n{}", line, dot);
+        }
+    }
+
+    #[test]
+    fn fork_trees_indent_only_forks() {
+        let node = |block_id: &str, parent_id: Option<&str>| TreeNode {
+            block_id: block_id.to_string(),
+            parent_id: parent_id.map(str::to_string),
+            status: NodeStatus::Connected,
+            height: None,
+            transactions: 0,
+            canonical: false,
+            tip: false,
+            error: None,
+        };
+        let edge = |from: &str, to: &str| TreeEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+            canonical: false,
+        };
+        let mut tree = ForkTree {
+            canonical_tip: None,
+            nodes: vec![
+                node("A", None),
+                node("B", Some("A")),
+                node("C", Some("B")),
+                node("D", Some("B")),
+                node("E", Some("C")),
+            ],
+            edges: vec![
+                edge("A", "B"),
+                edge("B", "C"),
+                edge("B", "D"),
+                edge("C", "E"),
+            ],
+            highlight: None,
+        };
+        assert_eq!(tree.to_ascii(), "A\nB\n├─ C\n│  E\n└─ D");
+
+        // a long chain is drawn in one column, without recursing per block.
+        tree.nodes = vec![node("0", None)];
+        tree.edges.clear();
+        for height in 1..20_000 {
+            let parent = (height - 1).to_string();
+            tree.nodes.push(node(&height.to_string(), Some(&parent)));
+            tree.edges.push(edge(&parent, &height.to_string()));
+        }
+        let ascii = tree.to_ascii();
+        assert_eq!(ascii.lines().count(), 20_000);
+        assert_eq!(ascii.lines().last(), Some("19999"));
+    }
+
+    #[test]
+    fn dot_ids_are_escaped() {
+        let mut service = MemoryService::new().unwrap();
+        let genesis = Block {
+            block_id: "x\\".to_string(),
+            parent_id: None,
+            transactions: Vec::new(),
+            producer: None,
+        };
+        service.ingest_block(&genesis).unwrap();
+        service.ingest_block(&empty_block("y\"z", "x\\")).unwrap();
+        let dot = ForkTree::of_service(&service, None)
+            .unwrap()
+            .render(TreeFormat::Dot);
+        for line in [
+            r#"  "x\\" [label="x\\\nheight 0, 0 txs", style=bold, color=blue];"#,
+            r#"  "x\\" -> "y\"z" [style=bold, color=blue];"#,
+        ] {
+            assert!(
+                dot.contains(&format!("{}\n", line)),
+                "{} not in\n{}",
+                line,
+                dot
+            );
+        }
+    }
+
+    #[test]
+    fn fork_trees_can_stop_below_the_tip() {
+        let service = fork_tree();
+        let tree = ForkTree::of_service(&service, Some(1)).unwrap();
+        assert_eq!(
+            tree.to_ascii(),
+            "… B\n… C *\n  D *\nE pending, waiting for X"
+        );
+        assert!(tree.edges.iter().all(|edge| edge.from != "A"));
+        let tree = ForkTree::of_service(&service, Some(0)).unwrap();
+        assert_eq!(tree.to_ascii(), "… D *\nE pending, waiting for X");
+        assert_eq!(
+            ForkTree::of_service(&MemoryService::new().unwrap(), Some(3))
+                .unwrap()
+                .to_ascii(),
+            "no blocks"
+        );
+    }
+
+    fn burn(tx_id: &str, from: &str, amount: u64) -> Transaction {
+        Transaction::Burn {
+            tx_id: tx_id.to_string(),
+            from: from.to_string(),
+            amount,
+            asset: None,
+        }
+    }
+
+    #[test]
+    fn burns_leave_the_supply() {
+        let mut service = MemoryService::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        let mut block = empty_block("F", "A");
+        block.transactions = vec![burn("F0", "Alice", 3), burn("F1", "Bob", 9)];
+        let report = service.ingest_block(&block).unwrap();
+        assert_eq!(report.transactions[0].status, TxStatus::Applied);
+        assert_eq!(
+            report.transactions[1].status,
+            TxStatus::Rejected(IngestError::InsufficientBalance {
+                tx_id: "F1".to_string(),
+                account: "Bob".to_string(),
+                balance: 5,
+                amount: 9,
+            })
+        );
+        assert_balances(&service, Ok(2), Ok(5));
+        let history = service
+            .get_account_history("Alice", &HistoryQuery::default())
+            .unwrap();
+        assert_eq!(history.entries[0].tx_id, "F0");
+        assert_eq!(history.entries[0].counterparty, None);
+        assert_eq!(history.entries[0].delta, -3);
+
+        let supply = service.get_supply(NATIVE_ASSET).unwrap();
+        assert_eq!(
+            supply,
+            Supply {
+                minted: 10,
+                burned: 3
+            }
+        );
+        assert_eq!(supply.circulating(), 7);
+        assert_eq!(
+            service
+                .get_supply_at_height(NATIVE_ASSET, 0)
+                .unwrap()
+                .circulating(),
+            10
+        );
+        assert!(service.get_supply_at_height(NATIVE_ASSET, 2).is_err());
+
+        // a longer fork without the burn takes over the canonical supply.
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        let mut block = empty_block("G", "B");
+        block.transactions = vec![
+            Transaction::Mint {
+                tx_id: "G0".to_string(),
+                to: "Bob".to_string(),
+                amount: 4,
+                asset: None,
+            },
+            burn("G1", "Alice", 10),
+        ];
+        service.ingest_block(&block).unwrap();
+        assert_eq!(service.canonical_tip().unwrap(), "G");
+        assert_eq!(
+            service.get_supply(NATIVE_ASSET).unwrap(),
+            Supply {
+                minted: 14,
+                burned: 10
+            }
+        );
+        assert_eq!(
+            service.get_supply_at_height(NATIVE_ASSET, 1).unwrap(),
+            Supply {
+                minted: 10,
+                burned: 0
+            }
+        );
+        assert_eq!(service.tree.get("F").unwrap().supply.circulating(), 7);
+        assert_eq!(
+            MemoryService::new()
+                .unwrap()
+                .get_supply(NATIVE_ASSET)
+                .unwrap(),
+            Supply::default()
+        );
+    }
+
+    fn gold(tx: Transaction) -> Transaction {
+        let gold = Some("GOLD".to_string());
+        match tx {
+            Transaction::Mint {
+                tx_id, to, amount, ..
+            } => Transaction::Mint {
+                tx_id,
+                to,
+                amount,
+                asset: gold,
+            },
+            Transaction::Transfer {
+                tx_id,
+                from,
+                to,
+                amount,
+                ..
+            } => Transaction::Transfer {
+                tx_id,
+                from,
+                to,
+                amount,
+                asset: gold,
+                fee: None,
+                nonce: None,
+            },
+            Transaction::Burn {
+                tx_id,
+                from,
+                amount,
+                ..
+            } => Transaction::Burn {
+                tx_id,
+                from,
+                amount,
+                asset: gold,
+            },
+        }
+    }
+
+    #[test]
+    fn assets_have_separate_balances() {
+        let mut service = MemoryService::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        let mut block = empty_block("F", "A");
+        block.transactions = vec![
+            gold(Transaction::Mint {
+                tx_id: "F0".to_string(),
+                to: "Bob".to_string(),
+                amount: 7,
+                asset: None,
+            }),
+            gold(transfer("F1", "Bob", "Alice", 3)),
+            // Alice holds 5 of the native asset but only 3 GOLD.
+            gold(burn("F2", "Alice", 5)),
+            gold(burn("F3", "Alice", 1)),
+        ];
+        let report = service.ingest_block(&block).unwrap();
+        assert!(matches!(
+            report.transactions[2].status,
+            TxStatus::Rejected(IngestError::InsufficientBalance { balance: 3, .. })
+        ));
+        assert_balances(&service, Ok(5), Ok(5));
+        assert_eq!(service.get_balance("Alice", "GOLD").unwrap(), 2);
+        assert_eq!(service.get_balance("Bob", "GOLD").unwrap(), 4);
+        assert_eq!(service.get_balance_at_block("Bob", "GOLD", "A").unwrap(), 0);
+        assert_eq!(
+            service.get_portfolio("Alice").unwrap(),
+            [("GOLD".to_string(), 2), (NATIVE_ASSET.to_string(), 5)].into()
+        );
+        assert_eq!(
+            service.get_supply("GOLD").unwrap(),
+            Supply {
+                minted: 7,
+                burned: 1
+            }
+        );
+        assert_eq!(service.get_supply(NATIVE_ASSET).unwrap().circulating(), 10);
+        let history = service
+            .get_account_history("Alice", &HistoryQuery::default())
+            .unwrap();
+        assert_eq!(history.entries[0].asset.as_deref(), Some("GOLD"));
+        assert_eq!(history.entries[0].balance, 2);
+
+        // a reorg to a fork without GOLD empties the portfolio of it.
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        assert_eq!(service.canonical_tip().unwrap(), "D");
+        assert_eq!(service.get_balance("Bob", "GOLD").unwrap(), 0);
+        assert_eq!(
+            service.get_portfolio("Alice").unwrap(),
+            [(NATIVE_ASSET.to_string(), 6)].into()
+        );
+        assert_eq!(service.get_balance_on_fork("Bob", "GOLD", "F").unwrap(), 4);
+        assert_eq!(service.get_supply("GOLD").unwrap(), Supply::default());
+
+        // transactions of the native asset keep their JSON.
+        let json = serde_json::to_value(&blocks::BLOCK_B.transactions[0]).unwrap();
+        assert!(json["Transfer"].get("asset").is_none());
+        let json = serde_json::to_value(&block.transactions[0]).unwrap();
+        assert_eq!(json["Mint"]["asset"], "GOLD");
+    }
+
+    fn with_fee(tx: Transaction, fee: u64) -> Transaction {
+        match tx {
+            Transaction::Transfer {
+                tx_id,
+                from,
+                to,
+                amount,
+                asset,
+                ..
+            } => Transaction::Transfer {
+                tx_id,
+                from,
+                to,
+                amount,
+                asset,
+                fee: Some(fee),
+                nonce: None,
+            },
+            tx => tx,
+        }
+    }
+
+    #[test]
+    fn fees_and_rewards_go_to_the_producer() {
+        let mut service = MemoryService::with_config(ServiceConfig {
+            block_reward: RewardSchedule::Constant(1),
+            ..ServiceConfig::default()
+        })
+        .unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        let mut block = empty_block("F", "A");
+        block.producer = Some("Carol".to_string());
+        block.transactions = vec![
+            with_fee(transfer("F0", "Alice", "Bob", 2), 1),
+            // the fee counts in what Alice spends.
+            with_fee(transfer("F1", "Alice", "Bob", 2), 1),
+            gold(Transaction::Mint {
+                tx_id: "F2".to_string(),
+                to: "Bob".to_string(),
+                amount: 4,
+                asset: None,
+            }),
+            // fees are paid in the native asset whatever the transfer moves.
+            with_fee(gold(transfer("F3", "Bob", "Alice", 4)), 2),
+        ];
+        let report = service.ingest_block(&block).unwrap();
+        assert_eq!(
+            report.transactions[1].status,
+            TxStatus::Rejected(IngestError::InsufficientBalance {
+                tx_id: "F1".to_string(),
+                account: "Alice".to_string(),
+                balance: 2,
+                amount: 3,
+            })
+        );
+        assert_balances(&service, Ok(2), Ok(5));
+        assert_eq!(service.get_balance("Carol", NATIVE_ASSET).unwrap(), 4);
+        assert_eq!(service.get_balance("Alice", "GOLD").unwrap(), 4);
+        assert_eq!(
+            service.get_block_fees("F").unwrap(),
+            BlockFees { fees: 3, reward: 1 }
+        );
+        assert_eq!(
+            service.get_account_fees("Bob").unwrap(),
+            AccountFees { paid: 2, earned: 0 }
+        );
+        assert_eq!(
+            service.get_account_fees("Carol").unwrap(),
+            AccountFees { paid: 0, earned: 3 }
+        );
+        assert_eq!(service.get_supply(NATIVE_ASSET).unwrap().circulating(), 11);
+        let history = service
+            .get_account_history("Carol", &HistoryQuery::default())
+            .unwrap();
+        let entries: Vec<_> = history
+            .entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.tx_id.as_str(),
+                    entry.counterparty.as_deref(),
+                    entry.delta,
+                )
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("F/reward", None, 1),
+                ("F3", Some("Bob"), 2),
+                ("F0", Some("Alice"), 1)
+            ]
+        );
+
+        // a longer fork moves the fees and the reward out, and burns the fees of a block
+        // without producer.
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        let mut block = empty_block("G", "D");
+        block.transactions = vec![with_fee(transfer("G0", "Alice", "Bob", 1), 1)];
+        service.ingest_block(&block).unwrap();
+        assert_eq!(service.canonical_tip().unwrap(), "G");
+        assert_balances(&service, Ok(4), Ok(5));
+        assert_eq!(service.get_balance("Carol", NATIVE_ASSET).unwrap(), 0);
+        assert_eq!(
+            service.get_account_fees("Carol").unwrap(),
+            AccountFees::default()
+        );
+        assert_eq!(
+            service.get_account_fees("Alice").unwrap(),
+            AccountFees { paid: 1, earned: 0 }
+        );
+        assert_eq!(
+            service.get_supply(NATIVE_ASSET).unwrap(),
+            Supply {
+                minted: 10,
+                burned: 1
+            }
+        );
+        assert_eq!(
+            service.get_block_fees("G").unwrap(),
+            BlockFees { fees: 1, reward: 0 }
+        );
+
+        let halving = RewardSchedule::Halving {
+            initial: 8,
+            interval: 2,
+        };
+        let rewards: Vec<u64> = (0..6).map(|height| halving.reward_at(height)).collect();
+        assert_eq!(rewards, [8, 8, 4, 4, 2, 2]);
+        assert_eq!(halving.reward_at(200), 0);
+    }
+
+    fn with_nonce(tx: Transaction, nonce: u64) -> Transaction {
+        match tx {
+            Transaction::Transfer {
+                tx_id,
+                from,
+                to,
+                amount,
+                asset,
+                fee,
+                ..
+            } => Transaction::Transfer {
+                tx_id,
+                from,
+                to,
+                amount,
+                asset,
+                fee,
+                nonce: Some(nonce),
+            },
+            tx => tx,
+        }
+    }
+
+    #[test]
+    fn replays_are_rejected() {
+        let mut service = MemoryService::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        assert_eq!(service.get_next_nonce("Alice").unwrap(), 0);
+        let mut block = empty_block("F", "A");
+        block.transactions = vec![
+            with_nonce(transfer("F0", "Alice", "Bob", 1), 0),
+            // the same transfer under another id.
+            with_nonce(transfer("F1", "Alice", "Bob", 1), 0),
+            // nonces may skip values.
+            with_nonce(transfer("F2", "Alice", "Bob", 1), 5),
+            transfer("F3", "Alice", "Bob", 1),
+        ];
+        let report = service.ingest_block(&block).unwrap();
+        assert_eq!(
+            report.transactions[1].status,
+            TxStatus::Rejected(IngestError::StaleNonce {
+                tx_id: "F1".to_string(),
+                account: "Alice".to_string(),
+                nonce: 0,
+                expected: 1,
+            })
+        );
+        assert_eq!(report.transactions[2].status, TxStatus::Applied);
+        assert_balances(&service, Ok(2), Ok(8));
+        assert_eq!(service.get_next_nonce("Alice").unwrap(), 6);
+        assert_eq!(service.get_next_nonce("Bob").unwrap(), 0);
+
+        // transactions already applied on the chain are replays.
+        let mut block = empty_block("G", "F");
+        block.transactions = vec![
+            blocks::BLOCK_A.transactions[1].clone(),
+            with_nonce(transfer("F0", "Alice", "Bob", 1), 9),
+            // a rejected transaction may be included again.
+            with_nonce(transfer("F1", "Alice", "Bob", 1), 6),
+        ];
+        let report = service.ingest_block(&block).unwrap();
+        let statuses: Vec<_> = report.transactions.iter().map(|tx| &tx.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                &TxStatus::Rejected(IngestError::DuplicateTransaction("A0".to_string())),
+                &TxStatus::Rejected(IngestError::DuplicateTransaction("F0".to_string())),
+                &TxStatus::Applied,
+            ]
+        );
+        assert_eq!(service.get_next_nonce("Alice").unwrap(), 7);
+
+        // another fork may apply the same transaction, and takes the nonces along.
+        let mut block = empty_block("H", "A");
+        block.transactions = vec![with_nonce(transfer("F0", "Alice", "Bob", 1), 0)];
+        assert_eq!(
+            service.ingest_block(&block).unwrap().transactions[0].status,
+            TxStatus::Applied
+        );
+        service.ingest_block(&empty_block("I", "H")).unwrap();
+        service.ingest_block(&empty_block("J", "I")).unwrap();
+        assert_eq!(service.canonical_tip().unwrap(), "J");
+        assert_eq!(service.get_next_nonce("Alice").unwrap(), 1);
+        let applied: Vec<_> = service
+            .get_transaction("F0")
+            .unwrap()
+            .into_iter()
+            .filter(|inclusion| inclusion.status == TxStatus::Applied)
+            .map(|inclusion| inclusion.block_id)
+            .collect();
+        assert_eq!(applied, ["F", "H"]);
+        let mut block = empty_block("K", "G");
+        block.transactions = vec![with_nonce(transfer("K0", "Alice", "Bob", 1), 6)];
+        assert!(matches!(
+            service.ingest_block(&block).unwrap().transactions[0].status,
+            TxStatus::Rejected(IngestError::StaleNonce { expected: 7, .. })
+        ));
+        // replays are found both among the blocks of a fork and on the canonical chain below it.
+        assert_eq!(service.canonical_tip().unwrap(), "K");
+        for parent in ["K", "J"] {
+            let mut block = empty_block(&format!("L{parent}"), parent);
+            block.transactions = vec![
+                blocks::BLOCK_A.transactions[1].clone(),
+                transfer("F0", "Alice", "Bob", 1),
+                transfer("B0", "Bob", "Alice", 1),
+            ];
+            let report = service.ingest_block(&block).unwrap();
+            let statuses: Vec<_> = report.transactions.iter().map(|tx| &tx.status).collect();
+            assert_eq!(
+                statuses,
+                [
+                    &TxStatus::Rejected(IngestError::DuplicateTransaction("A0".to_string())),
+                    &TxStatus::Rejected(IngestError::DuplicateTransaction("F0".to_string())),
+                    &TxStatus::Applied,
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn forgotten_blocks_leave_their_descendants_pending() {
+        let mut service = LmdbService::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        service.ingest_block(&blocks::BLOCK_D).unwrap();
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        assert_eq!(service.canonical_tip().unwrap(), "D");
+
+        service.forget_block("C").unwrap();
+        assert!(!service.tree.contains("C"));
+        assert_eq!(service.canonical_tip().unwrap(), "B");
+        assert_balances(&service, anyhow::Ok(10), anyhow::Ok(0));
+        assert!(service.get_transaction("C0").unwrap().is_empty());
+        assert!(service.forget_block("C").is_err());
+        let dir = service.path.clone().unwrap();
+        drop(service);
+
+        // the storage holds the replayed state, ready for C to arrive again.
+        let mut service = LmdbService::from_db(dir.as_str(), DB_NAME).unwrap();
+        let pending = service.get_pending_blocks().unwrap();
+        assert_eq!(pending[0].block_id, "D");
+        service.ingest_block(&blocks::BLOCK_C).unwrap();
+        assert_eq!(service.canonical_tip().unwrap(), "D");
+        assert_balances(&service, anyhow::Ok(6), anyhow::Ok(4));
+
+        // the deletions and the replay are committed together, or not at all.
+        let mut service = ServiceImpl::<FaultyStorage>::new().unwrap();
+        for block in [
+            &*blocks::BLOCK_A,
+            &*blocks::BLOCK_B,
+            &*blocks::BLOCK_C,
+            &*blocks::BLOCK_D,
+        ] {
+            service.ingest_block(block).unwrap();
+        }
+        service.store.fail_commits = true;
+        assert!(service.forget_block("C").is_err());
+        service.store.fail_commits = false;
+        assert_eq!(service.canonical_tip().unwrap(), "D");
+        assert!(service.orphans.is_empty());
+        assert_balances(&service, anyhow::Ok(6), anyhow::Ok(4));
+        service.forget_block("C").unwrap();
+        assert_eq!(service.canonical_tip().unwrap(), "B");
+        assert_eq!(service.orphans.len(), 1);
+    }
+
+    #[test]
+    fn repl_completes_commands_blocks_and_accounts() {
+        let words: Vec<String> = ["A", "Alice", "B", "Bob"].map(String::from).to_vec();
+        assert_eq!(complete(&words, "ba", 2), (0, vec!["balance".to_string()]));
+        assert_eq!(
+            complete(&words, "balance A", 9),
+            (8, vec!["A".to_string(), "Alice".to_string()])
+        );
+        assert_eq!(
+            complete(&words, "ingest C B transfer:Bo", 22),
+            (20, vec!["Bob".to_string()])
+        );
+        assert_eq!(
+            complete(&words, "goto B tail", 6),
+            (5, vec!["B".to_string(), "Bob".to_string()])
+        );
+    }
+    #[test]
+    fn rejected_blocks_are_remembered() {
+        let mut service = LmdbService::new().unwrap();
+        service.ingest_block(&blocks::BLOCK_A).unwrap();
+        let mut malformed = empty_block("F", "A");
+        malformed.transactions = vec![transfer("", &blocks::ALICE, &blocks::BOB, 1)];
+        assert!(service.ingest_block(&malformed).is_err());
+        // a block without an id cannot be told apart, nor can a duplicate.
+        assert!(service.ingest_block(&empty_block("", "A")).is_err());
+        assert!(service.ingest_block(&blocks::BLOCK_A).is_err());
+        let rejected = vec![RejectedBlock {
+            block_id: "F".to_string(),
+            parent_id: Some("A".to_string()),
+            transactions: 1,
+            error: IngestError::MalformedBlock {
+                block_id: "F".to_string(),
+                reason: "empty transaction id".to_string(),
+            },
+        }];
+        assert_eq!(service.get_rejected_blocks().unwrap(), rejected);
+
+        let dir = service.path.clone().unwrap();
+        drop(service);
+        let mut service = LmdbService::from_db(dir.as_str(), DB_NAME).unwrap();
+        assert_eq!(service.get_rejected_blocks().unwrap(), rejected);
+        service.ingest_block(&blocks::BLOCK_B).unwrap();
+        service.forget_block("B").unwrap();
+        assert_eq!(service.get_rejected_blocks().unwrap(), rejected);
+        // a valid block under the same id takes its place.
+        service.ingest_block(&empty_block("F", "A")).unwrap();
+        assert!(service.get_rejected_blocks().unwrap().is_empty());
+    }
 }
@@ -0,0 +1,281 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::fees::BlockFees;
+use crate::state::Supply;
+
+use crate::{AssetID, Block, BlockID, NATIVE_ASSET};
+
+/// The position of a block in the block tree. The block itself is kept in the store.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BlockNode {
+    pub block_id: BlockID,
+    pub parent_id: Option<BlockID>,
+    /// Stored separately in the children index.
+    #[serde(skip)]
+    pub children: Vec<BlockID>,
+    /// Distance from the genesis block, which has height 0.
+    pub height: u64,
+    /// Order in which the block was inserted into the tree.
+    pub seq: u64,
+    /// Number of transactions from the genesis block up to and including this block.
+    pub cumulative_txs: u64,
+    /// Sum of the fork-choice weights from the genesis block up to and including this block.
+    pub cumulative_weight: u64,
+    /// Coins of the native asset minted and burned from the genesis block up to and including
+    /// this block. Zero in headers stored before the supply was tracked.
+    #[serde(default)]
+    pub supply: Supply,
+    /// The same for the other assets, by asset.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub asset_supply: BTreeMap<AssetID, Supply>,
+    /// Fees paid in this block and the reward of its producer.
+    #[serde(default)]
+    pub fees: BlockFees,
+    /// Number of blocks in the subtree rooted at this block, itself included. Only kept once
+    /// `BlockTree::track_subtree_sizes` is called, zero otherwise.
+    #[serde(skip)]
+    pub subtree_size: u64,
+}
+
+impl BlockNode {
+    pub fn id(&self) -> &BlockID {
+        &self.block_id
+    }
+
+    pub fn parent_id(&self) -> Option<&BlockID> {
+        self.parent_id.as_ref()
+    }
+
+    /// Coins of an asset minted and burned up to this block.
+    pub fn supply_of(&self, asset: &str) -> Supply {
+        if asset == NATIVE_ASSET {
+            self.supply
+        } else {
+            self.asset_supply.get(asset).copied().unwrap_or_default()
+        }
+    }
+}
+
+/// A tree of blocks indexed by block id. Forks share their common ancestry.
+#[derive(Default, Debug)]
+pub struct BlockTree {
+    nodes: HashMap<BlockID, BlockNode>,
+    roots: BTreeSet<BlockID>,
+    tips: BTreeSet<BlockID>,
+    next_seq: u64,
+    /// Whether inserting a block bumps the subtree sizes of its ancestors.
+    subtree_sizes: bool,
+}
+
+impl BlockTree {
+    /// Rebuild the tree from stored nodes whose children are already filled in.
+    pub fn from_nodes(nodes: impl IntoIterator<Item = BlockNode>) -> Self {
+        let nodes: HashMap<BlockID, BlockNode> = nodes
+            .into_iter()
+            .map(|node| (node.block_id.clone(), node))
+            .collect();
+        let roots = nodes
+            .values()
+            .filter(|node| node.parent_id.is_none())
+            .map(|node| node.block_id.clone())
+            .collect();
+        let tips = nodes
+            .values()
+            .filter(|node| node.children.is_empty())
+            .map(|node| node.block_id.clone())
+            .collect();
+        let next_seq = nodes.values().map(|node| node.seq + 1).max().unwrap_or(0);
+        Self {
+            nodes,
+            roots,
+            tips,
+            next_seq,
+            subtree_sizes: false,
+        }
+    }
+
+    /// Count the subtree of every block and keep the counts up to date from now on. Each insert
+    /// then walks up to the genesis block, so only fork choices comparing subtrees ask for it.
+    pub fn track_subtree_sizes(&mut self) {
+        // count the subtrees with children before their parents.
+        let mut ids: Vec<(u64, BlockID)> = self
+            .nodes
+            .values()
+            .map(|node| (node.height, node.block_id.clone()))
+            .collect();
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        for (_, id) in ids {
+            let size = 1 + self.nodes[&id]
+                .children
+                .iter()
+                .filter_map(|child| self.nodes.get(child))
+                .map(|child| child.subtree_size)
+                .sum::<u64>();
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.subtree_size = size;
+            }
+        }
+        self.subtree_sizes = true;
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.nodes.contains_key(id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&BlockNode> {
+        self.nodes.get(id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &BlockNode> {
+        self.nodes.values()
+    }
+
+    /// Genesis blocks, i.e. the roots of every tree.
+    pub fn roots(&self) -> impl Iterator<Item = &BlockNode> {
+        self.roots.iter().map(|id| &self.nodes[id])
+    }
+
+    pub fn children<'a>(&'a self, id: &str) -> impl Iterator<Item = &'a BlockNode> {
+        self.nodes
+            .get(id)
+            .into_iter()
+            .flat_map(|node| node.children.iter())
+            .map(|child| &self.nodes[child])
+    }
+
+    /// Leaf blocks, i.e. the tips of every fork.
+    pub fn tips(&self) -> impl Iterator<Item = &BlockNode> {
+        self.tips.iter().map(|id| &self.nodes[id])
+    }
+
+    pub fn is_tip(&self, id: &str) -> bool {
+        self.tips.contains(id)
+    }
+
+    /// Insert a block whose parent is already in the tree (or which is a genesis block).
+    /// Returns `None` if the block is already known or its parent is missing. `supply` counts
+    /// the coins minted and burned by the block itself, by asset.
+    pub fn insert(
+        &mut self,
+        block: &Block,
+        weight: u64,
+        supply: &BTreeMap<AssetID, Supply>,
+        fees: BlockFees,
+    ) -> Option<&BlockNode> {
+        if self.contains(&block.block_id) {
+            return None;
+        }
+        let (height, parent_txs, parent_weight, mut native_supply, mut asset_supply) =
+            match &block.parent_id {
+                Some(parent_id) => {
+                    let parent = self.nodes.get_mut(parent_id)?;
+                    parent.children.push(block.block_id.clone());
+                    self.tips.remove(parent_id);
+                    (
+                        parent.height + 1,
+                        parent.cumulative_txs,
+                        parent.cumulative_weight,
+                        parent.supply,
+                        parent.asset_supply.clone(),
+                    )
+                }
+                None => {
+                    self.roots.insert(block.block_id.clone());
+                    (0, 0, 0, Supply::default(), BTreeMap::new())
+                }
+            };
+        for (asset, block_supply) in supply {
+            let total = if asset == NATIVE_ASSET {
+                &mut native_supply
+            } else {
+                asset_supply.entry(asset.clone()).or_default()
+            };
+            *total = *total + *block_supply;
+        }
+        let node = BlockNode {
+            block_id: block.block_id.clone(),
+            parent_id: block.parent_id.clone(),
+            children: Vec::new(),
+            height,
+            seq: self.next_seq,
+            cumulative_txs: parent_txs + block.transactions.len() as u64,
+            cumulative_weight: parent_weight.saturating_add(weight),
+            supply: native_supply,
+            asset_supply,
+            fees,
+            subtree_size: u64::from(self.subtree_sizes),
+        };
+        if self.subtree_sizes {
+            let mut ancestor = block.parent_id.clone();
+            while let Some(node) = ancestor.and_then(|id| self.nodes.get_mut(&id)) {
+                node.subtree_size += 1;
+                ancestor = node.parent_id.clone();
+            }
+        }
+        self.next_seq += 1;
+        self.tips.insert(block.block_id.clone());
+        Some(self.nodes.entry(block.block_id.clone()).or_insert(node))
+    }
+
+    /// Iterate from the given block up to its genesis block.
+    pub fn ancestors<'a>(&'a self, id: &str) -> impl Iterator<Item = &'a BlockNode> {
+        std::iter::successors(self.nodes.get(id), move |node| {
+            node.parent_id().and_then(|parent| self.nodes.get(parent))
+        })
+    }
+
+    /// Whether `ancestor` is the given block or one of its ancestors.
+    pub fn is_ancestor(&self, ancestor: &str, id: &str) -> bool {
+        let Some(height) = self.get(ancestor).map(|node| node.height) else {
+            return false;
+        };
+        self.ancestors(id)
+            .take_while(|node| node.height >= height)
+            .any(|node| node.block_id == ancestor)
+    }
+
+    /// The chain of blocks from the genesis block down to the given block.
+    pub fn path(&self, id: &str) -> Vec<&BlockNode> {
+        let mut path: Vec<&BlockNode> = self.ancestors(id).collect();
+        path.reverse();
+        path
+    }
+
+    /// The blocks to disconnect and connect to move from the tip `from` to the tip `to`.
+    /// Disconnected blocks are listed from `from` downwards, connected blocks from the fork
+    /// point upwards to `to`.
+    pub fn route(&self, from: Option<&str>, to: &str) -> (Vec<BlockID>, Vec<BlockID>) {
+        let mut disconnect = Vec::new();
+        let mut connect = Vec::new();
+        let mut from = from.and_then(|id| self.get(id));
+        let mut to = self.get(to);
+        loop {
+            match (from, to) {
+                (Some(a), Some(b)) if a.id() == b.id() => break,
+                (Some(a), Some(b)) if a.height >= b.height => {
+                    disconnect.push(a.id().clone());
+                    from = a.parent_id().and_then(|id| self.get(id));
+                }
+                (_, Some(b)) => {
+                    connect.push(b.id().clone());
+                    to = b.parent_id().and_then(|id| self.get(id));
+                }
+                (Some(a), None) => {
+                    disconnect.push(a.id().clone());
+                    from = a.parent_id().and_then(|id| self.get(id));
+                }
+                (None, None) => break,
+            }
+        }
+        connect.reverse();
+        (disconnect, connect)
+    }
+}
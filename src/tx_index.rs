@@ -0,0 +1,34 @@
+use crate::ingest::TxStatus;
+use crate::BlockID;
+
+/// A block including a transaction, as persisted in the transaction index.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TxLocation {
+    pub block_id: BlockID,
+    /// Position of the transaction in the block.
+    pub index: usize,
+    /// Whether the transaction applied when the block was connected.
+    pub status: TxStatus,
+}
+
+/// A block including a transaction, seen from the current canonical chain.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TxInclusion {
+    pub block_id: BlockID,
+    pub height: u64,
+    /// Position of the transaction in the block.
+    pub index: usize,
+    pub status: TxStatus,
+    /// Whether the block is on the canonical chain.
+    pub canonical: bool,
+    /// Number of canonical blocks from this block to the tip, including both. Zero if the
+    /// block is not canonical.
+    pub confirmations: u64,
+}
+
+impl TxInclusion {
+    /// Whether the transaction is on the canonical chain and changed its accounts.
+    pub fn is_applied(&self) -> bool {
+        self.canonical && self.status == TxStatus::Applied
+    }
+}
@@ -0,0 +1,500 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
+use tungstenite::{Error, Message, WebSocket};
+
+use crate::api::{ApiError, Params};
+use crate::events::ChainEvent;
+use crate::store::Storage;
+use crate::tx_index::TxInclusion;
+use crate::{AssetID, BlockID, Service, ServiceImpl, TransactionID, NATIVE_ASSET};
+
+/// How long the writer of a client waits for a frame from it before looking for new messages.
+pub(crate) const READ_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// A JSON message pushed to a WebSocket client.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Push {
+    /// The canonical tip moved. Topic `tips`.
+    TipChanged {
+        old_tip: Option<BlockID>,
+        new_tip: BlockID,
+        height: u64,
+    },
+    /// Another fork became canonical. Topic `reorgs`.
+    Reorg {
+        old_tip: BlockID,
+        new_tip: BlockID,
+        fork_point: Option<BlockID>,
+        depth: u64,
+        height: u64,
+    },
+    /// The balance of an account at the canonical tip changed. Topic `account`. `asset` is
+    /// only named for the assets other than the native one.
+    BalanceChanged {
+        account: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        asset: Option<AssetID>,
+        previous: u64,
+        balance: u64,
+        block_id: BlockID,
+        height: u64,
+    },
+    /// The number of confirmations of a transaction changed. `block_id` is the canonical
+    /// block including it, `None` once a reorg dropped it. Topic `tx`.
+    Confirmations {
+        tx_id: TransactionID,
+        block_id: Option<BlockID>,
+        confirmations: u64,
+        height: u64,
+    },
+    /// The client fell too far behind and is disconnected. Reconnecting with
+    /// `from_height=resume_from_height` replays what it missed.
+    Lagged { resume_from_height: u64 },
+}
+
+impl Push {
+    /// Height of the canonical tip when the message was produced.
+    pub fn height(&self) -> u64 {
+        match self {
+            Push::TipChanged { height, .. }
+            | Push::Reorg { height, .. }
+            | Push::BalanceChanged { height, .. }
+            | Push::Confirmations { height, .. } => *height,
+            Push::Lagged { resume_from_height } => *resume_from_height,
+        }
+    }
+}
+
+/// A balance followed by a WebSocket client, `account=ACCOUNT[:ASSET]` in its URL.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AccountTopic {
+    pub account: String,
+    /// `None` for the native asset.
+    pub asset: Option<AssetID>,
+}
+
+impl AccountTopic {
+    fn parse(value: &str) -> Self {
+        match value.split_once(':') {
+            Some((account, asset)) if asset != NATIVE_ASSET => AccountTopic {
+                account: account.to_string(),
+                asset: Some(asset.to_string()),
+            },
+            Some((account, _)) => AccountTopic {
+                account: account.to_string(),
+                asset: None,
+            },
+            None => AccountTopic {
+                account: value.to_string(),
+                asset: None,
+            },
+        }
+    }
+
+    fn asset(&self) -> &str {
+        self.asset.as_deref().unwrap_or(NATIVE_ASSET)
+    }
+
+    fn changed(&self, previous: u64, balance: u64, block_id: &str, height: u64) -> Push {
+        Push::BalanceChanged {
+            account: self.account.clone(),
+            asset: self.asset.clone(),
+            previous,
+            balance,
+            block_id: block_id.to_string(),
+            height,
+        }
+    }
+}
+
+/// The topics of a WebSocket client, from the query of its `/subscribe` URL, e.g.
+/// `/subscribe?tips&reorgs&account=Alice&account=Bob:GOLD&tx=C0&from_height=3`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Subscription {
+    pub tips: bool,
+    pub reorgs: bool,
+    pub accounts: Vec<AccountTopic>,
+    pub transactions: Vec<TransactionID>,
+    /// Replay the canonical chain from this height before pushing new events.
+    pub from_height: Option<u64>,
+}
+
+impl Subscription {
+    pub(crate) fn from_query(query: &Params) -> Result<Self, ApiError> {
+        let mut subscription = Subscription::default();
+        for (key, value) in query {
+            match key.as_str() {
+                "tips" => subscription.tips = true,
+                "reorgs" => subscription.reorgs = true,
+                "account" => subscription.accounts.push(AccountTopic::parse(value)),
+                "tx" => subscription.transactions.push(value.clone()),
+                "from_height" => {
+                    let height = value
+                        .parse()
+                        .map_err(|_| ApiError::bad_request(format!("invalid height {}", value)))?;
+                    subscription.from_height = Some(height);
+                }
+                _ => return Err(ApiError::bad_request(format!("unknown parameter {}", key))),
+            }
+        }
+        if !subscription.tips
+            && !subscription.reorgs
+            && subscription.accounts.is_empty()
+            && subscription.transactions.is_empty()
+        {
+            return Err(ApiError::bad_request("no topic to subscribe to"));
+        }
+        Ok(subscription)
+    }
+}
+
+/// Why the server dropped a client, told to it before closing its socket.
+#[derive(Debug, Clone, Copy)]
+enum Dropped {
+    /// Its queue was full; it can resume from this height.
+    Lagged(u64),
+    /// Its topics could not be read from the storage.
+    Failed,
+}
+
+/// A connected client, with the last state pushed to it.
+struct Client {
+    subscription: Subscription,
+    balances: HashMap<AccountTopic, u64>,
+    confirmations: HashMap<TransactionID, (Option<BlockID>, u64)>,
+    sender: SyncSender<Push>,
+    /// Set when the client is dropped by the server rather than disconnected.
+    dropped: Arc<Mutex<Option<Dropped>>>,
+}
+
+impl Client {
+    /// Push the changes of the balances and confirmations the client follows.
+    fn refresh<S: Storage>(
+        &mut self,
+        service: &ServiceImpl<S>,
+        pushes: &mut Vec<Push>,
+    ) -> anyhow::Result<()> {
+        let Some((tip, height)) = canonical_tip(service) else {
+            return Ok(());
+        };
+        for topic in &self.subscription.accounts {
+            let balance = service.get_balance(&topic.account, topic.asset())?;
+            let previous = self.balances.insert(topic.clone(), balance).unwrap_or(0);
+            if balance != previous {
+                pushes.push(topic.changed(previous, balance, &tip, height));
+            }
+        }
+        for tx_id in &self.subscription.transactions {
+            let current = confirmations_of(service, tx_id)?;
+            if self.confirmations.insert(tx_id.clone(), current.clone()) != Some(current.clone()) {
+                pushes.push(Push::Confirmations {
+                    tx_id: tx_id.clone(),
+                    block_id: current.0,
+                    confirmations: current.1,
+                    height,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue messages, returning whether the client is still connected and keeping up.
+    fn deliver(&mut self, pushes: Vec<Push>) -> bool {
+        for push in pushes {
+            let height = push.height();
+            match self.sender.try_send(push) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    self.drop_with(Dropped::Lagged(height));
+                    return false;
+                }
+                Err(TrySendError::Disconnected(_)) => return false,
+            }
+        }
+        true
+    }
+
+    fn drop_with(&self, reason: Dropped) {
+        *self
+            .dropped
+            .lock()
+            .expect("the drop reason is never poisoned") = Some(reason);
+    }
+
+    /// Push the confirmations of the transactions on the canonical chain.
+    fn replay_confirmations<S: Storage>(
+        &mut self,
+        service: &ServiceImpl<S>,
+        pushes: &mut Vec<Push>,
+    ) -> anyhow::Result<()> {
+        let Some((_, height)) = canonical_tip(service) else {
+            return Ok(());
+        };
+        for tx_id in &self.subscription.transactions {
+            let (block_id, confirmations) = confirmations_of(service, tx_id)?;
+            self.confirmations
+                .insert(tx_id.clone(), (block_id.clone(), confirmations));
+            if block_id.is_some() {
+                pushes.push(Push::Confirmations {
+                    tx_id: tx_id.clone(),
+                    block_id,
+                    confirmations,
+                    height,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What the writer of a client sends, in order: the replay, then the live messages.
+pub(crate) struct Outbox {
+    replay: Vec<Push>,
+    receiver: Receiver<Push>,
+    dropped: Arc<Mutex<Option<Dropped>>>,
+}
+
+impl Outbox {
+    /// Write the messages of a client to its socket until it disconnects or is dropped. In
+    /// between, the socket is read to answer the pings of the client and its closing
+    /// handshake, so its reads should time out after `READ_TIMEOUT`.
+    pub(crate) fn run<T: Read + Write>(self, mut socket: WebSocket<T>) {
+        for push in self.replay {
+            if !send(&mut socket, &push) {
+                return;
+            }
+        }
+        loop {
+            match self.receiver.try_recv() {
+                Ok(push) => {
+                    if !send(&mut socket, &push) {
+                        return;
+                    }
+                    continue;
+                }
+                Err(TryRecvError::Disconnected) => break,
+                Err(TryRecvError::Empty) => {}
+            }
+            // pongs and the reply to a close frame are queued by `read` and sent by `flush`.
+            match socket.read() {
+                Ok(Message::Close(_)) => {
+                    let _ = socket.flush();
+                    return;
+                }
+                Ok(_) => {}
+                Err(Error::Io(error))
+                    if matches!(
+                        error.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) => {}
+                Err(_) => return,
+            }
+        }
+        let dropped = *self
+            .dropped
+            .lock()
+            .expect("the drop reason is never poisoned");
+        let (code, reason) = match dropped {
+            Some(Dropped::Lagged(resume_from_height)) => {
+                if !send(&mut socket, &Push::Lagged { resume_from_height }) {
+                    return;
+                }
+                (CloseCode::Again, "lagged")
+            }
+            Some(Dropped::Failed) => (CloseCode::Error, "internal error"),
+            None => (CloseCode::Away, "server stopped"),
+        };
+        let _ = socket.close(Some(CloseFrame {
+            code,
+            reason: reason.into(),
+        }));
+        let _ = socket.flush();
+    }
+}
+
+/// The WebSocket clients of a server. Each client has a queue of `buffer` messages drained by
+/// its own writer thread, so a slow client never blocks ingestion: once its queue is full it
+/// is dropped and told the height to resume from.
+#[derive(Default)]
+pub(crate) struct Hub {
+    clients: Vec<Client>,
+    buffer: usize,
+}
+
+impl Hub {
+    pub(crate) fn new(buffer: usize) -> Self {
+        Self {
+            clients: Vec::new(),
+            buffer,
+        }
+    }
+
+    /// Register a client, returning the outbox its writer drains.
+    pub(crate) fn subscribe<S: Storage>(
+        &mut self,
+        service: &ServiceImpl<S>,
+        subscription: Subscription,
+    ) -> anyhow::Result<Outbox> {
+        let mut replay = Vec::new();
+        let mut balances = HashMap::new();
+        let mut confirmations = HashMap::new();
+        match subscription.from_height {
+            Some(from_height) => {
+                // past the tip there is nothing to replay, the balances are the ones at the tip.
+                let next_height = canonical_tip(service).map_or(0, |(_, height)| height + 1);
+                let from_height = from_height.min(next_height);
+                let mut previous = match from_height.checked_sub(1) {
+                    Some(height) => service.canonical_block_at(height)?,
+                    None => None,
+                };
+                for topic in &subscription.accounts {
+                    let balance = match &previous {
+                        Some(block_id) => {
+                            service.get_balance_at_block(&topic.account, topic.asset(), block_id)?
+                        }
+                        None => 0,
+                    };
+                    balances.insert(topic.clone(), balance);
+                }
+                let mut height = from_height;
+                while let Some(block_id) = service.canonical_block_at(height)? {
+                    if subscription.tips {
+                        replay.push(Push::TipChanged {
+                            old_tip: previous.clone(),
+                            new_tip: block_id.clone(),
+                            height,
+                        });
+                    }
+                    for topic in &subscription.accounts {
+                        let balance = service.get_balance_at_block(
+                            &topic.account,
+                            topic.asset(),
+                            &block_id,
+                        )?;
+                        let previous = balances.insert(topic.clone(), balance).unwrap_or(0);
+                        if balance != previous {
+                            replay.push(topic.changed(previous, balance, &block_id, height));
+                        }
+                    }
+                    previous = Some(block_id);
+                    height += 1;
+                }
+            }
+            None => {
+                for topic in &subscription.accounts {
+                    let balance = service.get_balance(&topic.account, topic.asset())?;
+                    balances.insert(topic.clone(), balance);
+                }
+                for tx_id in &subscription.transactions {
+                    confirmations.insert(tx_id.clone(), confirmations_of(service, tx_id)?);
+                }
+            }
+        }
+        let (sender, receiver) = mpsc::sync_channel(self.buffer);
+        let dropped = Arc::new(Mutex::new(None));
+        let mut client = Client {
+            subscription,
+            balances,
+            confirmations,
+            sender,
+            dropped: dropped.clone(),
+        };
+        if client.subscription.from_height.is_some() {
+            client.replay_confirmations(service, &mut replay)?;
+        }
+        self.clients.push(client);
+        Ok(Outbox {
+            replay,
+            receiver,
+            dropped,
+        })
+    }
+
+    /// Push the chain events of an ingest to the clients subscribed to them. A client whose
+    /// topics cannot be read is dropped without affecting the others.
+    pub(crate) fn dispatch<S: Storage>(&mut self, service: &ServiceImpl<S>, events: &[ChainEvent]) {
+        let mut tips = Vec::new();
+        let mut reorgs = Vec::new();
+        for event in events {
+            match event {
+                ChainEvent::TipChanged {
+                    old_tip,
+                    new_tip,
+                    height,
+                } => tips.push(Push::TipChanged {
+                    old_tip: old_tip.clone(),
+                    new_tip: new_tip.clone(),
+                    height: *height,
+                }),
+                ChainEvent::ReorgCompleted {
+                    old_tip,
+                    new_tip,
+                    fork_point,
+                    depth,
+                } => reorgs.push(Push::Reorg {
+                    old_tip: old_tip.clone(),
+                    new_tip: new_tip.clone(),
+                    fork_point: fork_point.clone(),
+                    depth: *depth,
+                    height: service.tree.get(new_tip).map_or(0, |node| node.height),
+                }),
+                ChainEvent::BlockConnected { .. } | ChainEvent::BlockDisconnected { .. } => {}
+            }
+        }
+        if tips.is_empty() {
+            return;
+        }
+        let mut kept = Vec::with_capacity(self.clients.len());
+        for mut client in self.clients.drain(..) {
+            let mut pushes = Vec::new();
+            if client.subscription.tips {
+                pushes.extend(tips.iter().cloned());
+            }
+            if client.subscription.reorgs {
+                pushes.extend(reorgs.iter().cloned());
+            }
+            if let Err(error) = client.refresh(service, &mut pushes) {
+                eprintln!("dropped a WebSocket client: {:#}", error);
+                client.drop_with(Dropped::Failed);
+                continue;
+            }
+            if client.deliver(pushes) {
+                kept.push(client);
+            }
+        }
+        self.clients = kept;
+    }
+}
+
+/// Send a message, returning whether the client is still connected.
+fn send<T: Read + Write>(socket: &mut WebSocket<T>, push: &Push) -> bool {
+    let text = serde_json::to_string(push).expect("pushes are serializable");
+    socket.send(Message::Text(text)).is_ok()
+}
+
+fn canonical_tip<S: Storage>(service: &ServiceImpl<S>) -> Option<(BlockID, u64)> {
+    let tip = service.canonical_tip()?;
+    let node = service.tree.get(tip)?;
+    Some((tip.clone(), node.height))
+}
+
+/// The canonical block applying a transaction and its number of confirmations.
+fn confirmations_of<S: Storage>(
+    service: &ServiceImpl<S>,
+    tx_id: &str,
+) -> anyhow::Result<(Option<BlockID>, u64)> {
+    let inclusions = service.get_transaction(tx_id)?;
+    Ok(inclusions
+        .into_iter()
+        .find(TxInclusion::is_applied)
+        .map_or((None, 0), |inclusion| {
+            (Some(inclusion.block_id), inclusion.confirmations)
+        }))
+}
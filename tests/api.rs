@@ -0,0 +1,682 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::thread::JoinHandle;
+
+use blockchain_explorer::api::{open_data_dir, ApiServer, ShutdownHandle};
+use blockchain_explorer::blocks;
+use blockchain_explorer::ws::Push;
+use blockchain_explorer::{Block, LmdbService, ServiceConfig, Transaction};
+use serde_json::{json, Value};
+use tempdir::TempDir;
+use tungstenite::{Message, WebSocket};
+
+/// A server on an ephemeral port, answering requests from a background thread.
+struct TestServer {
+    addr: SocketAddr,
+    shutdown: ShutdownHandle,
+    thread: Option<JoinHandle<LmdbService>>,
+}
+
+impl TestServer {
+    fn start(data_dir: &Path) -> Self {
+        let (service, _) = open_data_dir(data_dir, ServiceConfig::default(), false).unwrap();
+        let server = ApiServer::bind("127.0.0.1:0", service).unwrap();
+        let addr = server.local_addr();
+        let shutdown = server.shutdown_handle();
+        let thread = std::thread::spawn(move || server.run());
+        Self {
+            addr,
+            shutdown,
+            thread: Some(thread),
+        }
+    }
+
+    /// Stop the server and wait for it to release the data directory.
+    fn stop(mut self) {
+        self.shutdown.shutdown();
+        self.thread.take().unwrap().join().unwrap();
+    }
+
+    fn request(&self, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(self.addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            self.addr,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn get(&self, path: &str) -> (u16, Value) {
+        self.request("GET", path, "")
+    }
+
+    fn post_block(&self, block: &Block) -> (u16, Value) {
+        self.request("POST", "/blocks", &serde_json::to_string(block).unwrap())
+    }
+
+    fn subscribe(&self, query: &str) -> WebSocket<TcpStream> {
+        let stream = TcpStream::connect(self.addr).unwrap();
+        let url = format!("ws://{}/subscribe?{}", self.addr, query);
+        tungstenite::client(url, stream).unwrap().0
+    }
+
+    fn balance(&self, path: &str) -> u64 {
+        let (status, body) = self.get(path);
+        assert_eq!(status, 200, "{}", body);
+        body["balance"].as_u64().unwrap()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            self.shutdown.shutdown();
+            let _ = thread.join();
+        }
+    }
+}
+
+fn next_push(socket: &mut WebSocket<TcpStream>) -> Push {
+    match socket.read().unwrap() {
+        Message::Text(text) => serde_json::from_str(&text).unwrap(),
+        message => panic!("unexpected message {:?}", message),
+    }
+}
+
+fn ingest_abcd(server: &TestServer) {
+    for block in [
+        &*blocks::BLOCK_A,
+        &*blocks::BLOCK_B,
+        &*blocks::BLOCK_C,
+        &*blocks::BLOCK_D,
+    ] {
+        let (status, report) = server.post_block(block);
+        assert_eq!(status, 200, "{}", report);
+    }
+}
+
+#[test]
+fn balances_blocks_and_transactions() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+
+    let (status, report) = server.post_block(&blocks::BLOCK_A);
+    assert_eq!(status, 200);
+    assert_eq!(report["block_id"], "A");
+    for block in [&*blocks::BLOCK_B, &*blocks::BLOCK_C, &*blocks::BLOCK_D] {
+        assert_eq!(server.post_block(block).0, 200);
+    }
+
+    assert_eq!(server.balance("/accounts/Alice/balance"), 6);
+    assert_eq!(server.balance("/accounts/Bob/balance"), 4);
+    assert_eq!(server.balance("/accounts/Alice/balance?block=B"), 10);
+    assert_eq!(server.balance("/accounts/Alice/balance?height=1"), 8);
+    assert_eq!(server.balance("/accounts/Bob/balance?fork=B"), 0);
+    assert_eq!(server.balance("/accounts/Carol/balance"), 0);
+    let (_, body) = server.get("/accounts/Alice/balance");
+    assert_eq!(body["block_id"], "D");
+
+    let (status, block) = server.get("/blocks/C");
+    assert_eq!(status, 200);
+    assert_eq!(block["block"]["parent_id"], "A");
+    assert_eq!(block["height"], 1);
+    assert_eq!(block["children"], json!(["D"]));
+    assert_eq!(block["canonical"], true);
+    assert_eq!(server.get("/blocks/B").1["canonical"], false);
+
+    let (status, tx) = server.get("/transactions/B0");
+    assert_eq!(status, 200);
+    assert_eq!(tx["inclusions"][0]["block_id"], "B");
+    assert_eq!(tx["inclusions"][0]["canonical"], false);
+    let (_, tx) = server.get("/transactions/C0");
+    assert_eq!(tx["inclusions"][0]["confirmations"], 2);
+}
+
+#[test]
+fn tips_and_forks() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    ingest_abcd(&server);
+
+    let (status, tips) = server.get("/chain/tips");
+    assert_eq!(status, 200);
+    assert_eq!(tips["canonical_tip"], "D");
+    let mut heights: Vec<(String, u64)> = tips["tips"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tip| {
+            let id = tip["block_id"].as_str().unwrap().to_string();
+            (id, tip["height"].as_u64().unwrap())
+        })
+        .collect();
+    heights.sort();
+    assert_eq!(heights, [("B".to_string(), 1), ("D".to_string(), 2)]);
+
+    let (status, forks) = server.get("/chain/forks");
+    assert_eq!(status, 200);
+    let fork = |tip: &str| {
+        forks
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|fork| fork["tip"] == tip)
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(
+        fork("B"),
+        json!({"tip": "B", "height": 1, "fork_point": "A", "length": 1, "canonical": false})
+    );
+    assert_eq!(
+        fork("D"),
+        json!({"tip": "D", "height": 2, "fork_point": "D", "length": 0, "canonical": true})
+    );
+}
+
+#[test]
+fn supply_is_read_like_a_balance() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    assert_eq!(
+        server.get("/chain/supply").1,
+        json!({"block_id": null, "height": null, "minted": 0, "burned": 0, "circulating": 0})
+    );
+    ingest_abcd(&server);
+    let burn = Block {
+        block_id: "F".to_string(),
+        parent_id: Some("D".to_string()),
+        transactions: vec![Transaction::Burn {
+            tx_id: "F0".to_string(),
+            from: "Bob".to_string(),
+            amount: 4,
+            asset: None,
+        }],
+        producer: None,
+    };
+    assert_eq!(server.post_block(&burn).0, 200);
+    assert_eq!(
+        server.get("/chain/supply").1,
+        json!({"block_id": "F", "height": 3, "minted": 10, "burned": 4, "circulating": 6})
+    );
+    assert_eq!(server.get("/chain/supply?fork=B").1["circulating"], 10);
+    assert_eq!(server.get("/chain/supply?height=2").1["burned"], 0);
+    assert_eq!(server.get("/chain/supply?height=9").0, 404);
+    assert_eq!(server.get("/chain/supply?block=A&height=0").0, 400);
+    assert_eq!(server.balance("/accounts/Bob/balance"), 0);
+}
+
+#[test]
+fn assets_are_queried_by_name() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    let mut block = blocks::BLOCK_A.clone();
+    block.transactions.push(Transaction::Mint {
+        tx_id: "A2".to_string(),
+        to: "Bob".to_string(),
+        amount: 3,
+        asset: Some("GOLD".to_string()),
+    });
+    assert_eq!(server.post_block(&block).0, 200);
+    assert_eq!(
+        server.get("/accounts/Bob/balance?asset=GOLD").1,
+        json!({"account": "Bob", "block_id": "A", "balance": 3, "asset": "GOLD"})
+    );
+    assert_eq!(server.balance("/accounts/Bob/balance"), 5);
+    assert_eq!(server.balance("/accounts/Alice/balance?asset=GOLD"), 0);
+    assert_eq!(
+        server.get("/accounts/Bob/portfolio").1,
+        json!({"account": "Bob", "block_id": "A", "balances": {"GOLD": 3, "native": 5}})
+    );
+    assert_eq!(server.get("/chain/supply?asset=GOLD").1["minted"], 3);
+    assert_eq!(server.request("POST", "/accounts/Bob/portfolio", "").0, 405);
+
+    let mut socket = server.subscribe("account=Bob:GOLD&account=Bob:native");
+    let mut block = blocks::BLOCK_B.clone();
+    block.transactions.push(Transaction::Transfer {
+        tx_id: "B1".to_string(),
+        from: "Bob".to_string(),
+        to: "Alice".to_string(),
+        amount: 1,
+        asset: Some("GOLD".to_string()),
+        fee: None,
+        nonce: None,
+    });
+    assert_eq!(server.post_block(&block).0, 200);
+    let bob = |asset: Option<&str>, previous, balance| Push::BalanceChanged {
+        account: "Bob".to_string(),
+        asset: asset.map(str::to_string),
+        previous,
+        balance,
+        block_id: "B".to_string(),
+        height: 1,
+    };
+    assert_eq!(next_push(&mut socket), bob(Some("GOLD"), 3, 2));
+    assert_eq!(next_push(&mut socket), bob(None, 5, 0));
+}
+
+#[test]
+fn fees_are_queried_per_block_and_account() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    server.post_block(&blocks::BLOCK_A);
+    let block = json!({
+        "block_id": "F",
+        "parent_id": "A",
+        "producer": "Carol",
+        "transactions": [
+            {"Transfer": {"tx_id": "F0", "from": "Alice", "to": "Bob", "amount": 2, "fee": 1}}
+        ]
+    });
+    assert_eq!(server.request("POST", "/blocks", &block.to_string()).0, 200);
+    let (status, info) = server.get("/blocks/F");
+    assert_eq!(status, 200);
+    assert_eq!(info["block"]["producer"], "Carol");
+    assert_eq!(info["fees"], 1);
+    assert_eq!(info["reward"], 0);
+    assert_eq!(
+        server.get("/accounts/Alice/fees").1,
+        json!({"account": "Alice", "block_id": "F", "paid": 1, "earned": 0})
+    );
+    assert_eq!(server.get("/accounts/Carol/fees").1["earned"], 1);
+    assert_eq!(server.balance("/accounts/Carol/balance"), 1);
+    assert_eq!(server.request("POST", "/accounts/Carol/fees", "").0, 405);
+}
+
+#[test]
+fn next_nonces_are_queried_per_account() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    server.post_block(&blocks::BLOCK_A);
+    let block = json!({
+        "block_id": "F",
+        "parent_id": "A",
+        "transactions": [
+            {"Transfer": {"tx_id": "F0", "from": "Alice", "to": "Bob", "amount": 2, "nonce": 4}}
+        ]
+    });
+    assert_eq!(server.request("POST", "/blocks", &block.to_string()).0, 200);
+    assert_eq!(
+        server.get("/accounts/Alice/nonce").1,
+        json!({"account": "Alice", "block_id": "F", "next_nonce": 5})
+    );
+    assert_eq!(server.get("/accounts/Bob/nonce").1["next_nonce"], 0);
+    assert_eq!(server.request("POST", "/accounts/Bob/nonce", "").0, 405);
+}
+
+#[test]
+fn account_history_pages() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    ingest_abcd(&server);
+
+    let (status, page) = server.get("/accounts/Alice/history?limit=2&direction=forward");
+    assert_eq!(status, 200);
+    let tx_ids = |page: &Value| -> Vec<String> {
+        page["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["tx_id"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(tx_ids(&page), ["A1", "A0"]);
+    let cursor = page["next_cursor"].as_str().unwrap();
+    let (status, page) = server.get(&format!(
+        "/accounts/Alice/history?limit=2&direction=forward&cursor={}",
+        cursor.replace('@', "%40")
+    ));
+    assert_eq!(status, 200);
+    assert_eq!(tx_ids(&page), ["C0", "D0"]);
+    assert_eq!(page["next_cursor"], Value::Null);
+
+    let (_, page) = server.get("/accounts/Alice/history?limit=1");
+    assert_eq!(tx_ids(&page), ["D0"]);
+    assert_eq!(page["entries"][0]["balance"], 6);
+    assert_eq!(server.get("/accounts/Alice/history?limit=0").0, 400);
+}
+
+#[test]
+fn errors_have_statuses() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    server.post_block(&blocks::BLOCK_A);
+
+    let (status, body) = server.post_block(&blocks::BLOCK_A);
+    assert_eq!(status, 422);
+    assert_eq!(body["ingest_error"], json!({"DuplicateBlock": "A"}));
+    assert_eq!(server.request("POST", "/blocks", "{").0, 400);
+    assert_eq!(server.get("/blocks/Z").0, 404);
+    assert_eq!(server.get("/transactions/Z0").0, 404);
+    assert_eq!(server.get("/accounts/Alice/balance?block=Z").0, 404);
+    assert_eq!(server.get("/accounts/Alice/balance?height=7").0, 404);
+    assert_eq!(server.get("/accounts/Alice/balance?fork=Z").0, 404);
+    assert_eq!(server.get("/accounts/Alice/balance?height=x").0, 400);
+    assert_eq!(server.get("/accounts/Alice/history?direction=up").0, 400);
+    assert_eq!(server.get("/accounts/Alice/history?cursor=0@Z").0, 409);
+    assert_eq!(server.get("/nowhere").0, 404);
+    assert_eq!(server.get("/blocks").0, 405);
+    assert_eq!(server.request("DELETE", "/blocks/A", "").0, 405);
+
+    // rejected blocks are kept and drawn with the tree.
+    let malformed = Block {
+        block_id: "F".to_string(),
+        parent_id: Some("A".to_string()),
+        transactions: vec![Transaction::Mint {
+            tx_id: String::new(),
+            to: "Bob".to_string(),
+            amount: 1,
+            asset: None,
+        }],
+        producer: None,
+    };
+    assert_eq!(server.post_block(&malformed).0, 422);
+    let (_, tree) = server.get("/chain/tree");
+    assert_eq!(tree["nodes"][1]["block_id"], "F");
+    assert_eq!(tree["nodes"][1]["status"], "rejected");
+    assert_eq!(
+        tree["edges"],
+        json!([{"from": "A", "to": "F", "canonical": false}])
+    );
+    assert_eq!(server.get("/accounts/Alice/balance?block=%+1").0, 400);
+    assert_eq!(server.get("/accounts/Al%69ce/balance").0, 200);
+    // a `+` is a space in the query string only.
+    assert_eq!(server.get("/accounts/A+B/balance").1["account"], "A+B");
+    assert_eq!(
+        server.get("/accounts/A/balance?asset=G+D").1["asset"],
+        "G D"
+    );
+    let (status, body) = server.request("POST", "/blocks", &" ".repeat((1 << 20) + 1));
+    assert_eq!(status, 413, "{}", body);
+}
+
+#[test]
+fn orphans_are_pending_until_their_parent_arrives() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+
+    let (status, report) = server.post_block(&blocks::BLOCK_B);
+    assert_eq!(status, 200);
+    assert_eq!(
+        report["status"],
+        json!({"Pending": {"missing_parent": "A"}})
+    );
+    let (_, pending) = server.get("/chain/pending");
+    assert_eq!(pending[0]["block_id"], "B");
+    let (_, tree) = server.get("/chain/tree");
+    assert_eq!(
+        tree["edges"],
+        json!([{"from": "A", "to": "B", "canonical": false}])
+    );
+    assert_eq!(tree["nodes"][1]["status"], "missing");
+    assert_eq!(server.get("/chain/tree?depth=-1").0, 400);
+
+    server.post_block(&blocks::BLOCK_A);
+    assert_eq!(server.get("/chain/pending").1, json!([]));
+    assert_eq!(server.balance("/accounts/Alice/balance"), 10);
+}
+
+#[test]
+fn data_survives_a_restart() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    ingest_abcd(&server);
+    server.stop();
+
+    let server = TestServer::start(dir.path());
+    assert_eq!(server.balance("/accounts/Alice/balance"), 6);
+    assert_eq!(server.get("/chain/tips").1["canonical_tip"], "D");
+    assert_eq!(server.post_block(&blocks::BLOCK_D).0, 422);
+}
+
+#[test]
+fn websocket_pushes_chain_events() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    let mut socket = server.subscribe("tips&reorgs&account=Bob&tx=C0");
+    ingest_abcd(&server);
+
+    let tip = |old_tip: Option<&str>, new_tip: &str, height| Push::TipChanged {
+        old_tip: old_tip.map(str::to_string),
+        new_tip: new_tip.to_string(),
+        height,
+    };
+    let bob = |previous, balance, block_id: &str, height| Push::BalanceChanged {
+        account: "Bob".to_string(),
+        asset: None,
+        previous,
+        balance,
+        block_id: block_id.to_string(),
+        height,
+    };
+    let confirmations = |confirmations, height| Push::Confirmations {
+        tx_id: "C0".to_string(),
+        block_id: Some("C".to_string()),
+        confirmations,
+        height,
+    };
+    let expected = [
+        tip(None, "A", 0),
+        bob(0, 5, "A", 0),
+        tip(Some("A"), "B", 1),
+        bob(5, 0, "B", 1),
+        // C ties with B and wins as the latest seen block.
+        tip(Some("B"), "C", 1),
+        Push::Reorg {
+            old_tip: "B".to_string(),
+            new_tip: "C".to_string(),
+            fork_point: Some("A".to_string()),
+            depth: 1,
+            height: 1,
+        },
+        bob(0, 2, "C", 1),
+        confirmations(1, 1),
+        tip(Some("C"), "D", 2),
+        bob(2, 4, "D", 2),
+        confirmations(2, 2),
+    ];
+    for push in expected {
+        assert_eq!(next_push(&mut socket), push);
+    }
+}
+
+#[test]
+fn websocket_resumes_from_a_height() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    ingest_abcd(&server);
+    let mut socket = server.subscribe("tips&account=Alice&tx=C0&from_height=1");
+
+    let replayed = [
+        Push::TipChanged {
+            old_tip: Some("A".to_string()),
+            new_tip: "C".to_string(),
+            height: 1,
+        },
+        Push::BalanceChanged {
+            account: "Alice".to_string(),
+            asset: None,
+            previous: 5,
+            balance: 8,
+            block_id: "C".to_string(),
+            height: 1,
+        },
+        Push::TipChanged {
+            old_tip: Some("C".to_string()),
+            new_tip: "D".to_string(),
+            height: 2,
+        },
+        Push::BalanceChanged {
+            account: "Alice".to_string(),
+            asset: None,
+            previous: 8,
+            balance: 6,
+            block_id: "D".to_string(),
+            height: 2,
+        },
+        Push::Confirmations {
+            tx_id: "C0".to_string(),
+            block_id: Some("C".to_string()),
+            confirmations: 2,
+            height: 2,
+        },
+    ];
+    for push in replayed {
+        assert_eq!(next_push(&mut socket), push);
+    }
+
+    // live events follow the replay.
+    let block = Block {
+        block_id: "F".to_string(),
+        parent_id: Some("D".to_string()),
+        transactions: vec![],
+        producer: None,
+    };
+    assert_eq!(server.post_block(&block).0, 200);
+    assert_eq!(
+        next_push(&mut socket),
+        Push::TipChanged {
+            old_tip: Some("D".to_string()),
+            new_tip: "F".to_string(),
+            height: 3,
+        }
+    );
+    assert_eq!(
+        next_push(&mut socket),
+        Push::Confirmations {
+            tx_id: "C0".to_string(),
+            block_id: Some("C".to_string()),
+            confirmations: 3,
+            height: 3,
+        }
+    );
+}
+
+#[test]
+fn websocket_resumes_past_the_tip_and_confirms_applied_transactions() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    ingest_abcd(&server);
+    // a height past the tip replays nothing and starts from the balances at the tip.
+    let mut socket = server.subscribe("account=Alice&tx=F0&from_height=10");
+    let alice = |previous, balance, block_id: &str, height| Push::BalanceChanged {
+        account: "Alice".to_string(),
+        asset: None,
+        previous,
+        balance,
+        block_id: block_id.to_string(),
+        height,
+    };
+    let transfer = |tx_id: &str, amount| json!({"Transfer": {"tx_id": tx_id, "from": "Alice", "to": "Bob", "amount": amount}});
+    let block = json!({
+        "block_id": "F",
+        "parent_id": "D",
+        "transactions": [transfer("F0", 100), transfer("F1", 1)]
+    });
+    assert_eq!(server.request("POST", "/blocks", &block.to_string()).0, 200);
+    // F includes F0 without applying it, so F0 has no confirmation yet.
+    assert_eq!(next_push(&mut socket), alice(6, 5, "F", 3));
+    let block = json!({"block_id": "G", "parent_id": "F", "transactions": [transfer("F0", 1)]});
+    assert_eq!(server.request("POST", "/blocks", &block.to_string()).0, 200);
+    assert_eq!(next_push(&mut socket), alice(5, 4, "G", 4));
+    assert_eq!(
+        next_push(&mut socket),
+        Push::Confirmations {
+            tx_id: "F0".to_string(),
+            block_id: Some("G".to_string()),
+            confirmations: 1,
+            height: 4,
+        }
+    );
+}
+
+#[test]
+fn websocket_clients_are_answered() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    let mut socket = server.subscribe("tips");
+    socket.send(Message::Ping(b"ping".to_vec())).unwrap();
+    assert_eq!(socket.read().unwrap(), Message::Pong(b"ping".to_vec()));
+    server.post_block(&blocks::BLOCK_A);
+    assert!(matches!(next_push(&mut socket), Push::TipChanged { .. }));
+
+    // the server answers the closing handshake of the client.
+    socket.close(None).unwrap();
+    loop {
+        match socket.read() {
+            Ok(Message::Close(_)) => {}
+            Ok(message) => panic!("unexpected message {:?}", message),
+            Err(tungstenite::Error::ConnectionClosed) => break,
+            Err(error) => panic!("unexpected error {}", error),
+        }
+    }
+}
+
+#[test]
+fn websocket_subscriptions_are_validated() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    assert_eq!(server.get("/subscribe?tips").0, 426);
+    let stream = TcpStream::connect(server.addr).unwrap();
+    let url = format!("ws://{}/subscribe", server.addr);
+    match tungstenite::client(url, stream) {
+        Err(tungstenite::HandshakeError::Failure(tungstenite::Error::Http(response))) => {
+            assert_eq!(response.status(), 400)
+        }
+        other => panic!(
+            "unexpected handshake {:?}",
+            other.map(|(_, response)| response)
+        ),
+    }
+}
+
+#[test]
+fn json_rpc_over_http() {
+    let dir = TempDir::new("api").unwrap();
+    let server = TestServer::start(dir.path());
+    let mut socket = server.subscribe("tips");
+
+    let request = json!({
+        "jsonrpc": "2.0",
+        "method": "explorer_ingestBlock",
+        "params": {"block": &*blocks::BLOCK_A},
+        "id": 1
+    });
+    let (status, response) = server.request("POST", "/rpc", &request.to_string());
+    assert_eq!(status, 200);
+    assert_eq!(response["result"]["block_id"], "A");
+    // blocks ingested through JSON-RPC are pushed to subscribers too.
+    assert_eq!(
+        next_push(&mut socket),
+        Push::TipChanged {
+            old_tip: None,
+            new_tip: "A".to_string(),
+            height: 0,
+        }
+    );
+
+    let request = json!([
+        {"jsonrpc": "2.0", "method": "explorer_getBalance", "params": {"account": "Alice"}, "id": 2},
+        {"jsonrpc": "2.0", "method": "explorer_getBlock", "params": {"block_id": "Z"}, "id": 3},
+    ]);
+    let (status, response) = server.request("POST", "/rpc", &request.to_string());
+    assert_eq!(status, 200);
+    assert_eq!(response[0]["result"]["balance"], 5);
+    assert_eq!(response[1]["error"]["code"], -32001);
+    // the query string does not change the route.
+    let (status, response) = server.request("POST", "/rpc?x=1", &request.to_string());
+    assert_eq!(status, 200);
+    assert_eq!(response[0]["result"]["balance"], 5);
+    assert_eq!(server.get("/rpc").0, 405);
+}
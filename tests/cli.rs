@@ -0,0 +1,261 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use blockchain_explorer::store::{LmdbStorage, Storage, Table, WriteBatch};
+use blockchain_explorer::{blocks, Block, DB_NAME};
+use serde_json::{json, Value};
+use tempdir::TempDir;
+
+/// Run `blockchain-explorer <args> --data-dir <dir>`.
+fn explorer(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_blockchain-explorer"))
+        .args(args)
+        .arg("--data-dir")
+        .arg(dir)
+        .output()
+        .unwrap()
+}
+
+/// Run a query which must succeed, returning its JSON output.
+fn query(dir: &Path, args: &[&str]) -> Value {
+    let output = explorer(dir, args);
+    assert!(output.status.success(), "{:?} failed: {:?}", args, output);
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+fn lines(output: &Output) -> Vec<Value> {
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn write_blocks(path: &Path, blocks: &[&Block]) {
+    fs::write(path, serde_json::to_string(blocks).unwrap()).unwrap();
+}
+
+#[test]
+fn state_survives_between_invocations() {
+    let dir = TempDir::new("cli").unwrap();
+    let data = dir.path().join("data");
+    let file = dir.path().join("blocks.json");
+    write_blocks(&file, &[&blocks::BLOCK_A, &blocks::BLOCK_B]);
+    let output = explorer(&data, &["ingest", file.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(lines(&output).len(), 2);
+    write_blocks(&file, &[&blocks::BLOCK_C, &blocks::BLOCK_D]);
+    assert!(explorer(&data, &["ingest", file.to_str().unwrap()])
+        .status
+        .success());
+
+    assert_eq!(
+        query(&data, &["balance", "Alice"]),
+        json!({"account": "Alice", "block_id": "D", "balance": 6})
+    );
+    assert_eq!(
+        query(&data, &["balance", "Bob", "--fork", "B"])["balance"],
+        0
+    );
+    assert_eq!(
+        query(&data, &["balance", "Bob", "--height", "1"])["balance"],
+        2
+    );
+    assert_eq!(query(&data, &["block", "C"])["children"], json!(["D"]));
+    assert_eq!(
+        query(&data, &["tx", "B0"])["inclusions"][0]["block_id"],
+        "B"
+    );
+    assert_eq!(query(&data, &["tips"])["canonical_tip"], "D");
+    let history = query(&data, &["history", "Bob", "--limit", "1"]);
+    assert_eq!(history["entries"][0]["tx_id"], "D0");
+    let cursor = history["next_cursor"].as_str().unwrap();
+    let history = query(&data, &["history", "Bob", "--cursor", cursor]);
+    assert_eq!(history["entries"][0]["tx_id"], "C0");
+
+    // options may come before the command.
+    let output = Command::new(env!("CARGO_BIN_EXE_blockchain-explorer"))
+        .arg("--data-dir")
+        .arg(&data)
+        .args(["--fork", "B", "balance", "Bob"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let balance: Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(balance["block_id"], "B");
+}
+
+#[test]
+fn export_and_import_rebuild_the_same_tree() {
+    let dir = TempDir::new("cli").unwrap();
+    let data = dir.path().join("data");
+    let file = dir.path().join("blocks.json");
+    // B and C tie at height 1 and E waits for its missing parent.
+    let mut orphan = blocks::BLOCK_E.clone();
+    orphan.parent_id = Some("X".to_string());
+    write_blocks(
+        &file,
+        &[
+            &blocks::BLOCK_A,
+            &blocks::BLOCK_C,
+            &blocks::BLOCK_B,
+            &orphan,
+        ],
+    );
+    assert!(explorer(&data, &["ingest", file.to_str().unwrap()])
+        .status
+        .success());
+
+    let export = dir.path().join("export.ndjson");
+    assert!(
+        explorer(&data, &["export", "--output", export.to_str().unwrap()])
+            .status
+            .success()
+    );
+    let exported: Vec<Value> = fs::read_to_string(&export)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let ids: Vec<&Value> = exported.iter().map(|block| &block["block_id"]).collect();
+    assert_eq!(ids, ["A", "C", "B", "E"]);
+
+    let copy = dir.path().join("copy");
+    let output = explorer(&copy, &["import", export.to_str().unwrap()]);
+    assert_eq!(
+        lines(&output),
+        [json!({"imported": 3, "pending": 1, "known": 0, "failed": 0})]
+    );
+    let output = explorer(&copy, &["import", export.to_str().unwrap()]);
+    assert_eq!(
+        lines(&output),
+        [json!({"imported": 0, "pending": 0, "known": 4, "failed": 0})]
+    );
+    let stats = query(&copy, &["stats"]);
+    assert_eq!(stats, query(&data, &["stats"]));
+    assert_eq!(
+        stats,
+        json!({
+            "blocks": 3,
+            "forks": 2,
+            "canonical_tip": "B",
+            "height": 1,
+            "canonical_transactions": 3,
+            "pending": 1,
+            "accounts": 2,
+        })
+    );
+}
+
+#[test]
+fn failures_set_the_exit_code() {
+    let dir = TempDir::new("cli").unwrap();
+    let data = dir.path().join("data");
+    let file = dir.path().join("blocks.json");
+    write_blocks(&file, &[&blocks::BLOCK_A, &blocks::BLOCK_A]);
+    let output = explorer(&data, &["ingest", file.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let results = lines(&output);
+    assert_eq!(results[0]["status"], json!({"Connected": {"height": 0}}));
+    assert_eq!(results[1]["ingest_error"], json!({"DuplicateBlock": "A"}));
+
+    let output = explorer(&data, &["block", "Z"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim(),
+        "error: unknown block Z"
+    );
+    assert_eq!(explorer(&data, &["balance"]).status.code(), Some(2));
+    assert_eq!(
+        explorer(&data, &["history", "Bob", "--direction", "up"])
+            .status
+            .code(),
+        Some(2)
+    );
+    assert_eq!(
+        explorer(&data, &["tips", "--limit", "1"]).status.code(),
+        Some(2)
+    );
+}
+
+#[test]
+fn corrupted_data_is_only_recovered_on_request() {
+    let dir = TempDir::new("cli").unwrap();
+    let data = dir.path().join("data");
+    let file = dir.path().join("blocks.json");
+    write_blocks(&file, &[&blocks::BLOCK_A, &blocks::BLOCK_C]);
+    assert!(explorer(&data, &["ingest", file.to_str().unwrap()])
+        .status
+        .success());
+    let mut store = LmdbStorage::open(&data, DB_NAME).unwrap();
+    let mut batch = WriteBatch::default();
+    batch.put(Table::Headers, "C", &42).unwrap();
+    store.commit(batch).unwrap();
+    drop(store);
+
+    let output = explorer(&data, &["balance", "Alice"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--recover"));
+    assert_eq!(
+        query(&data, &["balance", "Alice", "--recover"])["balance"],
+        8
+    );
+    assert_eq!(query(&data, &["balance", "Alice"])["block_id"], "C");
+}
+
+#[test]
+fn the_tree_is_drawn_in_each_format() {
+    let dir = TempDir::new("cli").unwrap();
+    let data = dir.path().join("data");
+    let file = dir.path().join("blocks.json");
+    write_blocks(
+        &file,
+        &[
+            &blocks::BLOCK_A,
+            &blocks::BLOCK_B,
+            &blocks::BLOCK_C,
+            &blocks::BLOCK_D,
+        ],
+    );
+    assert!(explorer(&data, &["ingest", file.to_str().unwrap()])
+        .status
+        .success());
+
+    let malformed = Block {
+        block_id: "F".to_string(),
+        parent_id: Some("F".to_string()),
+        transactions: Vec::new(),
+        producer: None,
+    };
+    write_blocks(&file, &[&malformed]);
+    assert_eq!(
+        explorer(&data, &["ingest", file.to_str().unwrap()])
+            .status
+            .code(),
+        Some(1)
+    );
+
+    let output = explorer(&data, &["tree"]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "A *\n├─ B\n└─ C *\n   D *\nF rejected: block F is malformed: block is its own parent\n"
+    );
+    let output = explorer(&data, &["tree", "--format", "dot", "--depth", "0"]);
+    let dot = String::from_utf8_lossy(&output.stdout);
+    assert!(dot.starts_with("digraph blocks {"), "{}", dot);
+    assert!(!dot.contains("->"), "{}", dot);
+    let tree = query(&data, &["tree", "--depth", "1", "--format", "json"]);
+    let ids: Vec<&Value> = tree["nodes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|node| &node["block_id"])
+        .collect();
+    assert_eq!(ids, ["B", "C", "D", "F"]);
+    assert_eq!(tree["nodes"][3]["status"], "rejected");
+    assert_eq!(tree["canonical_tip"], "D");
+    assert_eq!(
+        explorer(&data, &["tree", "--format", "svg"]).status.code(),
+        Some(2)
+    );
+}
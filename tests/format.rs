@@ -0,0 +1,120 @@
+use std::fs::File;
+use std::process::{Command, Stdio};
+
+use serde_json::{json, Value};
+
+/// Play a file of `scenarios/` out with `blockchain-explorer --format <format>`.
+fn play_out(scenario: &str, format: &str) -> String {
+    play_with(scenario, &["--format", format])
+}
+
+/// Play a file of `scenarios/` out with `blockchain-explorer <args>`.
+fn play_with(scenario: &str, args: &[&str]) -> String {
+    let path = format!("{}/scenarios/{}", env!("CARGO_MANIFEST_DIR"), scenario);
+    let output = Command::new(env!("CARGO_BIN_EXE_blockchain-explorer"))
+        .args(args)
+        .stdin(File::open(path).unwrap())
+        .stderr(Stdio::inherit())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn json_events_carry_heights_tips_and_error_kinds() {
+    let events: Vec<Value> = play_out("scenario3.json", "ndjson")
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(
+        events,
+        [
+            json!({"event": "block_ingestion", "block_id": "A", "height": 0, "missing_parent": null, "canonical_tip": "A"}),
+            json!({"event": "block_ingestion", "block_id": "F", "height": 1, "missing_parent": null, "canonical_tip": "F"}),
+            json!({"event": "transaction_rejected", "block_id": "F", "tx_id": "F0", "error_kind": "InsufficientBalance", "error": "transaction F0 spends 6 from Bob whose balance is 5"}),
+            json!({"event": "transaction_rejected", "block_id": "F", "tx_id": "F1", "error_kind": "DuplicateTransaction", "error": "transaction F1 was already included"}),
+            json!({"event": "ingestion_error", "block_id": "A", "error_kind": "DuplicateBlock", "error": "block A was already ingested"}),
+            json!({"event": "query_result", "account": "Alice", "balance": 6, "block_id": "F", "height": 1}),
+            json!({"event": "query_result", "account": "Bob", "balance": 4, "block_id": "F", "height": 1}),
+        ]
+    );
+
+    let array: Value = serde_json::from_str(&play_out("scenario3.json", "json")).unwrap();
+    assert_eq!(array, Value::Array(events));
+}
+
+#[test]
+fn pending_blocks_are_reported_and_reattached() {
+    let events: Value = serde_json::from_str(&play_out("scenario2.json", "json")).unwrap();
+    assert_eq!(
+        events[2],
+        json!({"event": "block_ingestion", "block_id": "D", "height": null, "missing_parent": "C", "canonical_tip": "B"})
+    );
+    assert_eq!(events[4]["block_id"], "D");
+    assert_eq!(events[4]["height"], 2);
+}
+
+#[test]
+fn csv_and_table_rows() {
+    let csv = play_out("scenario3.json", "csv");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "event,block_id,height,canonical_tip,missing_parent,account,tx_id,counterparty,delta,balance,next_cursor,error_kind,error,asset"
+    );
+    assert_eq!(lines[1], "block_ingestion,A,0,A,,,,,,,,,,");
+    assert_eq!(
+        lines[5],
+        "ingestion_error,A,,,,,,,,,,DuplicateBlock,block A was already ingested,"
+    );
+    assert_eq!(lines[7], "query_result,F,1,,,Bob,,,,4,,,,");
+
+    let table = play_out("scenario3.json", "table");
+    let lines: Vec<&str> = table.lines().collect();
+    // columns empty in every row are left out.
+    assert!(lines[0].starts_with("event                 block_id  height  canonical_tip  account"));
+    assert!(!lines[0].contains("missing_parent"));
+    assert!(lines[1].starts_with("--------------------  --------"));
+    assert_eq!(lines[2], "block_ingestion       A         0       A");
+    assert_eq!(lines.len(), 9);
+}
+
+#[test]
+fn history_pages_take_one_row_per_entry() {
+    let path = format!("{}/scenarios/scenario1.json", env!("CARGO_MANIFEST_DIR"));
+    let mut scenario: Value =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    scenario["history"] = json!([{"account": "Bob", "limit": 2}]);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_blockchain-explorer"))
+        .args(["--format", "csv"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    serde_json::to_writer(child.stdin.take().unwrap(), &scenario).unwrap();
+    let output = child.wait_with_output().unwrap();
+    let csv = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<&str> = csv
+        .lines()
+        .filter(|line| line.starts_with("history_result"))
+        .collect();
+    assert_eq!(rows.len(), 2);
+    assert!(rows[0].starts_with("history_result,D,2,,,Bob,D0,Alice,2,4,,"));
+    // the last row of a page carries the cursor of the next one.
+    assert!(!rows[1].split(',').nth(10).unwrap().is_empty());
+}
+
+#[test]
+fn the_format_is_an_option_of_the_play_command() {
+    let table = play_out("scenario1.json", "table");
+    assert_eq!(
+        play_with("scenario1.json", &["play", "--format", "table"]),
+        table
+    );
+    assert_eq!(
+        play_with("scenario1.json", &["--format", "table", "play"]),
+        table
+    );
+    assert_ne!(play_with("scenario1.json", &["play"]), table);
+}
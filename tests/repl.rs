@@ -0,0 +1,124 @@
+use blockchain_explorer::api::open_data_dir;
+use blockchain_explorer::repl::Repl;
+use blockchain_explorer::{blocks, MemoryService, Service, ServiceConfig, NATIVE_ASSET};
+use tempdir::TempDir;
+
+#[test]
+fn forks_can_be_explored_inline() {
+    let mut service = MemoryService::new().unwrap();
+    let mut repl = Repl::new(&mut service);
+    let mut run = |line: &str| repl.execute(line).unwrap();
+
+    assert_eq!(
+        run("ingest A - mint:Alice:10 transfer:Alice:Bob:5"),
+        "A connected at height 0\ncanonical tip A"
+    );
+    run("ingest B A transfer:Bob:Alice:5");
+    assert_eq!(
+        run("ingest D C transfer:Alice:Bob:2"),
+        "D pending, waiting for C\ncanonical tip B"
+    );
+    assert_eq!(
+        run("ingest C A transfer:Bob:Alice:3"),
+        "C connected at height 1\nD connected at height 2\ncanonical tip D"
+    );
+    assert_eq!(run("tree"), "A *\n├─ B\n└─ C *\n   D *");
+    assert_eq!(run("balance Alice"), "Alice: 6 after D");
+    assert_eq!(run("balance Bob B"), "Bob: 0 after B");
+    assert_eq!(
+        run(
+            r#"ingest {"block_id": "G", "parent_id": "B", "transactions": [{"Mint": {"tx_id": "G0", "to": "Bob", "amount": 3, "asset": "GOLD"}}, {"Transfer": {"tx_id": "G1", "from": "Alice", "to": "Bob", "amount": 1, "nonce": 4}}]}"#
+        ),
+        "G connected at height 2\ncanonical tip G"
+    );
+    assert_eq!(
+        run("diff G"),
+        "G changed 2 account(s)\n  Alice: 10 -> 9 (-1)\n  Bob: 0 -> 1 (+1)\n  Bob GOLD: 0 -> 3 (+3)\n  \
+         Alice nonce: none -> 4"
+    );
+    assert_eq!(run("balance Bob:GOLD"), "Bob: 3 GOLD after G");
+    assert_eq!(run("balance Bob:GOLD D"), "Bob: 0 GOLD after D");
+    assert_eq!(run("balance Bob:native G"), "Bob: 1 after G");
+    assert_eq!(run("undo"), "forgot G, canonical tip D");
+
+    assert_eq!(
+        run("goto B"),
+        "B at height 1, parent A, off the canonical chain\n  B0: transfer 5 from Bob to Alice"
+    );
+    assert_eq!(run("balance Alice"), "Alice: 10 after B");
+    assert_eq!(
+        run("diff"),
+        "B changed 2 account(s)\n  Alice: 5 -> 10 (+5)\n  Bob: 5 -> 0 (-5)"
+    );
+    assert_eq!(run("tree"), "A *\n├─ B <- focus\n└─ C *\n   D *");
+    assert!(run("goto").starts_with("D at height 2, parent C, canonical"));
+    assert_eq!(run("tips"), "B at height 1\nD at height 2 *");
+}
+
+#[test]
+fn undo_forgets_the_last_ingest() {
+    let dir = TempDir::new("repl").unwrap();
+    let (mut service, _) = open_data_dir(dir.path(), ServiceConfig::default(), false).unwrap();
+    {
+        let mut repl = Repl::new(&mut service);
+        for block in [&*blocks::BLOCK_A, &*blocks::BLOCK_B, &*blocks::BLOCK_C] {
+            let line = format!("ingest {}", serde_json::to_string(block).unwrap());
+            repl.execute(&line).unwrap();
+        }
+        repl.execute("goto C").unwrap();
+        assert_eq!(repl.execute("undo").unwrap(), "forgot C, canonical tip B");
+        // the focus moves back to the canonical tip with its block gone.
+        assert_eq!(repl.execute("balance Alice").unwrap(), "Alice: 10 after B");
+        assert_eq!(repl.execute("undo").unwrap(), "forgot B, canonical tip A");
+        assert_eq!(
+            repl.execute("undo").unwrap(),
+            "forgot A, canonical tip none"
+        );
+        let error = repl.execute("undo").unwrap_err();
+        assert_eq!(error.to_string(), "nothing ingested in this session");
+        repl.execute("ingest A - mint:Carol:1").unwrap();
+    }
+    drop(service);
+
+    // the undone blocks are gone from the storage too.
+    let (service, _) = open_data_dir(dir.path(), ServiceConfig::default(), false).unwrap();
+    assert_eq!(service.tree.len(), 1);
+    assert_eq!(service.get_balance("Carol", NATIVE_ASSET).unwrap(), 1);
+    assert_eq!(service.get_balance("Alice", NATIVE_ASSET).unwrap(), 0);
+}
+
+#[test]
+fn mistakes_are_reported() {
+    let mut service = MemoryService::new().unwrap();
+    let mut repl = Repl::new(&mut service);
+    let mut error = |line: &str| repl.execute(line).unwrap_err().to_string();
+
+    assert_eq!(error("mine"), "unknown command mine, see help");
+    assert_eq!(error("balance Alice"), "no block ingested yet");
+    assert_eq!(error("tree A"), "usage: tree [depth]");
+    assert_eq!(
+        error("ingest A - mint:Alice"),
+        "invalid transaction mint:Alice, expected mint:TO:AMOUNT, transfer:FROM:TO:AMOUNT[:FEE] \
+         or burn:FROM:AMOUNT"
+    );
+    assert_eq!(
+        error("ingest A - mint:Alice:ten"),
+        "invalid amount ten in mint:Alice:ten"
+    );
+    assert_eq!(error("goto A"), "unknown block A");
+    assert_eq!(error("ingest {\"block_id\": 1}"), "malformed block");
+
+    // rejected blocks are kept for the tree only.
+    repl.execute("ingest A - mint:Alice:5").unwrap();
+    let error = repl.execute("ingest B B").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "block B is malformed: block is its own parent"
+    );
+    assert_eq!(
+        repl.execute("tree").unwrap(),
+        "A *\nB rejected: block B is malformed: block is its own parent"
+    );
+    repl.execute("ingest B A mint:Bob:1").unwrap();
+    assert_eq!(repl.execute("tree 0").unwrap(), "… B *");
+}
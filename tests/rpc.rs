@@ -0,0 +1,172 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use blockchain_explorer::blocks;
+use blockchain_explorer::rpc::{
+    DUPLICATE_BLOCK, INVALID_PARAMS, INVALID_REQUEST, METHOD_NOT_FOUND, NOT_FOUND, PARSE_ERROR,
+};
+use serde_json::{json, Value};
+
+/// The explorer binary serving JSON-RPC over its stdio.
+struct Explorer {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Explorer {
+    fn spawn() -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_blockchain-explorer"))
+            .arg("rpc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Self {
+            child,
+            stdin,
+            stdout,
+        }
+    }
+
+    fn send(&mut self, line: &str) -> Value {
+        writeln!(self.stdin, "{}", line).unwrap();
+        self.stdin.flush().unwrap();
+        let mut response = String::new();
+        self.stdout.read_line(&mut response).unwrap();
+        serde_json::from_str(&response).unwrap()
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Value {
+        let request = json!({"jsonrpc": "2.0", "method": method, "params": params, "id": 1});
+        self.send(&request.to_string())
+    }
+}
+
+impl Drop for Explorer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn methods_mirror_the_service() {
+    let mut explorer = Explorer::spawn();
+    for block in [
+        &*blocks::BLOCK_A,
+        &*blocks::BLOCK_B,
+        &*blocks::BLOCK_C,
+        &*blocks::BLOCK_D,
+    ] {
+        let response = explorer.call("explorer_ingestBlock", json!({ "block": block }));
+        assert_eq!(response["result"]["block_id"], block.block_id.as_str());
+    }
+
+    let response = explorer.call("explorer_getBalance", json!({"account": "Alice"}));
+    assert_eq!(
+        response,
+        json!({
+            "jsonrpc": "2.0",
+            "result": {"account": "Alice", "block_id": "D", "balance": 6},
+            "id": 1
+        })
+    );
+    let response = explorer.call(
+        "explorer_getBalance",
+        json!({"account": "Bob", "fork": "B"}),
+    );
+    assert_eq!(response["result"]["balance"], 0);
+    let response = explorer.call(
+        "explorer_getBalance",
+        json!({"account": "Bob", "height": 1}),
+    );
+    assert_eq!(response["result"]["balance"], 2);
+
+    let response = explorer.call("explorer_getBlock", json!({"block_id": "D"}));
+    assert_eq!(response["result"]["height"], 2);
+    assert_eq!(response["result"]["canonical"], true);
+    let response = explorer.call("explorer_getTransaction", json!({"tx_id": "D0"}));
+    assert_eq!(response["result"]["inclusions"][0]["confirmations"], 1);
+    let response = explorer.call(
+        "explorer_getAccountHistory",
+        json!({"account": "Bob", "limit": 1}),
+    );
+    assert_eq!(response["result"]["entries"][0]["tx_id"], "D0");
+    let response = explorer.call("explorer_getTips", json!({}));
+    assert_eq!(response["result"]["canonical_tip"], "D");
+}
+
+#[test]
+fn batches_and_notifications() {
+    let mut explorer = Explorer::spawn();
+    let batch = json!([
+        {"jsonrpc": "2.0", "method": "explorer_ingestBlock", "params": {"block": &*blocks::BLOCK_A}},
+        {"jsonrpc": "2.0", "method": "explorer_getBalance", "params": {"account": "Bob"}, "id": "bob"},
+        {"jsonrpc": "2.0", "method": "explorer_getPendingBlocks", "id": 7},
+    ]);
+    let response = explorer.send(&batch.to_string());
+    // the notification ingesting A gets no response but runs before the queries.
+    assert_eq!(
+        response,
+        json!([
+            {"jsonrpc": "2.0", "result": {"account": "Bob", "block_id": "A", "balance": 5}, "id": "bob"},
+            {"jsonrpc": "2.0", "result": [], "id": 7},
+        ])
+    );
+
+    // a batch of notifications has no response line at all.
+    let notifications = json!([
+        {"jsonrpc": "2.0", "method": "explorer_ingestBlock", "params": {"block": &*blocks::BLOCK_C}},
+    ]);
+    writeln!(explorer.stdin, "{}", notifications).unwrap();
+    let response = explorer.call("explorer_getBalance", json!({"account": "Bob"}));
+    assert_eq!(response["result"]["balance"], 2);
+}
+
+#[test]
+fn errors_use_standard_codes() {
+    let mut explorer = Explorer::spawn();
+    let code = |response: Value| response["error"]["code"].as_i64().unwrap();
+
+    assert_eq!(code(explorer.send("{not json")), PARSE_ERROR);
+    assert_eq!(code(explorer.send("[]")), INVALID_REQUEST);
+    assert_eq!(
+        code(explorer.send(r#"{"jsonrpc": "1.0", "method": "explorer_getTips", "id": 1}"#)),
+        INVALID_REQUEST
+    );
+    assert_eq!(
+        code(explorer.call("explorer_mine", json!({}))),
+        METHOD_NOT_FOUND
+    );
+    let response = explorer.call("explorer_getBalance", json!(["Alice"]));
+    assert_eq!(response["error"]["code"], INVALID_PARAMS);
+    assert_eq!(
+        response["error"]["message"],
+        "positional parameters are not supported, name them in an object"
+    );
+    // without parameters, an empty array is as good as none.
+    assert!(explorer.call("explorer_getTips", json!([]))["result"]["tips"].is_array());
+    assert_eq!(
+        code(explorer.call("explorer_getBlock", json!({"block": "A"}))),
+        INVALID_PARAMS
+    );
+    assert_eq!(
+        code(explorer.call("explorer_getBlock", json!({"block_id": "A"}))),
+        NOT_FOUND
+    );
+
+    explorer.call(
+        "explorer_ingestBlock",
+        json!({ "block": &*blocks::BLOCK_A }),
+    );
+    let response = explorer.call(
+        "explorer_ingestBlock",
+        json!({ "block": &*blocks::BLOCK_A }),
+    );
+    assert_eq!(response["error"]["code"], DUPLICATE_BLOCK);
+    assert_eq!(response["error"]["data"], json!({"DuplicateBlock": "A"}));
+    assert_eq!(response["id"], 1);
+}
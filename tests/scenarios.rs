@@ -0,0 +1,110 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use blockchain_explorer::scenario::{verify_scenario, Mismatch, Scenario};
+
+fn scenario_files() -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+fn load(path: &Path) -> Scenario {
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn every_scenario_passes() {
+    let files = scenario_files();
+    assert!(!files.is_empty());
+    let mut report = String::new();
+    for file in &files {
+        match verify_scenario(&load(file)) {
+            Ok(mismatches) => {
+                for mismatch in mismatches {
+                    report += &format!("{}: {}\n", file.display(), mismatch);
+                }
+            }
+            Err(error) => report += &format!("{}: {:#}\n", file.display(), error),
+        }
+    }
+    assert!(report.is_empty(), "scenarios failed:\n{}", report);
+}
+
+#[test]
+fn wrong_expectations_are_reported() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/scenario1.json");
+    let mut scenario = load(&path);
+    let expect = scenario.blocks[2].expect.as_mut().unwrap();
+    expect.balances.insert("Alice".to_string(), 7);
+    expect.error = Some("UnknownParent".to_string());
+    scenario.expect.as_mut().unwrap().canonical_tip = Some("B".to_string());
+
+    let mismatches = verify_scenario(&scenario).unwrap();
+    assert_eq!(
+        mismatches,
+        vec![
+            Mismatch {
+                step: "after block C".to_string(),
+                check: "error".to_string(),
+                expected: "UnknownParent".to_string(),
+                actual: "no error".to_string(),
+            },
+            Mismatch {
+                step: "after block C".to_string(),
+                check: "balance of Alice".to_string(),
+                expected: "7".to_string(),
+                actual: "8".to_string(),
+            },
+            Mismatch {
+                step: "at the end".to_string(),
+                check: "canonical tip".to_string(),
+                expected: "B".to_string(),
+                actual: "D".to_string(),
+            },
+        ]
+    );
+    assert_eq!(
+        mismatches[1].to_string(),
+        "after block C: balance of Alice: expected 7, got 8"
+    );
+}
+
+#[test]
+fn verify_subcommand_exits_with_failure() {
+    let dir = std::env::temp_dir().join(format!("explorer-verify-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/scenario1.json");
+    let mut scenario = load(&path);
+    scenario
+        .expect
+        .as_mut()
+        .unwrap()
+        .balances
+        .insert("Bob".to_string(), 5);
+    let failing = dir.join("failing.json");
+    fs::write(&failing, serde_json::to_string(&scenario).unwrap()).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_blockchain-explorer"))
+        .arg("verify")
+        .arg(&path)
+        .arg(&failing)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], format!("PASS {}", path.display()));
+    assert_eq!(lines[1], format!("FAIL {}", failing.display()));
+    assert_eq!(lines[2], "  at the end: balance of Bob: expected 5, got 4");
+}
@@ -0,0 +1,111 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use blockchain_explorer::blocks;
+use serde_json::{json, Value};
+
+/// Pipe commands through `blockchain-explorer stream`, returning its result lines.
+fn stream(commands: &[Value]) -> Vec<Value> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_blockchain-explorer"))
+        .arg("stream")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    for command in commands {
+        writeln!(stdin, "{}", command).unwrap();
+    }
+    drop(stdin);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn ingest(block: &blockchain_explorer::Block) -> Value {
+    json!({"command": "ingest", "block": block})
+}
+
+fn balances() -> [Value; 2] {
+    [
+        json!({"command": "balance", "account": "Alice"}),
+        json!({"command": "balance", "account": "Bob"}),
+    ]
+}
+
+#[test]
+fn queries_run_between_ingests() {
+    // test_5: the longest chain changes mid stream.
+    let mut commands = balances().to_vec();
+    for block in [
+        &*blocks::BLOCK_A,
+        &*blocks::BLOCK_B,
+        &*blocks::BLOCK_C,
+        &*blocks::BLOCK_D,
+    ] {
+        commands.push(ingest(block));
+        commands.extend(balances());
+    }
+    let results = stream(&commands);
+    assert_eq!(results.len(), commands.len());
+
+    let balances: Vec<(u64, u64)> = results
+        .iter()
+        .filter(|result| result["command"] == "balance")
+        .map(|result| result["result"]["balance"].as_u64().unwrap())
+        .collect::<Vec<_>>()
+        .chunks(2)
+        .map(|pair| (pair[0], pair[1]))
+        .collect();
+    assert_eq!(balances, [(0, 0), (5, 5), (10, 0), (8, 2), (6, 4)]);
+    assert_eq!(results[2]["line"], 3);
+    assert_eq!(
+        results[2]["result"]["status"],
+        json!({"Connected": {"height": 0}})
+    );
+}
+
+#[test]
+fn blocks_snapshots_and_errors() {
+    let results = stream(&[
+        ingest(&blocks::BLOCK_A),
+        ingest(&blocks::BLOCK_D),
+        json!({"command": "block", "block_id": "A"}),
+        json!({"command": "snapshot"}),
+        ingest(&blocks::BLOCK_A),
+        json!({"command": "block", "block_id": "D"}),
+        json!({"command": "balance", "account": "Alice", "height": 1}),
+        json!({"command": "mine"}),
+        json!({"command": "snapshot", "accounts": ["Carol"]}),
+    ]);
+
+    assert_eq!(results[2]["result"]["height"], 0);
+    assert_eq!(
+        results[3],
+        json!({
+            "line": 4,
+            "command": "snapshot",
+            "result": {
+                "canonical_tip": "A",
+                "height": 0,
+                "tips": ["A"],
+                "pending": ["D"],
+                "balances": {"Alice": 5, "Bob": 5}
+            }
+        })
+    );
+    assert_eq!(results[4]["command"], "ingest");
+    assert_eq!(results[4]["ingest_error"], json!({"DuplicateBlock": "A"}));
+    assert_eq!(results[5]["error"], "unknown block D");
+    assert_eq!(results[6]["error"], "no canonical block at height 1");
+    assert_eq!(results[7].get("command"), None);
+    assert!(results[7]["error"]
+        .as_str()
+        .unwrap()
+        .starts_with("malformed command"));
+    assert_eq!(results[8]["result"]["balances"], json!({"Carol": 0}));
+}